@@ -1,8 +1,10 @@
 // Make first function small enough so that compiler doesn't try
 // to crate a huge stack frame before we have a chance to set SP.
+//
+// The firmware passes the DTB address in x0, keep it for the loaded kernel.
 #[no_mangle]
 #[link_section = ".text.chainboot.entry"]
-pub unsafe extern "C" fn _start() -> ! {
+pub unsafe extern "C" fn _start(dtb_phys_addr: u64) -> ! {
     use {
         aarch64_cpu::registers::{MPIDR_EL1, SP},
         core::cell::UnsafeCell,
@@ -25,15 +27,20 @@ pub unsafe extern "C" fn _start() -> ! {
     // Set stack pointer.
     SP.set(__boot_core_stack_end_exclusive.get() as u64);
 
-    reset();
+    reset(dtb_phys_addr);
 }
 
 #[no_mangle]
 #[link_section = ".text.chainboot"]
-pub unsafe extern "C" fn reset() -> ! {
-    use core::{
-        cell::UnsafeCell,
-        sync::{atomic, atomic::Ordering},
+pub unsafe extern "C" fn reset(dtb_phys_addr: u64) -> ! {
+    use {
+        aarch64_cpu::registers::{CurrentEL, MPIDR_EL1},
+        core::{
+            cell::UnsafeCell,
+            sync::{atomic, atomic::Ordering},
+        },
+        machine::cpu::BootInfo,
+        tock_registers::interfaces::Readable,
     };
 
     // These are a problem, because they are not interpreted as constants here.
@@ -87,9 +94,19 @@ pub unsafe extern "C" fn reset() -> ! {
     // Additionally, we assume that no statics are accessed before this point.
     atomic::compiler_fence(Ordering::SeqCst);
 
+    machine::cpu::per_cpu::use_template();
+    machine::cpu::stack::set_kernel_stack(0..__boot_core_stack_end_exclusive.get() as usize);
+
+    let boot_info = BootInfo::new(
+        dtb_phys_addr,
+        MPIDR_EL1.get() & 0x3,
+        CurrentEL.read(CurrentEL::EL) as u8,
+        __binary_nonzero_lma.get() as u64,
+    );
+
     let max_kernel_size =
         __binary_nonzero_vma.get() as u64 - __boot_core_stack_end_exclusive.get() as u64;
-    crate::kernel_init(max_kernel_size)
+    crate::kernel_init(max_kernel_size, boot_info)
 }
 
 #[inline(always)]
@@ -10,7 +10,7 @@
 use {
     aarch64_cpu::asm::barrier,
     core::hash::Hasher,
-    machine::{console::console, platform::raspberrypi::BcmHost, print, println},
+    machine::{console::console, cpu::BootInfo, platform::raspberrypi::BcmHost, print, println},
     seahash::SeaHasher,
 };
 
@@ -22,10 +22,12 @@ mod boot;
 ///
 /// - Only a single core must be active and running this function.
 /// - The init calls in this function must appear in the correct order.
-unsafe fn kernel_init(max_kernel_size: u64) -> ! {
+unsafe fn kernel_init(max_kernel_size: u64, boot_info: BootInfo) -> ! {
     #[cfg(feature = "jtag")]
     machine::debug::jtag::wait_debugger();
 
+    machine::platform::init_from_device_tree(boot_info.dtb_phys_addr());
+
     if let Err(x) = machine::platform::drivers::init() {
         panic!("Error initializing platform drivers: {}", x);
     }
@@ -36,7 +38,7 @@ unsafe fn kernel_init(max_kernel_size: u64) -> ! {
     // println! is usable from here on.
 
     // Transition from unsafe to safe.
-    kernel_main(max_kernel_size)
+    kernel_main(max_kernel_size, boot_info)
 }
 
 // https://onlineasciitools.com/convert-text-to-ascii-art (FIGlet) with `cricket` font
@@ -61,7 +63,7 @@ fn read_u64() -> u64 {
 
 /// The main function running after the early init.
 #[inline(always)]
-fn kernel_main(max_kernel_size: u64) -> ! {
+fn kernel_main(max_kernel_size: u64, boot_info: BootInfo) -> ! {
     #[cfg(test)]
     test_main();
 
@@ -129,13 +131,18 @@ fn kernel_main(max_kernel_size: u64) -> ! {
     console().flush();
 
     // Use black magic to create a function pointer.
-    let kernel: fn() -> ! = unsafe { core::mem::transmute(kernel_addr) };
+    // The loaded kernel expects the DTB address in x0, same as from the firmware.
+    let kernel: extern "C" fn(u64) -> ! = unsafe { core::mem::transmute(kernel_addr) };
 
     // Force everything to complete before we jump.
     barrier::isb(barrier::SY);
 
     // Jump to loaded kernel!
-    kernel()
+    kernel(
+        boot_info
+            .dtb_phys_addr()
+            .map_or(0, |addr| addr.as_usize() as u64),
+    )
 }
 
 #[cfg(not(test))]
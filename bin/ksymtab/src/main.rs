@@ -0,0 +1,109 @@
+/*
+ * SPDX-License-Identifier: BlueOak-1.0.0
+ * Copyright (c) Berkus Decker <berkus+vesper@metta.systems>
+ */
+
+//! Fill in the `.ksyms` section of a linked image with its function symbols.
+//!
+//! The image is patched in place, before it is converted to a binary. The table layout
+//! must match `machine::symbols`.
+
+use {
+    anyhow::{anyhow, bail, Context, Result},
+    object::{Object, ObjectSection, ObjectSymbol, SymbolKind},
+    std::{env, fs},
+};
+
+/// Identifies a filled in table.
+const MAGIC: &[u8; 8] = b"KSYMTAB1";
+const HEADER_SIZE: usize = 24;
+const ENTRY_SIZE: usize = 24;
+
+struct Symbol {
+    addr: u64,
+    size: u64,
+    name: String,
+}
+
+/// Function symbols sorted by address, one per address.
+fn collect_symbols(elf: &object::File) -> Vec<Symbol> {
+    let mut symbols: Vec<_> = elf
+        .symbols()
+        .filter(|symbol| symbol.kind() == SymbolKind::Text && symbol.address() != 0)
+        .filter_map(|symbol| {
+            let name = symbol.name().ok()?;
+            Some(Symbol {
+                addr: symbol.address(),
+                size: symbol.size(),
+                name: format!("{:#}", rustc_demangle::demangle(name)),
+            })
+        })
+        .collect();
+
+    symbols.sort_by_key(|symbol| symbol.addr);
+    symbols.dedup_by_key(|symbol| symbol.addr);
+    symbols
+}
+
+/// Serialize the table for a section at `link_addr`.
+fn build_table(link_addr: u64, symbols: &[Symbol]) -> Result<Vec<u8>> {
+    let mut entries = Vec::with_capacity(symbols.len() * ENTRY_SIZE);
+    let mut names = Vec::new();
+
+    for symbol in symbols {
+        entries.extend_from_slice(&symbol.addr.to_le_bytes());
+        // Sizes too large for the table are unknown, the symbol then extends to the next.
+        entries.extend_from_slice(&u32::try_from(symbol.size).unwrap_or(0).to_le_bytes());
+        entries.extend_from_slice(&u32::try_from(names.len())?.to_le_bytes());
+        entries.extend_from_slice(&u32::try_from(symbol.name.len())?.to_le_bytes());
+        entries.extend_from_slice(&0u32.to_le_bytes());
+        names.extend_from_slice(symbol.name.as_bytes());
+    }
+
+    let mut table = Vec::with_capacity(HEADER_SIZE + entries.len() + names.len());
+    table.extend_from_slice(MAGIC);
+    table.extend_from_slice(&link_addr.to_le_bytes());
+    table.extend_from_slice(&u32::try_from(symbols.len())?.to_le_bytes());
+    table.extend_from_slice(&u32::try_from(names.len())?.to_le_bytes());
+    table.extend_from_slice(&entries);
+    table.extend_from_slice(&names);
+    Ok(table)
+}
+
+fn main() -> Result<()> {
+    let path = env::args()
+        .nth(1)
+        .ok_or_else(|| anyhow!("Usage: ksymtab <kernel.elf>"))?;
+    let mut image = fs::read(&path).with_context(|| format!("Reading {}", path))?;
+
+    let (offset, table) = {
+        let elf = object::File::parse(&*image).with_context(|| format!("Parsing {}", path))?;
+        let section = elf
+            .section_by_name(".ksyms")
+            .ok_or_else(|| anyhow!("{} has no .ksyms section", path))?;
+        let (offset, capacity) = section
+            .file_range()
+            .ok_or_else(|| anyhow!(".ksyms in {} has no file contents", path))?;
+
+        let symbols = collect_symbols(&elf);
+        let table = build_table(section.address(), &symbols)?;
+        if table.len() as u64 > capacity {
+            bail!(
+                "Symbol table of {} bytes doesn't fit .ksyms of {} bytes, grow it in the linker script",
+                table.len(),
+                capacity
+            );
+        }
+
+        println!(
+            "Embedding {} symbols ({} bytes) into {}",
+            symbols.len(),
+            table.len(),
+            path
+        );
+        (offset as usize, table)
+    };
+
+    image[offset..offset + table.len()].copy_from_slice(&table);
+    fs::write(&path, image).with_context(|| format!("Writing {}", path))
+}
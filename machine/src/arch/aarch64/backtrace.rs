@@ -0,0 +1,97 @@
+/*
+ * SPDX-License-Identifier: BlueOak-1.0.0
+ * Copyright (c) Berkus Decker <berkus+vesper@metta.systems>
+ */
+
+//! Architectural stack unwinding.
+//!
+//! The kernel is built with frame pointers: every function pushes a frame record of the
+//! caller's x29 and its own return address, and points x29 at it. Records form a chain up
+//! the stack, which is followed as long as it stays inside the stack and keeps going up.
+//!
+//! Exception handlers run on their own stack, their chain continues on the stack of the
+//! interrupted code. Records may move on from one stack to the next, but never back.
+
+use {
+    crate::mm,
+    core::{arch::asm, ops::Range},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Iterator over the call sites found in a chain of frame records.
+pub struct FrameRecords {
+    fp: usize,
+    /// Stacks the chain may run through, in order.
+    stacks: [Range<usize>; 2],
+    /// Index of the stack holding the previous record.
+    current: usize,
+    /// Address of the previous record.
+    previous: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl FrameRecords {
+    /// Index of the stack holding a whole record at `fp`, from the current one on.
+    fn stack_of(&self, fp: usize) -> Option<usize> {
+        (self.current..self.stacks.len())
+            .find(|&index| fp >= self.stacks[index].start && fp + 16 <= self.stacks[index].end)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// The executing function's frame pointer.
+#[inline(always)]
+pub fn frame_pointer() -> usize {
+    let fp: usize;
+    unsafe { asm!("mov {}, x29", out(reg) fp, options(nomem, nostack, preserves_flags)) };
+    fp
+}
+
+impl FrameRecords {
+    /// Walk the records starting at `fp`, all of which must lie within `stacks`.
+    pub fn new(fp: usize, stacks: [Range<usize>; 2]) -> Self {
+        Self {
+            fp,
+            stacks,
+            current: 0,
+            previous: 0,
+        }
+    }
+}
+
+impl Iterator for FrameRecords {
+    /// Address of the call instruction.
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        let fp = self.fp;
+        if fp == 0 || !mm::is_aligned(fp, 8) {
+            return None;
+        }
+
+        // Callers' records sit higher up on the same stack, or on a later one.
+        let stack = self.stack_of(fp)?;
+        if stack == self.current && fp <= self.previous {
+            return None;
+        }
+        self.current = stack;
+        self.previous = fp;
+
+        // Record is [x29, x30].
+        let record = unsafe { (fp as *const [usize; 2]).read() };
+        let lr = record[1];
+        self.fp = record[0];
+
+        // Return address is right after the call.
+        lr.checked_sub(4)
+    }
+}
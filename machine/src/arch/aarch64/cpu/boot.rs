@@ -9,10 +9,23 @@
 //! <http://infocenter.arm.com/help/topic/com.arm.doc.dai0527a/DAI0527A_baremetal_boot_code_for_ARMv8_A_processors.pdf>
 
 use {
-    super::endless_sleep,
-    crate::platform::cpu::BOOT_CORE_ID,
-    aarch64_cpu::{asm, registers::*},
+    super::{endless_sleep, relocation},
+    crate::{
+        arch::aarch64::{
+            exception::{self, syndrome::ExceptionClass},
+            memory::mmu as arch_mmu,
+        },
+        cpu::boot::BootInfo,
+        memory::mmu::interface::MMU,
+        platform::{
+            cpu::BOOT_CORE_ID,
+            memory::mmu::{virt_exception_stack_region, KernelGranule, KERNEL_VIRT_OFFSET},
+            BcmHost,
+        },
+    },
+    aarch64_cpu::registers::*,
     core::{
+        arch::asm,
         cell::UnsafeCell,
         slice,
         sync::atomic::{self, Ordering},
@@ -20,6 +33,17 @@ use {
     tock_registers::interfaces::{Readable, Writeable},
 };
 
+/// Where to continue execution once in EL1, and with what.
+#[derive(Copy, Clone)]
+pub(super) struct EL1Entry {
+    /// Address of an `extern "C"` function taking up to four u64 arguments.
+    pub pc: u64,
+    /// Initial SP_EL1.
+    pub sp: u64,
+    /// Values for x0-x3.
+    pub args: [u64; 4],
+}
+
 /// Type check the user-supplied entry function.
 #[macro_export]
 macro_rules! entry {
@@ -28,11 +52,11 @@ macro_rules! entry {
         /// Only type-checks!
         #[export_name = "main"]
         #[inline(always)]
-        pub unsafe fn __main() -> ! {
+        pub unsafe fn __main(boot_info: $crate::cpu::boot::BootInfo) -> ! {
             // type check the given path
-            let f: unsafe fn() -> ! = $path;
+            let f: unsafe fn($crate::cpu::boot::BootInfo) -> ! = $path;
 
-            f()
+            f(boot_info)
         }
     };
 }
@@ -45,6 +69,10 @@ macro_rules! entry {
 /// This is invoked from the linker script, does arch-specific init
 /// and passes control to the kernel boot function reset().
 ///
+/// The firmware (or QEMU with `-dtb`) passes the physical address of the device tree
+/// blob in x0. It is carried through the exception level switch in registers, because
+/// nothing in memory may be touched before the .bss is cleared.
+///
 /// Dissection of various RPi core boot stubs is available
 /// [here](https://leiradel.github.io/2019/01/20/Raspberry-Pi-Stubs.html).
 ///
@@ -54,7 +82,7 @@ macro_rules! entry {
 /// We assume that no statics are accessed before transition to main from reset() function.
 #[no_mangle]
 #[link_section = ".text.main.entry"]
-pub unsafe extern "C" fn _boot_cores() -> ! {
+pub unsafe extern "C" fn _boot_cores(dtb_phys_addr: u64) -> ! {
     // Can't match values with dots in match, so use intermediate consts.
     #[cfg(qemu)]
     const EL3: u64 = CurrentEL::EL::EL3.value;
@@ -71,12 +99,22 @@ pub unsafe extern "C" fn _boot_cores() -> ! {
 
     shared_setup_and_enter_pre();
 
-    if BOOT_CORE_ID == super::smp::core_id() {
+    let core_id = super::smp::core_id();
+    // Where we actually run from, not where we were linked at.
+    let load_addr = _boot_cores as *const () as u64;
+
+    if BOOT_CORE_ID == core_id {
+        let entry_el = CurrentEL.read(CurrentEL::EL);
+        let el1_entry = EL1Entry {
+            pc: reset as *const () as u64,
+            sp: __STACK_TOP.get() as u64,
+            args: [dtb_phys_addr, core_id, entry_el, load_addr],
+        };
         match CurrentEL.get() {
             #[cfg(qemu)]
-            EL3 => setup_and_enter_el1_from_el3(),
-            EL2 => setup_and_enter_el1_from_el2(),
-            EL1 => reset(),
+            EL3 => setup_and_enter_el1_from_el3(el1_entry),
+            EL2 => setup_and_enter_el1_from_el2(el1_entry),
+            EL1 => reset(dtb_phys_addr, core_id, entry_el, load_addr),
             _ => endless_sleep(),
         }
     }
@@ -87,7 +125,7 @@ pub unsafe extern "C" fn _boot_cores() -> ! {
 
 #[link_section = ".text.boot"]
 #[inline(always)]
-fn shared_setup_and_enter_pre() {
+pub(super) fn shared_setup_and_enter_pre() {
     // Enable timer counter registers for EL1
     CNTHCTL_EL2.write(CNTHCTL_EL2::EL1PCEN::SET + CNTHCTL_EL2::EL1PCTEN::SET);
 
@@ -118,20 +156,23 @@ fn shared_setup_and_enter_pre() {
 
 #[link_section = ".text.boot"]
 #[inline]
-fn shared_setup_and_enter_post() -> ! {
-    extern "Rust" {
-        // Stack top
-        static __STACK_TOP: UnsafeCell<()>;
-    }
+fn shared_setup_and_enter_post(el1_entry: EL1Entry) -> ! {
     // Set up SP_EL1 (stack pointer), which will be used by EL1 once
     // we "return" to it.
-    unsafe {
-        SP_EL1.set(__STACK_TOP.get() as u64);
-    }
+    SP_EL1.set(el1_entry.sp);
 
     // Use `eret` to "return" to EL1. This will result in execution of
-    // `reset()` in EL1.
-    asm::eret()
+    // the entry function in EL1, with the boot parameters as its arguments.
+    unsafe {
+        asm!(
+            "eret",
+            in("x0") el1_entry.args[0],
+            in("x1") el1_entry.args[1],
+            in("x2") el1_entry.args[2],
+            in("x3") el1_entry.args[3],
+            options(noreturn)
+        )
+    }
 }
 
 /// Real hardware boot-up sequence.
@@ -139,7 +180,7 @@ fn shared_setup_and_enter_post() -> ! {
 /// Prepare and execute transition from EL2 to EL1.
 #[link_section = ".text.boot"]
 #[inline]
-fn setup_and_enter_el1_from_el2() -> ! {
+pub(super) fn setup_and_enter_el1_from_el2(el1_entry: EL1Entry) -> ! {
     // Set Saved Program Status Register (EL2)
     // Set up a simulated exception return.
     //
@@ -153,10 +194,10 @@ fn setup_and_enter_el1_from_el2() -> ! {
             + SPSR_EL2::M::EL1h, // Use SP_EL1
     );
 
-    // Make the Exception Link Register (EL2) point to reset().
-    ELR_EL2.set(reset as *const () as u64);
+    // Make the Exception Link Register (EL2) point to the EL1 entry, usually reset().
+    ELR_EL2.set(el1_entry.pc);
 
-    shared_setup_and_enter_post()
+    shared_setup_and_enter_post(el1_entry)
 }
 
 /// QEMU boot-up sequence.
@@ -172,7 +213,7 @@ fn setup_and_enter_el1_from_el2() -> ! {
 #[cfg(qemu)]
 #[link_section = ".text.boot"]
 #[inline]
-fn setup_and_enter_el1_from_el3() -> ! {
+pub(super) fn setup_and_enter_el1_from_el3(el1_entry: EL1Entry) -> ! {
     // Set Secure Configuration Register (EL3)
     SCR_EL3.write(SCR_EL3::RW::NextELIsAarch64 + SCR_EL3::NS::NonSecure);
 
@@ -189,15 +230,45 @@ fn setup_and_enter_el1_from_el3() -> ! {
             + SPSR_EL3::M::EL1h, // Use SP_EL1
     );
 
-    // Make the Exception Link Register (EL3) point to reset().
-    ELR_EL3.set(reset as *const () as u64);
+    // Make the Exception Link Register (EL3) point to the EL1 entry, usually reset().
+    ELR_EL3.set(el1_entry.pc);
+
+    shared_setup_and_enter_post(el1_entry)
+}
 
-    shared_setup_and_enter_post()
+/// Continue at the top half alias of `entry`, the kernel's link address, with the stack moved
+/// along.
+///
+/// # Safety
+///
+/// - The MMU must be on, with the kernel mapped at both its physical and its link address.
+/// - `entry` must be the physical address of an `extern "C"` function taking up to four u64
+///   arguments.
+#[link_section = ".text.boot"]
+#[inline(always)]
+pub(super) unsafe fn enter_high_half(entry: u64, args: [u64; 4]) -> ! {
+    asm!(
+        "add sp, sp, {offset}",
+        "add {entry}, {entry}, {offset}",
+        "br {entry}",
+        entry = inout(reg) entry => _,
+        offset = in(reg) KERNEL_VIRT_OFFSET as u64,
+        in("x0") args[0],
+        in("x1") args[1],
+        in("x2") args[2],
+        in("x3") args[3],
+        options(noreturn)
+    )
 }
 
 /// Reset function.
 ///
-/// Initializes the bss section before calling into the user's `main()`.
+/// Relocates the image for where it was loaded, initializes the bss section and the per-CPU
+/// areas, then enables the MMU with the boot
+/// translation tables and moves over to the kernel's link address in the top half.
+///
+/// Runs at the physical load address, where only PC-relative addressing gives correct results:
+/// nothing here may use absolute addresses, like trait objects, formatting or panics.
 ///
 /// # Safety
 ///
@@ -206,11 +277,27 @@ fn setup_and_enter_el1_from_el3() -> ! {
 ///
 /// We are guaranteed to be in EL1 non-secure mode here.
 #[link_section = ".text.boot"]
-unsafe fn reset() -> ! {
+unsafe extern "C" fn reset(
+    dtb_phys_addr: u64,
+    boot_core_id: u64,
+    entry_el: u64,
+    load_addr: u64,
+) -> ! {
     extern "Rust" {
         // Boundaries of the .bss section, provided by the linker script.
         static __BSS_START: UnsafeCell<()>;
-        static __BSS_SIZE_U64S: UnsafeCell<()>;
+        static __BSS_END: UnsafeCell<()>;
+    }
+
+    // Only load offsets that keep the boot core stack, right below the image, in memory and
+    // the kernel mappable with its pages. Too early to report anything else.
+    let Some(load_offset) =
+        (load_addr as usize).checked_sub(BcmHost::kernel_load_address() as usize)
+    else {
+        endless_sleep()
+    };
+    if load_offset & KernelGranule::MASK != 0 || !relocation::relocate(load_offset) {
+        endless_sleep()
     }
 
     // Zeroes the .bss section
@@ -223,11 +310,12 @@ unsafe fn reset() -> ! {
     // compiler may assume they come from different allocations and thus performing
     // undesirable optimizations on them.
     // So we use a painter-and-a-size as described in provenance section.
+    //
+    // The size is computed from both boundaries, an absolute size symbol would come out
+    // shifted by the distance between the load and link addresses.
 
-    let bss = slice::from_raw_parts_mut(
-        __BSS_START.get() as *mut u64,
-        __BSS_SIZE_U64S.get() as usize,
-    );
+    let bss_size = __BSS_END.get() as usize - __BSS_START.get() as usize;
+    let bss = slice::from_raw_parts_mut(__BSS_START.get() as *mut u64, bss_size / 8);
     for i in bss {
         *i = 0;
     }
@@ -238,9 +326,55 @@ unsafe fn reset() -> ! {
     // Additionally, we assume that no statics are accessed before this point.
     atomic::compiler_fence(Ordering::SeqCst);
 
+    crate::cpu::per_cpu::init_areas();
+    crate::cpu::per_cpu::init_core(boot_core_id as usize);
+
+    let boot_tables = arch_mmu::boot_tables_init();
+    if arch_mmu::mmu()
+        .enable_mmu_and_caching(boot_tables, boot_tables)
+        .is_err()
+    {
+        // Too early to tell anyone.
+        endless_sleep()
+    }
+
+    enter_high_half(
+        reset_high_half as *const () as u64,
+        [dtb_phys_addr, boot_core_id, entry_el, load_addr],
+    )
+}
+
+/// Continuation of [`reset`] at the kernel's link address.
+///
+/// Sets up FP/SIMD trapping and its trap handler, the exception fixup table and the core's
+/// stacks, then calls into the user's `main()` with the collected [`BootInfo`].
+///
+/// # Safety
+///
+/// Only to be entered from `reset()`.
+unsafe extern "C" fn reset_high_half(
+    dtb_phys_addr: u64,
+    boot_core_id: u64,
+    entry_el: u64,
+    load_addr: u64,
+) -> ! {
+    let boot_info = BootInfo::new(dtb_phys_addr, boot_core_id, entry_el as u8, load_addr);
+
+    super::fpsimd::init();
+    exception::register_sync_handler(ExceptionClass::TrappedFpSimd, super::fpsimd::handle_trap)
+        .expect("FP/SIMD trap handler registered once");
+    exception::extable::init();
+
     extern "Rust" {
-        fn main() -> !;
+        // Boot core stack, provided by the linker script.
+        static __STACK_BOTTOM: UnsafeCell<()>;
+        static __STACK_TOP: UnsafeCell<()>;
+
+        fn main(boot_info: BootInfo) -> !;
     }
 
-    main()
+    crate::cpu::stack::set_kernel_stack(__STACK_BOTTOM.get() as usize..__STACK_TOP.get() as usize);
+    exception::use_exception_stack(virt_exception_stack_region(boot_core_id as usize).addr_range());
+
+    main(boot_info)
 }
@@ -0,0 +1,366 @@
+/*
+ * SPDX-License-Identifier: BlueOak-1.0.0
+ * Copyright (c) Berkus Decker <berkus+vesper@metta.systems>
+ */
+
+//! FP/SIMD register state.
+//!
+//! The kernel itself is built without FP/SIMD, so its code never touches q0-q31, FPCR or
+//! FPSR and exception entry doesn't save them. They belong to whatever context used them
+//! last and are switched lazily:
+//!
+//! - CPACR_EL1.FPEN traps every FP/SIMD access unless the registers hold the running
+//!   context's state.
+//! - On the trap, the registers are saved to the state of their previous owner and loaded
+//!   from the running context's state, then access is granted and the instruction retried.
+//!
+//! A context that moves to another core must be [`flush`]ed first, its registers are only
+//! ever loaded on the core it ran on last.
+//!
+//! Kernel code that wants the registers, like a NEON memcpy, runs inside [`kernel_fpsimd`].
+
+use {
+    crate::{
+        exception::{asynchronous::exec_with_irq_masked, ExceptionContext, HandlerResult},
+        per_cpu,
+    },
+    core::{arch::asm, cell::Cell, ptr},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Saved FP/SIMD registers of one context.
+#[repr(C, align(16))]
+#[derive(Debug, Clone)]
+pub struct FpSimdState {
+    /// Vector registers q0-q31.
+    vregs: [u128; 32],
+    /// Floating-point status register.
+    fpsr: u64,
+    /// Floating-point control register.
+    fpcr: u64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// CPACR_EL1.FPEN, trap FP/SIMD accesses from EL0 and EL1.
+const CPACR_FPEN_TRAP_ALL: u64 = 0b00 << 20;
+
+/// CPACR_EL1.FPEN, trap nothing.
+const CPACR_FPEN_TRAP_NOTHING: u64 = 0b11 << 20;
+
+const CPACR_FPEN_MASK: u64 = 0b11 << 20;
+
+per_cpu! {
+    /// State of the context running on this core, zero if it has none.
+    static CURRENT: Cell<usize> = Cell::new(0);
+
+    /// State the registers hold, zero if they belong to nobody.
+    static LIVE: Cell<usize> = Cell::new(0);
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Grant or take away FP/SIMD access for EL0 and EL1.
+fn set_access(enabled: bool) {
+    let fpen = if enabled {
+        CPACR_FPEN_TRAP_NOTHING
+    } else {
+        CPACR_FPEN_TRAP_ALL
+    };
+    unsafe {
+        asm!(
+            "mrs {tmp}, cpacr_el1",
+            "bic {tmp}, {tmp}, {mask}",
+            "orr {tmp}, {tmp}, {fpen}",
+            "msr cpacr_el1, {tmp}",
+            "isb",
+            tmp = out(reg) _,
+            mask = in(reg) CPACR_FPEN_MASK,
+            fpen = in(reg) fpen,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+/// Store the registers to `state`.
+///
+/// # Safety
+///
+/// - FP/SIMD access must be granted.
+unsafe fn save(state: *mut FpSimdState) {
+    // The target has FP/SIMD disabled, enable it for the assembler only.
+    asm!(
+        ".arch_extension fp",
+        ".arch_extension simd",
+        "stp q0,  q1,  [{state}, #32 * 0]",
+        "stp q2,  q3,  [{state}, #32 * 1]",
+        "stp q4,  q5,  [{state}, #32 * 2]",
+        "stp q6,  q7,  [{state}, #32 * 3]",
+        "stp q8,  q9,  [{state}, #32 * 4]",
+        "stp q10, q11, [{state}, #32 * 5]",
+        "stp q12, q13, [{state}, #32 * 6]",
+        "stp q14, q15, [{state}, #32 * 7]",
+        "stp q16, q17, [{state}, #32 * 8]",
+        "stp q18, q19, [{state}, #32 * 9]",
+        "stp q20, q21, [{state}, #32 * 10]",
+        "stp q22, q23, [{state}, #32 * 11]",
+        "stp q24, q25, [{state}, #32 * 12]",
+        "stp q26, q27, [{state}, #32 * 13]",
+        "stp q28, q29, [{state}, #32 * 14]",
+        "stp q30, q31, [{state}, #32 * 15]",
+        "mrs {fpsr}, fpsr",
+        "mrs {fpcr}, fpcr",
+        "stp {fpsr}, {fpcr}, [{state}, #32 * 16]",
+        state = in(reg) state,
+        fpsr = out(reg) _,
+        fpcr = out(reg) _,
+        options(nostack, preserves_flags)
+    );
+}
+
+/// Load the registers from `state`.
+///
+/// # Safety
+///
+/// - FP/SIMD access must be granted.
+/// - Nothing may be using the registers, the compiler doesn't know they change.
+unsafe fn restore(state: *const FpSimdState) {
+    asm!(
+        ".arch_extension fp",
+        ".arch_extension simd",
+        "ldp q0,  q1,  [{state}, #32 * 0]",
+        "ldp q2,  q3,  [{state}, #32 * 1]",
+        "ldp q4,  q5,  [{state}, #32 * 2]",
+        "ldp q6,  q7,  [{state}, #32 * 3]",
+        "ldp q8,  q9,  [{state}, #32 * 4]",
+        "ldp q10, q11, [{state}, #32 * 5]",
+        "ldp q12, q13, [{state}, #32 * 6]",
+        "ldp q14, q15, [{state}, #32 * 7]",
+        "ldp q16, q17, [{state}, #32 * 8]",
+        "ldp q18, q19, [{state}, #32 * 9]",
+        "ldp q20, q21, [{state}, #32 * 10]",
+        "ldp q22, q23, [{state}, #32 * 11]",
+        "ldp q24, q25, [{state}, #32 * 12]",
+        "ldp q26, q27, [{state}, #32 * 13]",
+        "ldp q28, q29, [{state}, #32 * 14]",
+        "ldp q30, q31, [{state}, #32 * 15]",
+        "ldp {fpsr}, {fpcr}, [{state}, #32 * 16]",
+        "msr fpsr, {fpsr}",
+        "msr fpcr, {fpcr}",
+        state = in(reg) state,
+        fpsr = out(reg) _,
+        fpcr = out(reg) _,
+        options(nostack, preserves_flags, readonly)
+    );
+}
+
+/// Save the registers to their owner, if any, and leave them to nobody.
+///
+/// # Safety
+///
+/// - FP/SIMD access must be granted.
+unsafe fn save_live() {
+    let live = LIVE.get();
+    if live != 0 {
+        save(live as *mut FpSimdState);
+        LIVE.set(0);
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl FpSimdState {
+    /// Zeroed registers, with default rounding and no exceptions flagged.
+    pub const fn new() -> Self {
+        Self {
+            vregs: [0; 32],
+            fpsr: 0,
+            fpcr: 0,
+        }
+    }
+
+    /// Saved floating-point control register.
+    pub fn fpcr(&self) -> u64 {
+        self.fpcr
+    }
+
+    /// Saved floating-point status register.
+    pub fn fpsr(&self) -> u64 {
+        self.fpsr
+    }
+
+    /// Saved vector register `n`.
+    ///
+    /// # Panics
+    ///
+    /// If `n` is not below 32.
+    pub fn vreg(&self, n: usize) -> u128 {
+        self.vregs[n]
+    }
+}
+
+impl Default for FpSimdState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Trap FP/SIMD accesses on the executing core, until a context with FP/SIMD state runs.
+///
+/// # Safety
+///
+/// - Must be called once per core, with its per-CPU area set up.
+pub unsafe fn init() {
+    CURRENT.set(0);
+    LIVE.set(0);
+    set_access(false);
+}
+
+/// Make `state` the FP/SIMD state of the context running on the executing core from now on.
+///
+/// Null for a context that doesn't use FP/SIMD. The registers are only switched on the
+/// context's first access.
+///
+/// # Safety
+///
+/// - `state` must stay valid until the next `switch_to()` or [`flush`] on this core.
+pub unsafe fn switch_to(state: *mut FpSimdState) {
+    exec_with_irq_masked(|| {
+        CURRENT.set(state as usize);
+        set_access(!state.is_null() && LIVE.get() == state as usize);
+    });
+}
+
+/// Save the registers to their owner and leave them to nobody.
+///
+/// Needed before a context moves to another core, or before the owner's state is freed.
+pub fn flush() {
+    exec_with_irq_masked(|| unsafe {
+        set_access(true);
+        save_live();
+        set_access(false);
+    });
+}
+
+/// Run `f` with FP/SIMD access, saving the registers' owner first.
+///
+/// `f` runs with IRQs masked and may clobber every FP/SIMD register. The running context
+/// gets its own state back on its next access.
+pub fn kernel_fpsimd<R>(f: impl FnOnce() -> R) -> R {
+    exec_with_irq_masked(|| {
+        set_access(true);
+        unsafe { save_live() };
+        let ret = f();
+        set_access(false);
+        ret
+    })
+}
+
+/// Load the running context's state on an FP/SIMD access trap.
+///
+/// Unhandled if the context has no FP/SIMD state, the access is a fault then.
+pub(in crate::arch::aarch64) fn handle_trap(_exc: &mut ExceptionContext) -> HandlerResult {
+    let current = CURRENT.get();
+    if current == 0 {
+        return HandlerResult::Unhandled;
+    }
+
+    set_access(true);
+    if LIVE.get() != current {
+        unsafe {
+            save_live();
+            restore(current as *const FpSimdState);
+        }
+        LIVE.set(current);
+    }
+    HandlerResult::Handled
+}
+
+/// Whether the registers hold `state`.
+pub fn is_live(state: *const FpSimdState) -> bool {
+    !state.is_null() && ptr::eq(LIVE.get() as *const FpSimdState, state)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// FPCR.DN, default NaN mode.
+    const FPCR_DN: u64 = 1 << 25;
+
+    fn read_fpcr() -> u64 {
+        let fpcr: u64;
+        unsafe {
+            asm!(
+                ".arch_extension fp",
+                "mrs {}, fpcr",
+                out(reg) fpcr,
+                options(nomem, nostack, preserves_flags)
+            )
+        };
+        fpcr
+    }
+
+    /// Kernel sections get the registers, and leave them trapped behind.
+    #[test_case]
+    fn kernel_fpsimd_grants_access() {
+        let value = kernel_fpsimd(|| {
+            let out: u64;
+            unsafe {
+                asm!(
+                    ".arch_extension fp",
+                    ".arch_extension simd",
+                    "fmov d0, {input}",
+                    "fmov {out}, d0",
+                    input = in(reg) 0x1234_5678_9abc_def0u64,
+                    out = out(reg) out,
+                    options(nomem, nostack, preserves_flags)
+                )
+            };
+            out
+        });
+        assert_eq!(value, 0x1234_5678_9abc_def0);
+        assert_eq!(LIVE.get(), 0);
+    }
+
+    /// The first access loads the context's state, flushing stores it back.
+    #[test_case]
+    fn state_is_loaded_lazily() {
+        let mut state = FpSimdState::new();
+        state.fpcr = FPCR_DN;
+
+        unsafe { switch_to(&mut state) };
+        assert!(!is_live(&state));
+
+        // Traps, and comes back with the state loaded.
+        assert_eq!(read_fpcr(), FPCR_DN);
+        assert!(is_live(&state));
+
+        unsafe {
+            asm!(
+                ".arch_extension fp",
+                "msr fpcr, {}",
+                in(reg) 0u64,
+                options(nomem, nostack, preserves_flags)
+            )
+        };
+        flush();
+        unsafe { switch_to(ptr::null_mut()) };
+
+        assert_eq!(state.fpcr(), 0);
+        assert!(!is_live(&state));
+    }
+}
@@ -1,11 +1,20 @@
 use aarch64_cpu::asm;
 
 pub mod boot;
+pub mod fpsimd;
+pub mod per_cpu;
+mod relocation;
 pub mod smp;
 
 /// Expose CPU-specific no-op opcode.
 pub use asm::nop;
 
+/// Sleep until an interrupt is pending, taken or not.
+#[inline]
+pub fn wait_for_interrupt() {
+    asm::wfi();
+}
+
 /// Loop forever in sleep mode.
 #[inline]
 pub fn endless_sleep() -> ! {
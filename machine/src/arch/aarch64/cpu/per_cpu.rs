@@ -0,0 +1,35 @@
+/*
+ * SPDX-License-Identifier: BlueOak-1.0.0
+ * Copyright (c) Berkus Decker <berkus+vesper@metta.systems>
+ */
+
+//! Architectural per-CPU data support.
+//!
+//! TPIDR_EL1 holds the distance from the `.percpu` template to the executing core's copy,
+//! so a per-CPU static is reached by adding it to the static's link-time address.
+//! A zero offset addresses the template itself, which is what single-core users like
+//! chainboot run with.
+
+use core::arch::asm;
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Offset of the executing core's per-CPU area from the template.
+#[inline(always)]
+pub fn offset() -> usize {
+    let offset: usize;
+    unsafe { asm!("mrs {}, tpidr_el1", out(reg) offset, options(nomem, nostack, preserves_flags)) };
+    offset
+}
+
+/// Point the executing core at its per-CPU area.
+///
+/// # Safety
+///
+/// - `offset` must be zero or lead to an initialized per-CPU area owned by this core.
+#[inline(always)]
+pub unsafe fn set_offset(offset: usize) {
+    asm!("msr tpidr_el1, {}", in(reg) offset, options(nomem, nostack, preserves_flags));
+}
@@ -0,0 +1,76 @@
+/*
+ * SPDX-License-Identifier: BlueOak-1.0.0
+ * Copyright (c) Berkus Decker <berkus+vesper@metta.systems>
+ */
+
+//! Self-relocation of the kernel image.
+//!
+//! The kernel is linked as a position-independent executable, so the only absolute addresses
+//! in the image are the ones listed in its `R_AARCH64_RELATIVE` relocations. The boot code
+//! rewrites them for the address the image was actually loaded at, before anything reads
+//! them. Code itself only uses PC-relative addressing and runs as is.
+
+use {
+    crate::platform::memory::mmu::KERNEL_VIRT_OFFSET,
+    core::{cell::UnsafeCell, slice},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// ELF64 relocation entry with addend.
+#[repr(C)]
+struct Elf64Rela {
+    offset: u64,
+    info: u64,
+    addend: u64,
+}
+
+const R_AARCH64_NONE: u64 = 0;
+const R_AARCH64_RELATIVE: u64 = 1027;
+
+extern "Rust" {
+    // Boundaries of the .rela.dyn section, provided by the linker script.
+    static __RELA_START: UnsafeCell<()>;
+    static __RELA_END: UnsafeCell<()>;
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Apply the image's relocations for a load `offset` from its link address.
+///
+/// Places are written through their physical address, values are the kernel's top half
+/// addresses it will run at once the MMU is on.
+///
+/// Returns false on a relocation type it doesn't know, the image is unusable then.
+///
+/// # Safety
+///
+/// - Runs with the MMU off, before .bss is cleared and before anything uses absolute addresses.
+/// - Must be called at most once, the relocations are applied in place.
+#[link_section = ".text.boot"]
+pub(super) unsafe fn relocate(offset: usize) -> bool {
+    let rela_size = __RELA_END.get() as usize - __RELA_START.get() as usize;
+    let relocations = slice::from_raw_parts(
+        __RELA_START.get() as *const Elf64Rela,
+        rela_size / core::mem::size_of::<Elf64Rela>(),
+    );
+
+    for rela in relocations {
+        match rela.info & 0xffff_ffff {
+            R_AARCH64_NONE => {}
+            R_AARCH64_RELATIVE => {
+                let place = (rela.offset as usize)
+                    .wrapping_sub(KERNEL_VIRT_OFFSET)
+                    .wrapping_add(offset);
+                *(place as *mut u64) = rela.addend.wrapping_add(offset as u64);
+            }
+            _ => return false,
+        }
+    }
+
+    true
+}
@@ -1,7 +1,266 @@
+/*
+ * SPDX-License-Identifier: BlueOak-1.0.0
+ * Copyright (c) Berkus Decker <berkus+vesper@metta.systems>
+ */
+
+//! Architectural symmetric multiprocessing.
+//!
+//! Secondary cores are held by the firmware, either spinning on a release address
+//! (spin-table, used by the Raspberry Pi armstubs and QEMU) or powered off until a
+//! PSCI CPU_ON call. Once released, a core enters at `__secondary_entry` at its physical
+//! address with the MMU off, picks up its stack, goes through the same EL2/EL3 to EL1
+//! transition as the boot core and enables the MMU. It then moves to the kernel's link
+//! address in the top half, drops the identity map, sets up exception vectors and calls
+//! into the generic [`crate::cpu::smp`] code.
+
+use {
+    super::boot::{self, EL1Entry},
+    crate::{
+        arch::aarch64::{exception as arch_exception, memory::mmu as arch_mmu},
+        exception,
+        memory::{self, mmu::interface::MMU},
+        platform::{
+            cpu::{self as platform_cpu, EnableMethod, PsciConduit, MAX_CORES},
+            memory::mmu::{
+                kernel_virt_to_phys, phys_to_kernel_virt, virt_exception_stack_region,
+                virt_kernel_stack_region,
+            },
+        },
+    },
+    aarch64_cpu::{
+        asm::{self, barrier},
+        registers::*,
+    },
+    core::arch::{asm, global_asm},
+    tock_registers::interfaces::Readable,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Physical stack top for each core, read by `__secondary_entry` with the MMU off.
+static mut SECONDARY_STACK_TOP: [u64; MAX_CORES] = [0; MAX_CORES];
+
+/// Physical address of the kernel translation tables the secondary cores switch to.
+static mut SECONDARY_TABLES_BASE: u64 = 0;
+
+// Secondary core entry point, runs without a stack and with the MMU off.
+//
+// Picks up the core's stack top, then continues in Rust.
+global_asm!(
+    r#"
+.section .text.boot, "ax"
+.global __secondary_entry
+__secondary_entry:
+    mrs     x0, MPIDR_EL1
+    and     x0, x0, #{core_mask}
+    adrp    x1, {stack_tops}
+    add     x1, x1, :lo12:{stack_tops}
+    ldr     x2, [x1, x0, lsl #3]
+    mov     sp, x2
+    b       {secondary_boot}
+"#,
+    core_mask = const 0x3,
+    stack_tops = sym SECONDARY_STACK_TOP,
+    secondary_boot = sym secondary_boot,
+);
+
+extern "C" {
+    fn __secondary_entry() -> !;
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Clean and invalidate a range to the point of coherency, so that a core with
+/// caches off observes the data.
+fn clean_dcache_range(start: usize, size: usize) {
+    let ctr: u64;
+    unsafe { asm!("mrs {}, ctr_el0", out(reg) ctr, options(nomem, nostack)) };
+    // CTR_EL0.DminLine is log2 of the line size in words.
+    let line = 4usize << ((ctr >> 16) & 0xf);
+
+    let mut addr = start & !(line - 1);
+    while addr < start + size {
+        unsafe { asm!("dc civac, {}", in(reg) addr, options(nostack)) };
+        addr += line;
+    }
+    barrier::dsb(barrier::SY);
+}
+
+/// Issue a PSCI call through the given conduit.
+unsafe fn psci_call(conduit: PsciConduit, function: u32, arg0: u64, arg1: u64, arg2: u64) -> i64 {
+    let mut result = u64::from(function);
+    match conduit {
+        PsciConduit::Hvc => asm!(
+            "hvc #0",
+            inout("x0") result,
+            in("x1") arg0,
+            in("x2") arg1,
+            in("x3") arg2,
+            options(nostack)
+        ),
+        PsciConduit::Smc => asm!(
+            "smc #0",
+            inout("x0") result,
+            in("x1") arg0,
+            in("x2") arg1,
+            in("x3") arg2,
+            options(nostack)
+        ),
+    }
+    result as i64
+}
+
+/// First Rust code on a secondary core, still in the firmware's exception level.
+///
+/// # Safety
+///
+/// Only to be called from `__secondary_entry` with a valid stack.
+#[link_section = ".text.boot"]
+unsafe extern "C" fn secondary_boot(core_id: u64) -> ! {
+    // Can't match values with dots in match, so use intermediate consts.
+    #[cfg(qemu)]
+    const EL3: u64 = CurrentEL::EL::EL3.value;
+    const EL2: u64 = CurrentEL::EL::EL2.value;
+    const EL1: u64 = CurrentEL::EL::EL1.value;
+
+    boot::shared_setup_and_enter_pre();
+
+    let el1_entry = EL1Entry {
+        pc: secondary_reset as *const () as u64,
+        sp: core::ptr::read_volatile(core::ptr::addr_of!(SECONDARY_STACK_TOP[core_id as usize])),
+        args: [core_id, 0, 0, 0],
+    };
+    match CurrentEL.get() {
+        #[cfg(qemu)]
+        EL3 => boot::setup_and_enter_el1_from_el3(el1_entry),
+        EL2 => boot::setup_and_enter_el1_from_el2(el1_entry),
+        EL1 => secondary_reset(core_id),
+        _ => super::endless_sleep(),
+    }
+}
+
+/// Secondary core init in EL1: per-CPU area, then MMU with the boot identity map and the
+/// kernel's tables.
+///
+/// Runs at the physical address, same restrictions as the boot core's `reset()` apply.
+///
+/// # Safety
+///
+/// Only to be entered from `secondary_boot()`.
+#[link_section = ".text.boot"]
+unsafe extern "C" fn secondary_reset(core_id: u64) -> ! {
+    // Areas were filled by the boot core, nothing touches them before the MMU is on.
+    crate::cpu::per_cpu::init_core(core_id as usize);
+
+    let tables = core::ptr::read_volatile(core::ptr::addr_of!(SECONDARY_TABLES_BASE));
+    if arch_mmu::mmu()
+        .enable_mmu_and_caching(
+            arch_mmu::boot_tables_phys_addr(),
+            memory::Address::new(tables as usize),
+        )
+        .is_err()
+    {
+        // Too early to tell anyone, the boot core times out waiting.
+        super::endless_sleep()
+    }
+
+    boot::enter_high_half(
+        secondary_reset_high_half as *const () as u64,
+        [core_id, 0, 0, 0],
+    )
+}
+
+/// Secondary core init at the kernel's link address: drop the identity map, then set up
+/// exception vectors, FP/SIMD trapping and the core's stacks.
+///
+/// # Safety
+///
+/// Only to be entered from `secondary_reset()`.
+unsafe extern "C" fn secondary_reset_high_half(core_id: u64) -> ! {
+    arch_mmu::mmu().disable_identity_map();
+    exception::handling_init();
+    super::fpsimd::init();
+
+    crate::cpu::stack::set_kernel_stack(virt_kernel_stack_region(core_id as usize).addr_range());
+    arch_exception::use_exception_stack(virt_exception_stack_region(core_id as usize).addr_range());
+
+    crate::cpu::smp::secondary_main(core_id)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Return the executing core's id.
 #[inline(always)]
 pub fn core_id() -> u64 {
-    use aarch64_cpu::registers::{Readable, MPIDR_EL1};
-
     const CORE_MASK: u64 = 0x3;
     MPIDR_EL1.get() & CORE_MASK
 }
+
+/// Release a secondary core from the firmware.
+///
+/// The core comes up in `crate::cpu::smp::secondary_main()` with MMU enabled
+/// using the translation tables at `phys_tables_base_addr`.
+///
+/// # Safety
+///
+/// - The core must not be running yet.
+/// - The boot translation tables must be intact, the core runs from them until it reaches
+///   the top half.
+pub unsafe fn start_core(
+    core_id: u64,
+    phys_tables_base_addr: memory::Address<memory::Physical>,
+) -> Result<(), &'static str> {
+    let core = core_id as usize;
+    if core >= MAX_CORES {
+        return Err("Core id out of range");
+    }
+
+    // Everything the core reads before its MMU is on must reach memory.
+    let stack = virt_kernel_stack_region(core).addr_range();
+    SECONDARY_STACK_TOP[core] =
+        kernel_virt_to_phys(memory::Address::new(stack.end)).as_usize() as u64;
+    SECONDARY_TABLES_BASE = phys_tables_base_addr.as_usize() as u64;
+    clean_dcache_range(
+        core::ptr::addr_of!(SECONDARY_STACK_TOP) as usize,
+        core::mem::size_of::<[u64; MAX_CORES]>(),
+    );
+    clean_dcache_range(
+        core::ptr::addr_of!(SECONDARY_TABLES_BASE) as usize,
+        core::mem::size_of::<u64>(),
+    );
+    // Drop any cached lines of the stack, the core writes it with caches off at first.
+    clean_dcache_range(stack.start, stack.len());
+
+    // The core starts with the MMU off, at the physical address.
+    let entry = kernel_virt_to_phys(memory::Address::new(
+        __secondary_entry as *const () as usize,
+    ))
+    .as_usize() as u64;
+
+    match platform_cpu::enable_method(core_id) {
+        EnableMethod::SpinTable { release_addr } => {
+            // The spin table sits in the first page of memory, mapped with the kernel binary.
+            let release = phys_to_kernel_virt(memory::Address::new(release_addr)).as_usize();
+            core::ptr::write_volatile(release as *mut u64, entry);
+            clean_dcache_range(release, core::mem::size_of::<u64>());
+            asm::sev();
+            Ok(())
+        }
+        EnableMethod::Psci {
+            conduit,
+            cpu_on,
+            mpidr,
+        } => match psci_call(conduit, cpu_on, mpidr, entry, 0) {
+            0 => Ok(()),
+            -4 => Err("PSCI: core already on"),
+            _ => Err("PSCI CPU_ON failed"),
+        },
+        EnableMethod::None => Err("Core has no enable method"),
+    }
+}
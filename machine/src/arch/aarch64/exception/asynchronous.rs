@@ -15,7 +15,9 @@ use {
 //--------------------------------------------------------------------------------------------------
 
 mod daif_bits {
+    pub const SERROR: u8 = 0b0100;
     pub const IRQ: u8 = 0b0010;
+    pub const FIQ: u8 = 0b0001;
 }
 
 trait DaifField {
@@ -68,7 +70,7 @@ where
 
 /// Returns whether IRQs are masked on the executing core.
 pub fn is_local_irq_masked() -> bool {
-    !is_masked::<IRQ>()
+    is_masked::<IRQ>()
 }
 
 /// Unmask IRQs on the executing core.
@@ -101,6 +103,40 @@ pub fn local_irq_mask() {
     }
 }
 
+/// Unmask SErrors on the executing core.
+///
+/// Out of reset they are masked, and an SError raised meanwhile stays pending until then.
+#[inline(always)]
+pub fn local_serror_unmask() {
+    unsafe {
+        asm!(
+        "msr DAIFClr, {arg}",
+        arg = const daif_bits::SERROR,
+        options(nomem, nostack, preserves_flags)
+        );
+    }
+}
+
+/// Unmask FIQs on the executing core.
+///
+/// Exception entry masks them along with IRQs, interrupt handlers unmask them again to let
+/// FIQs preempt them.
+#[inline(always)]
+pub fn local_fiq_unmask() {
+    unsafe {
+        asm!(
+        "msr DAIFClr, {arg}",
+        arg = const daif_bits::FIQ,
+        options(nomem, nostack, preserves_flags)
+        );
+    }
+}
+
+/// Whether SErrors are masked on the executing core.
+pub fn is_local_serror_masked() -> bool {
+    is_masked::<SError>()
+}
+
 /// Mask IRQs on the executing core and return the previously saved interrupt mask bits (DAIF).
 #[inline(always)]
 pub fn local_irq_mask_save() -> u64 {
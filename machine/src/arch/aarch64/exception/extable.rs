@@ -0,0 +1,139 @@
+/*
+ * SPDX-License-Identifier: BlueOak-1.0.0
+ * Copyright (c) Berkus Decker <berkus+vesper@metta.systems>
+ */
+
+//! Exception fixup table.
+//!
+//! Load and store instructions that are allowed to fault are recorded with
+//! [`asm_extable`] next to a fixup label. When one of them takes a synchronous exception,
+//! the handler looks up ELR_EL1 in the table and resumes at the fixup instead of
+//! panicking. The fixup code sets up the error for its caller, typically in a register
+//! the surrounding asm block returns.
+//!
+//! ```ignore
+//! asm!(
+//!     "mov {err}, #0",
+//!     "2: ldr {value}, [{addr}]",
+//!     "3:",
+//!     ".pushsection .text.fixup, \"ax\"",
+//!     "4: mov {err}, #1",
+//!     "   b 3b",
+//!     ".popsection",
+//!     asm_extable!("2b", "4b"),
+//!     ...
+//! );
+//! ```
+//!
+//! Entries end up in the `__ex_table` section in link order, it is sorted once at boot.
+
+use core::{cell::UnsafeCell, slice};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Record the instruction at label `$insn` as allowed to fault, resuming at label `$fixup`.
+///
+/// Expands to an asm template string, for use inside `asm!`.
+macro_rules! asm_extable {
+    ($insn:literal, $fixup:literal) => {
+        concat!(
+            ".pushsection __ex_table, \"aw\"\n",
+            ".balign 8\n",
+            ".quad ",
+            $insn,
+            ", ",
+            $fixup,
+            "\n",
+            ".popsection"
+        )
+    };
+}
+
+pub(crate) use asm_extable;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// One fixup table entry, as emitted by [`asm_extable`].
+///
+/// Plain addresses, they are relocated with the rest of the image.
+#[repr(C)]
+struct ExceptionTableEntry {
+    insn: usize,
+    fixup: usize,
+}
+
+extern "Rust" {
+    // Boundaries of the __ex_table section, provided by the linker script.
+    static __EX_TABLE_START: UnsafeCell<()>;
+    static __EX_TABLE_END: UnsafeCell<()>;
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn table_len() -> usize {
+    unsafe {
+        (__EX_TABLE_END.get() as usize - __EX_TABLE_START.get() as usize)
+            / core::mem::size_of::<ExceptionTableEntry>()
+    }
+}
+
+fn table() -> &'static [ExceptionTableEntry] {
+    unsafe {
+        slice::from_raw_parts(
+            __EX_TABLE_START.get() as *const ExceptionTableEntry,
+            table_len(),
+        )
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Sort the table by instruction address.
+///
+/// # Safety
+///
+/// - Must be called once by the boot core, after relocation and before any fixup is looked up.
+pub unsafe fn init() {
+    slice::from_raw_parts_mut(
+        __EX_TABLE_START.get() as *mut ExceptionTableEntry,
+        table_len(),
+    )
+    .sort_unstable_by_key(|entry| entry.insn);
+}
+
+/// Fixup address for a faulting instruction at `pc`, if it has one.
+pub fn search(pc: usize) -> Option<usize> {
+    let table = table();
+    table
+        .binary_search_by_key(&pc, |entry| entry.insn)
+        .ok()
+        .map(|index| table[index].fixup)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Entries are in order after boot, and every recorded instruction is found.
+    #[test_case]
+    fn table_is_sorted() {
+        let table = table();
+        assert!(table.windows(2).all(|pair| pair[0].insn <= pair[1].insn));
+        for entry in table {
+            assert_eq!(search(entry.insn), Some(entry.fixup));
+        }
+        assert_eq!(search(0), None);
+    }
+}
@@ -48,14 +48,37 @@
 //! nested exceptions, for example, to allow a higher priority interrupt
 //! to interrupt the handling of a lower priority source, then software needs
 //! to explicitly re-enable interrupts
+//!
+//! Synchronous exceptions are dispatched by exception class, see [`register_sync_handler`].
+//! Whatever a handler leaves unhandled falls through, in this order:
+//!
+//! 1. The handler registered for the exception class, if any.
+//! 2. The fixup table, for faults of the kernel's faultable accessors.
+//! 3. A data abort on the kernel stack's guard page panics as a stack overflow.
+//! 4. Everything else panics in the default handler.
+//!
+//! Exceptions from lower ELs only go through the registered handler.
+//!
+//! SErrors are unmasked once the vectors are installed. Corrected ones are only reported,
+//! any other goes through the panic policy, reporting its severity.
 
 use {
+    self::syndrome::{ExceptionClass, SErrorSeverity, Syndrome},
     crate::{
-        exception::{self, PrivilegeLevel},
-        info,
+        backtrace::Backtrace,
+        cpu::stack,
+        exception::{self, HandlerResult, PrivilegeLevel},
+        info, per_cpu, symbols,
+        synchronization::{interface::ReadWriteEx, RwSpinLock},
+        warn,
     },
     aarch64_cpu::{asm::barrier, registers::*},
-    core::{cell::UnsafeCell, fmt},
+    core::{
+        arch::asm,
+        cell::{Cell, UnsafeCell},
+        fmt,
+        ops::Range,
+    },
     snafu::Snafu,
     tock_registers::{
         interfaces::{Readable, Writeable},
@@ -64,6 +87,8 @@ use {
 };
 
 pub mod asynchronous;
+pub mod extable;
+pub mod syndrome;
 
 core::arch::global_asm!(include_str!("vectors.S"));
 
@@ -76,9 +101,19 @@ core::arch::global_asm!(include_str!("vectors.S"));
 struct SpsrEL1(InMemoryRegister<u64, SPSR_EL1::Register>);
 struct EsrEL1(InMemoryRegister<u64, ESR_EL1::Register>);
 
+/// Number of exception classes, ESR_ELx.EC is 6 bits wide.
+const NUM_EXCEPTION_CLASSES: usize = 64;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
 /// The exception context as it is stored on the stack on exception entry.
+///
+/// FP/SIMD registers are not part of it, the kernel doesn't touch them and they are
+/// switched lazily, see [`crate::arch::aarch64::cpu::fpsimd`].
 #[repr(C)]
-struct ExceptionContext {
+pub struct ExceptionContext {
     /// General Purpose Registers, x0-x29
     gpr: [u64; 30],
 
@@ -93,8 +128,29 @@ struct ExceptionContext {
 
     /// Exception syndrome register.
     esr_el1: EsrEL1,
+
+    /// Stack pointer of kernel code running on SP_EL0, see [`use_exception_stack`].
+    sp_el0: u64,
+
+    /// Pads the frame to a multiple of 16 bytes.
+    _reserved: u64,
 }
 
+/// Handler for one class of synchronous exceptions.
+pub type SyncHandler = fn(&mut ExceptionContext) -> HandlerResult;
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+per_cpu! {
+    /// IRQs the core is handling, more than one once handlers are preempted.
+    static IRQ_NESTING: Cell<usize> = Cell::new(0);
+}
+
+static SYNC_HANDLERS: RwSpinLock<[Option<SyncHandler>; NUM_EXCEPTION_CLASSES]> =
+    RwSpinLock::new([None; NUM_EXCEPTION_CLASSES]);
+
 //--------------------------------------------------------------------------------------------------
 // Private Code
 //--------------------------------------------------------------------------------------------------
@@ -112,54 +168,148 @@ fn default_exception_handler(exc: &ExceptionContext) {
     );
 }
 
+/// Run the handler registered for the exception's class.
+fn dispatch_sync_handler(exc: &mut ExceptionContext) -> HandlerResult {
+    let handler = exc
+        .exception_class()
+        .and_then(|class| SYNC_HANDLERS.read(|handlers| handlers[class as usize]));
+
+    match handler {
+        Some(handler) => handler(exc),
+        None => HandlerResult::Unhandled,
+    }
+}
+
+/// Resume a faulting instruction recorded in the fixup table at its fixup.
+fn apply_fixup(exc: &mut ExceptionContext) -> bool {
+    if exc.exception_class() != Some(ExceptionClass::DataAbortCurrentEL) {
+        return false;
+    }
+
+    match extable::search(exc.elr_el1 as usize) {
+        Some(fixup) => {
+            exc.elr_el1 = fixup as u64;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Whether a data abort hit the guard page below the kernel stack.
+fn is_kernel_stack_overflow(exc: &ExceptionContext) -> bool {
+    exc.exception_class() == Some(ExceptionClass::DataAbortCurrentEL)
+        && stack::is_kernel_stack_guard(FAR_EL1.get() as usize)
+}
+
+/// Synchronous exceptions of the kernel itself, taken on either stack.
+fn current_synchronous(e: &mut ExceptionContext) {
+    if dispatch_sync_handler(e) == HandlerResult::Handled || apply_fixup(e) {
+        return;
+    }
+
+    if is_kernel_stack_overflow(e) {
+        panic!("Kernel stack overflow!\n\n{}", e);
+    }
+
+    default_exception_handler(e);
+}
+
+/// SErrors, from any EL.
+///
+/// They are asynchronous, ELR points at wherever the core was when it took the SError and
+/// not at the access that caused it.
+fn serror(e: &mut ExceptionContext) {
+    match e.syndrome().serror_severity() {
+        Some(SErrorSeverity::Corrected) => {
+            warn!("Corrected SError at {:#018x}", e.elr_el1);
+        }
+        Some(severity) => panic!("{} SError!\n\n{}", severity, e),
+        None => panic!("Uncategorized SError!\n\n{}", e),
+    }
+}
+
+/// IRQs interrupting the kernel, taken on either stack.
+///
+/// IRQs may nest, if the IRQ manager lets more urgent IRQs preempt handlers. Every entry has
+/// its own frame, the saved ELR, SPSR and SP_EL0 included. Work deferred by the handlers runs
+/// before returning from the outermost IRQ, with IRQs unmasked.
+fn current_irq(e: &ExceptionContext) {
+    IRQ_NESTING.set(IRQ_NESTING.get() + 1);
+
+    // Let FIQs preempt the handlers, unless the interrupted code masked them.
+    if !e.spsr_el1.0.is_set(SPSR_EL1::F) {
+        exception::asynchronous::local_fiq_unmask();
+    }
+
+    let token = unsafe { &exception::asynchronous::IRQContext::new() };
+    exception::asynchronous::irq_manager().handle_pending_irqs(token);
+
+    IRQ_NESTING.set(IRQ_NESTING.get() - 1);
+
+    if IRQ_NESTING.get() == 0 {
+        exception::asynchronous::run_deferred_work();
+    }
+}
+
+/// FIQs, from anywhere.
+///
+/// They come in on a frame of their own, which only holds the registers a call may clobber,
+/// and go straight to the FIQ handler.
+fn fiq() {
+    exception::asynchronous::handle_fiq();
+}
+
 //------------------------------------------------------------------------------
 // Current, EL0
 //------------------------------------------------------------------------------
 
+// Kernel code runs on SP_EL0 once the core has its exception stack, see
+// [`use_exception_stack`]. Exceptions interrupting it come in here.
+
 #[no_mangle]
-extern "C" fn current_el0_synchronous(_e: &mut ExceptionContext) {
-    panic!("Should not be here. Use of SP_EL0 in EL1 is not supported.")
+extern "C" fn current_el0_synchronous(e: &mut ExceptionContext) {
+    current_synchronous(e);
 }
 
 #[no_mangle]
-extern "C" fn current_el0_irq(_e: &mut ExceptionContext) {
-    panic!("Should not be here. Use of SP_EL0 in EL1 is not supported.")
+extern "C" fn current_el0_irq(e: &mut ExceptionContext) {
+    current_irq(e);
 }
 
 #[no_mangle]
-extern "C" fn current_el0_serror(_e: &mut ExceptionContext) {
-    panic!("Should not be here. Use of SP_EL0 in EL1 is not supported.")
+extern "C" fn current_el0_fiq() {
+    fiq();
+}
+
+#[no_mangle]
+extern "C" fn current_el0_serror(e: &mut ExceptionContext) {
+    serror(e);
 }
 
 //------------------------------------------------------------------------------
 // Current, ELx
 //------------------------------------------------------------------------------
 
+// Exceptions taken while on SP_EL1: during early boot, or nested in an exception handler.
+
 #[no_mangle]
 extern "C" fn current_elx_synchronous(e: &mut ExceptionContext) {
-    #[cfg(feature = "test_build")]
-    {
-        const TEST_SVC_ID: u64 = 0x1337;
-
-        if let Some(ESR_EL1::EC::Value::SVC64) = e.esr_el1.exception_class() {
-            if e.esr_el1.iss() == TEST_SVC_ID {
-                return;
-            }
-        }
-    }
+    current_synchronous(e);
+}
 
-    default_exception_handler(e);
+#[no_mangle]
+extern "C" fn current_elx_irq(e: &mut ExceptionContext) {
+    current_irq(e);
 }
 
 #[no_mangle]
-extern "C" fn current_elx_irq(_e: &mut ExceptionContext) {
-    let token = unsafe { &exception::asynchronous::IRQContext::new() };
-    exception::asynchronous::irq_manager().handle_pending_irqs(token);
+extern "C" fn current_elx_fiq() {
+    fiq();
 }
 
 #[no_mangle]
 extern "C" fn current_elx_serror(e: &mut ExceptionContext) {
-    default_exception_handler(e);
+    serror(e);
 }
 
 //------------------------------------------------------------------------------
@@ -168,6 +318,10 @@ extern "C" fn current_elx_serror(e: &mut ExceptionContext) {
 
 #[no_mangle]
 extern "C" fn lower_aarch64_synchronous(e: &mut ExceptionContext) {
+    if dispatch_sync_handler(e) == HandlerResult::Handled {
+        return;
+    }
+
     default_exception_handler(e);
 }
 
@@ -176,9 +330,14 @@ extern "C" fn lower_aarch64_irq(e: &mut ExceptionContext) {
     default_exception_handler(e);
 }
 
+#[no_mangle]
+extern "C" fn lower_aarch64_fiq() {
+    fiq();
+}
+
 #[no_mangle]
 extern "C" fn lower_aarch64_serror(e: &mut ExceptionContext) {
-    default_exception_handler(e);
+    serror(e);
 }
 
 //------------------------------------------------------------------------------
@@ -195,9 +354,14 @@ extern "C" fn lower_aarch32_irq(e: &mut ExceptionContext) {
     default_exception_handler(e);
 }
 
+#[no_mangle]
+extern "C" fn lower_aarch32_fiq() {
+    fiq();
+}
+
 #[no_mangle]
 extern "C" fn lower_aarch32_serror(e: &mut ExceptionContext) {
-    default_exception_handler(e);
+    serror(e);
 }
 
 //------------------------------------------------------------------------------
@@ -239,62 +403,25 @@ impl fmt::Display for SpsrEL1 {
 
 impl EsrEL1 {
     #[inline(always)]
-    fn exception_class(&self) -> Option<ESR_EL1::EC::Value> {
-        self.0.read_as_enum(ESR_EL1::EC)
-    }
-
-    #[cfg(feature = "test_build")]
-    #[inline(always)]
-    fn iss(&self) -> u64 {
-        self.0.read(ESR_EL1::ISS)
+    fn syndrome(&self) -> Syndrome {
+        Syndrome::new(self.0.get())
     }
 }
 
 /// Human readable ESR_EL1.
-#[rustfmt::skip]
 impl fmt::Display for EsrEL1 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         // Raw print of whole register.
         writeln!(f, "ESR_EL1: {:#010x}", self.0.get())?;
 
-        // Raw print of exception class.
-        write!(f, "      Exception Class         (EC) : {:#x}", self.0.read(ESR_EL1::EC))?;
-
-        // Exception class.
-        let ec_translation = match self.exception_class() {
-            Some(ESR_EL1::EC::Value::DataAbortCurrentEL) => "Data Abort, current EL",
-            _ => "N/A",
-        };
-        writeln!(f, " - {}", ec_translation)?;
-
-        // Raw print of instruction specific syndrome.
-        write!(f, "      Instr Specific Syndrome (ISS): {:#x}", self.0.read(ESR_EL1::ISS))
+        write!(f, "{}", self.syndrome())
     }
 }
 
 impl ExceptionContext {
-    #[inline(always)]
-    fn exception_class(&self) -> Option<ESR_EL1::EC::Value> {
-        self.esr_el1.exception_class()
-    }
-
     #[inline(always)]
     fn fault_address_valid(&self) -> bool {
-        use ESR_EL1::EC::Value::*;
-
-        match self.exception_class() {
-            None => false,
-            Some(ec) => matches!(
-                ec,
-                InstrAbortLowerEL
-                    | InstrAbortCurrentEL
-                    | PCAlignmentFault
-                    | DataAbortLowerEL
-                    | DataAbortCurrentEL
-                    | WatchpointLowerEL
-                    | WatchpointCurrentEL
-            ),
-        }
+        matches!(self.exception_class(), Some(ec) if ec.has_fault_address())
     }
 }
 
@@ -308,7 +435,11 @@ impl fmt::Display for ExceptionContext {
         }
 
         writeln!(f, "{}", self.spsr_el1)?;
-        writeln!(f, "ELR_EL1: {:#018x}", self.elr_el1)?;
+        write!(f, "ELR_EL1: {:#018x}", self.elr_el1)?;
+        match symbols::lookup(self.elr_el1 as usize) {
+            Some(symbol) => writeln!(f, " ({})", symbol)?,
+            None => writeln!(f)?,
+        }
         writeln!(f)?;
         writeln!(f, "General purpose register:")?;
 
@@ -324,7 +455,15 @@ impl fmt::Display for ExceptionContext {
         for (i, reg) in self.gpr.iter().enumerate() {
             write!(f, "      x{: <2}: {: >#018x}{}", i, reg, alternating(i))?;
         }
-        write!(f, "      lr : {:#018x}", self.lr)
+        writeln!(f, "      lr : {:#018x}", self.lr)?;
+        writeln!(f, "      sp : {:#018x} (SP_EL0)", self.sp_el0)?;
+        writeln!(f)?;
+
+        write!(
+            f,
+            "{}",
+            Backtrace::from_context(self.elr_el1 as usize, self.gpr[29] as usize)
+        )
     }
 }
 
@@ -332,6 +471,82 @@ impl fmt::Display for ExceptionContext {
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+impl ExceptionContext {
+    /// The decoded ESR_EL1.
+    #[inline(always)]
+    pub fn syndrome(&self) -> Syndrome {
+        self.esr_el1.syndrome()
+    }
+
+    /// The class of the exception, `None` for an encoding the architecture doesn't define.
+    #[inline(always)]
+    pub fn exception_class(&self) -> Option<ExceptionClass> {
+        self.syndrome().exception_class()
+    }
+
+    /// The address execution resumes at on return.
+    pub fn elr(&self) -> usize {
+        self.elr_el1 as usize
+    }
+
+    /// Resume execution at `addr` on return.
+    pub fn set_elr(&mut self, addr: usize) {
+        self.elr_el1 = addr as u64;
+    }
+
+    /// Resume execution after the instruction that took the exception.
+    ///
+    /// Only for exceptions whose ELR points at that instruction, like BRK or aborts. SVC
+    /// already returns past it.
+    pub fn skip_instruction(&mut self) {
+        self.elr_el1 += self.syndrome().instruction_length() as u64;
+    }
+
+    /// General purpose register `xN`, x30 being the link register.
+    pub fn gpr(&self, n: usize) -> u64 {
+        match n {
+            30 => self.lr,
+            _ => self.gpr[n],
+        }
+    }
+
+    /// Set general purpose register `xN`, restored on return.
+    pub fn set_gpr(&mut self, n: usize, value: u64) {
+        match n {
+            30 => self.lr = value,
+            _ => self.gpr[n] = value,
+        }
+    }
+
+    /// The faulting virtual address, if the exception class reports one in FAR_EL1.
+    pub fn fault_address(&self) -> Option<usize> {
+        self.fault_address_valid().then(|| FAR_EL1.get() as usize)
+    }
+}
+
+/// Register the handler for one class of synchronous exceptions.
+///
+/// The handler runs for the class's exceptions from all ELs, ahead of the built-in fallbacks
+/// listed in the [module documentation](self). Each class has at most one owner.
+pub fn register_sync_handler(
+    class: ExceptionClass,
+    handler: SyncHandler,
+) -> Result<(), &'static str> {
+    SYNC_HANDLERS.write(|handlers| {
+        let slot = &mut handlers[class as usize];
+        if slot.is_some() {
+            return Err("Sync handler already registered");
+        }
+        *slot = Some(handler);
+        Ok(())
+    })
+}
+
+/// Remove the handler for `class`, its exceptions fall through to the built-in fallbacks.
+pub fn unregister_sync_handler(class: ExceptionClass) {
+    SYNC_HANDLERS.write(|handlers| handlers[class as usize] = None);
+}
+
 /// The processor's current privilege level.
 pub fn current_privilege_level() -> (PrivilegeLevel, &'static str) {
     let el = CurrentEL.read_as_enum(CurrentEL::EL);
@@ -344,7 +559,8 @@ pub fn current_privilege_level() -> (PrivilegeLevel, &'static str) {
     }
 }
 
-/// Init exception handling by setting the exception vector base address register.
+/// Init exception handling by setting the exception vector base address register, then
+/// unmask SErrors.
 ///
 /// # Safety
 ///
@@ -362,9 +578,34 @@ pub fn handling_init() {
         set_vbar_el1_checked(__EXCEPTION_VECTORS_START.get() as u64)
             .expect("Vector table properly aligned!");
     }
+    asynchronous::local_serror_unmask();
     info!("[!] Exception traps set up");
 }
 
+/// Take exceptions on their own stack from now on.
+///
+/// Kernel code moves over to SP_EL0 and keeps running on the stack it is on, while SP_EL1
+/// is pointed at `stack`. Exceptions are then taken on `stack`, where they can still be
+/// handled after the kernel stack overflowed into its guard page.
+///
+/// # Safety
+///
+/// - `stack` must be mapped and used for nothing else.
+/// - Must be called once per core, with IRQs masked and while running on SP_EL1.
+pub unsafe fn use_exception_stack(stack: Range<usize>) {
+    // SP_EL1 can't be written directly from EL1, only through SP while SPSel selects it.
+    asm!(
+        "mov {tmp}, sp",
+        "msr sp_el0, {tmp}",
+        "mov sp, {top}",
+        "msr spsel, #0",
+        tmp = out(reg) _,
+        top = in(reg) stack.end,
+        options(nomem, nostack)
+    );
+    stack::set_exception_stack(stack);
+}
+
 /// Errors possibly returned from the traps module.
 /// @todo a big over-engineered here.
 #[derive(Debug, Snafu)]
@@ -392,3 +633,62 @@ unsafe fn set_vbar_el1_checked(vec_base_addr: u64) -> Result<(), Error> {
 
     Ok(())
 }
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_SVC_ID: u16 = 0x1337;
+
+    fn test_svc_handler(exc: &mut ExceptionContext) -> HandlerResult {
+        if exc.syndrome().immediate() != TEST_SVC_ID {
+            return HandlerResult::Unhandled;
+        }
+        exc.set_gpr(0, exc.gpr(0) + 1);
+        HandlerResult::Handled
+    }
+
+    /// A registered handler owns its class and returns to the caller with its changes.
+    #[test_case]
+    fn sync_handler_owns_its_class() {
+        register_sync_handler(ExceptionClass::Svc64, test_svc_handler).unwrap();
+        assert!(register_sync_handler(ExceptionClass::Svc64, test_svc_handler).is_err());
+
+        let mut value: u64 = 41;
+        unsafe { asm!("svc #0x1337", inout("x0") value) };
+        assert_eq!(value, 42);
+
+        unregister_sync_handler(ExceptionClass::Svc64);
+    }
+
+    /// SErrors can be taken once exception handling is set up.
+    #[test_case]
+    fn serror_is_unmasked_after_init() {
+        // Start from the reset state, with SErrors masked.
+        unsafe { asm!("msr DAIFSet, #0b0100") };
+        assert!(asynchronous::is_local_serror_masked());
+
+        handling_init();
+        assert!(!asynchronous::is_local_serror_masked());
+    }
+
+    /// An injected corrected SError is reported and execution continues.
+    #[test_case]
+    fn corrected_serror_returns() {
+        let mut e = ExceptionContext {
+            gpr: [0; 30],
+            lr: 0,
+            elr_el1: 0,
+            spsr_el1: SpsrEL1(InMemoryRegister::new(0)),
+            // SError, Asynchronous SError with AET Corrected.
+            esr_el1: EsrEL1(InMemoryRegister::new(0xbe00_1811)),
+            sp_el0: 0,
+            _reserved: 0,
+        };
+        serror(&mut e);
+    }
+}
@@ -0,0 +1,928 @@
+/*
+ * SPDX-License-Identifier: BlueOak-1.0.0
+ * Copyright (c) Berkus Decker <berkus+vesper@metta.systems>
+ */
+
+//! Exception syndrome decoding.
+//!
+//! ESR_EL1 and ESR_EL2 share their layout: the exception class (EC) selects how the
+//! instruction specific syndrome (ISS) is to be read. [`Syndrome`] decodes a raw value of
+//! either and never touches the registers itself, so it can be checked against known
+//! syndrome values anywhere.
+
+use {
+    core::fmt,
+    tock_registers::{register_bitfields, registers::LocalRegisterCopy},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+register_bitfields! {
+    u64,
+
+    /// Fields common to every exception class.
+    ESR [
+        /// Exception Class.
+        EC  OFFSET(26) NUMBITS(6) [],
+        /// Instruction Length, set for 32 bit instructions.
+        IL  OFFSET(25) NUMBITS(1) [],
+        /// Instruction Specific Syndrome.
+        ISS OFFSET(0)  NUMBITS(25) []
+    ],
+
+    /// ISS of Instruction and Data Aborts.
+    ISS_ABORT [
+        /// Instruction Syndrome Valid, for Data Aborts: SAS, SSE, SRT, SF and AR hold.
+        ISV   OFFSET(24) NUMBITS(1) [],
+        /// Syndrome Access Size.
+        SAS   OFFSET(22) NUMBITS(2) [],
+        /// Syndrome Sign Extend.
+        SSE   OFFSET(21) NUMBITS(1) [],
+        /// Syndrome Register Transfer.
+        SRT   OFFSET(16) NUMBITS(5) [],
+        /// Sixty-Four bit register.
+        SF    OFFSET(15) NUMBITS(1) [],
+        /// Acquire/Release semantics.
+        AR    OFFSET(14) NUMBITS(1) [],
+        /// Synchronous Error Type, for synchronous External aborts.
+        SET   OFFSET(11) NUMBITS(2) [
+            Recoverable = 0b00,
+            Uncontainable = 0b10,
+            Restartable = 0b11
+        ],
+        /// FAR not Valid.
+        FNV   OFFSET(10) NUMBITS(1) [],
+        /// External Abort type.
+        EA    OFFSET(9)  NUMBITS(1) [],
+        /// Cache Maintenance, the abort came from a cache maintenance or address translation
+        /// instruction.
+        CM    OFFSET(8)  NUMBITS(1) [],
+        /// The abort happened on a stage 2 translation of a stage 1 translation table walk.
+        S1PTW OFFSET(7)  NUMBITS(1) [],
+        /// Write not Read.
+        WNR   OFFSET(6)  NUMBITS(1) [],
+        /// Data or Instruction Fault Status Code.
+        FSC   OFFSET(0)  NUMBITS(6) []
+    ],
+
+    /// ISS of trapped MSR, MRS and system instructions.
+    ISS_SYSREG [
+        OP0       OFFSET(20) NUMBITS(2) [],
+        OP2       OFFSET(17) NUMBITS(3) [],
+        OP1       OFFSET(14) NUMBITS(3) [],
+        CRN       OFFSET(10) NUMBITS(4) [],
+        RT        OFFSET(5)  NUMBITS(5) [],
+        CRM       OFFSET(1)  NUMBITS(4) [],
+        DIRECTION OFFSET(0)  NUMBITS(1) [
+            Write = 0,
+            Read = 1
+        ]
+    ],
+
+    /// ISS of SVC, HVC, SMC and BRK.
+    ISS_IMM [
+        IMM16 OFFSET(0) NUMBITS(16) []
+    ],
+
+    /// ISS of trapped WFI and WFE.
+    ISS_WFX [
+        TI OFFSET(0) NUMBITS(2) [
+            Wfi = 0b00,
+            Wfe = 0b01,
+            Wfit = 0b10,
+            Wfet = 0b11
+        ]
+    ],
+
+    /// ISS of SError interrupts.
+    ISS_SERROR [
+        /// IMPLEMENTATION DEFINED syndrome.
+        IDS OFFSET(24) NUMBITS(1) [],
+        /// Asynchronous Error Type.
+        AET OFFSET(10) NUMBITS(3) [
+            Uncontainable = 0b000,
+            Unrecoverable = 0b001,
+            Restartable = 0b010,
+            Recoverable = 0b011,
+            Corrected = 0b110
+        ],
+        EA  OFFSET(9)  NUMBITS(1) [],
+        DFSC OFFSET(0) NUMBITS(6) []
+    ],
+
+    /// ISS of trapped floating-point exceptions.
+    ISS_FP_EXC [
+        /// Trapped Fault Valid, the flags below hold.
+        TFV OFFSET(23) NUMBITS(1) [],
+        /// Input Denormal.
+        IDF OFFSET(7) NUMBITS(1) [],
+        /// Inexact.
+        IXF OFFSET(4) NUMBITS(1) [],
+        /// Underflow.
+        UFF OFFSET(3) NUMBITS(1) [],
+        /// Overflow.
+        OFF OFFSET(2) NUMBITS(1) [],
+        /// Divide by Zero.
+        DZF OFFSET(1) NUMBITS(1) [],
+        /// Invalid Operation.
+        IOF OFFSET(0) NUMBITS(1) []
+    ]
+}
+
+/// System register encoding of a trapped MSR or MRS.
+struct SysReg {
+    op0: u64,
+    op1: u64,
+    crn: u64,
+    crm: u64,
+    op2: u64,
+}
+
+/// General purpose register number, 31 is the zero register in MSR and MRS.
+struct Gpr(u64);
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Exception classes of the ARMv8-A ESR_ELx encoding.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ExceptionClass {
+    Unknown = 0x00,
+    TrappedWfx = 0x01,
+    TrappedMcrMrcCp15 = 0x03,
+    TrappedMcrrMrrcCp15 = 0x04,
+    TrappedMcrMrcCp14 = 0x05,
+    TrappedLdcStc = 0x06,
+    TrappedFpSimd = 0x07,
+    TrappedVmrs = 0x08,
+    TrappedPointerAuth = 0x09,
+    TrappedLs64 = 0x0a,
+    TrappedMrrcCp14 = 0x0c,
+    BranchTarget = 0x0d,
+    IllegalExecutionState = 0x0e,
+    Svc32 = 0x11,
+    Hvc32 = 0x12,
+    Smc32 = 0x13,
+    Svc64 = 0x15,
+    Hvc64 = 0x16,
+    Smc64 = 0x17,
+    TrappedMsrMrs = 0x18,
+    TrappedSve = 0x19,
+    TrappedEret = 0x1a,
+    TrappedTstart = 0x1b,
+    PointerAuthFailure = 0x1c,
+    TrappedSme = 0x1d,
+    GranuleProtectionCheck = 0x1e,
+    ImplementationDefinedEl3 = 0x1f,
+    InstrAbortLowerEL = 0x20,
+    InstrAbortCurrentEL = 0x21,
+    PcAlignment = 0x22,
+    DataAbortLowerEL = 0x24,
+    DataAbortCurrentEL = 0x25,
+    SpAlignment = 0x26,
+    MemoryOperation = 0x27,
+    TrappedFp32 = 0x28,
+    TrappedFp64 = 0x2c,
+    SError = 0x2f,
+    BreakpointLowerEL = 0x30,
+    BreakpointCurrentEL = 0x31,
+    SoftwareStepLowerEL = 0x32,
+    SoftwareStepCurrentEL = 0x33,
+    WatchpointLowerEL = 0x34,
+    WatchpointCurrentEL = 0x35,
+    Bkpt32 = 0x38,
+    VectorCatch32 = 0x3a,
+    Brk64 = 0x3c,
+}
+
+/// Fault status code of an abort, from its DFSC or IFSC.
+///
+/// Levels run from -1 to 3, level -1 only exists with 52 bit addressing.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FaultStatus {
+    /// Address size fault.
+    AddressSize { level: i8 },
+    /// Translation fault.
+    Translation { level: i8 },
+    /// Access flag fault.
+    AccessFlag { level: i8 },
+    /// Permission fault.
+    Permission { level: i8 },
+    /// Synchronous External abort, not on a translation table walk.
+    SyncExternal,
+    /// Synchronous Tag Check fault.
+    SyncTagCheck,
+    /// Synchronous External abort on a translation table walk.
+    SyncExternalOnWalk { level: i8 },
+    /// Synchronous parity or ECC error, not on a translation table walk.
+    SyncParity,
+    /// Synchronous parity or ECC error on a translation table walk.
+    SyncParityOnWalk { level: i8 },
+    /// Alignment fault.
+    Alignment,
+    /// Debug exception, for watchpoints.
+    Debug,
+    /// TLB conflict abort.
+    TlbConflict,
+    /// Unsupported atomic hardware update of a translation table.
+    UnsupportedAtomicUpdate,
+    /// IMPLEMENTATION DEFINED fault, lockdown.
+    Lockdown,
+    /// IMPLEMENTATION DEFINED fault, unsupported exclusive or atomic access.
+    UnsupportedExclusive,
+    /// Reserved or unknown code.
+    Unknown(u8),
+}
+
+/// Severity of an SError, from its Asynchronous Error Type.
+///
+/// In the order of the RAS extension, from harmless to fatal.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SErrorSeverity {
+    /// Corrected by hardware, the interrupted context is unaffected (CE).
+    Corrected,
+    /// Not consumed yet, the interrupted context can be restarted (UEO).
+    Restartable,
+    /// Consumed, but the interrupted context can be recovered by software (UER).
+    Recoverable,
+    /// Contained, but the interrupted context can't continue (UEU).
+    Unrecoverable,
+    /// Possibly propagated silently, nothing can be trusted anymore (UC).
+    Uncontainable,
+}
+
+/// Decoded view of an ESR_EL1 or ESR_EL2 value.
+#[derive(Copy, Clone)]
+pub struct Syndrome(LocalRegisterCopy<u64, ESR::Register>);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Printed the way assemblers accept it.
+impl fmt::Display for SysReg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "S{}_{}_C{}_C{}_{}",
+            self.op0, self.op1, self.crn, self.crm, self.op2
+        )
+    }
+}
+
+impl fmt::Display for Gpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 == 31 {
+            write!(f, "xzr")
+        } else {
+            write!(f, "x{}", self.0)
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl ExceptionClass {
+    /// Class for a raw EC value, `None` for unallocated ones.
+    pub const fn from_raw(ec: u8) -> Option<Self> {
+        use ExceptionClass::*;
+
+        Some(match ec {
+            0x00 => Unknown,
+            0x01 => TrappedWfx,
+            0x03 => TrappedMcrMrcCp15,
+            0x04 => TrappedMcrrMrrcCp15,
+            0x05 => TrappedMcrMrcCp14,
+            0x06 => TrappedLdcStc,
+            0x07 => TrappedFpSimd,
+            0x08 => TrappedVmrs,
+            0x09 => TrappedPointerAuth,
+            0x0a => TrappedLs64,
+            0x0c => TrappedMrrcCp14,
+            0x0d => BranchTarget,
+            0x0e => IllegalExecutionState,
+            0x11 => Svc32,
+            0x12 => Hvc32,
+            0x13 => Smc32,
+            0x15 => Svc64,
+            0x16 => Hvc64,
+            0x17 => Smc64,
+            0x18 => TrappedMsrMrs,
+            0x19 => TrappedSve,
+            0x1a => TrappedEret,
+            0x1b => TrappedTstart,
+            0x1c => PointerAuthFailure,
+            0x1d => TrappedSme,
+            0x1e => GranuleProtectionCheck,
+            0x1f => ImplementationDefinedEl3,
+            0x20 => InstrAbortLowerEL,
+            0x21 => InstrAbortCurrentEL,
+            0x22 => PcAlignment,
+            0x24 => DataAbortLowerEL,
+            0x25 => DataAbortCurrentEL,
+            0x26 => SpAlignment,
+            0x27 => MemoryOperation,
+            0x28 => TrappedFp32,
+            0x2c => TrappedFp64,
+            0x2f => SError,
+            0x30 => BreakpointLowerEL,
+            0x31 => BreakpointCurrentEL,
+            0x32 => SoftwareStepLowerEL,
+            0x33 => SoftwareStepCurrentEL,
+            0x34 => WatchpointLowerEL,
+            0x35 => WatchpointCurrentEL,
+            0x38 => Bkpt32,
+            0x3a => VectorCatch32,
+            0x3c => Brk64,
+            _ => return None,
+        })
+    }
+
+    /// Human readable description.
+    pub const fn description(self) -> &'static str {
+        use ExceptionClass::*;
+
+        match self {
+            Unknown => "Unknown reason",
+            TrappedWfx => "Trapped WFI or WFE",
+            TrappedMcrMrcCp15 => "Trapped MCR or MRC, coproc 0b1111 (AArch32)",
+            TrappedMcrrMrrcCp15 => "Trapped MCRR or MRRC, coproc 0b1111 (AArch32)",
+            TrappedMcrMrcCp14 => "Trapped MCR or MRC, coproc 0b1110 (AArch32)",
+            TrappedLdcStc => "Trapped LDC or STC (AArch32)",
+            TrappedFpSimd => "Trapped SVE, SIMD or floating-point access",
+            TrappedVmrs => "Trapped VMRS (AArch32)",
+            TrappedPointerAuth => "Trapped pointer authentication instruction",
+            TrappedLs64 => "Trapped LD64B or ST64B",
+            TrappedMrrcCp14 => "Trapped MRRC, coproc 0b1110 (AArch32)",
+            BranchTarget => "Branch Target exception",
+            IllegalExecutionState => "Illegal Execution state",
+            Svc32 => "SVC (AArch32)",
+            Hvc32 => "HVC (AArch32)",
+            Smc32 => "SMC (AArch32)",
+            Svc64 => "SVC",
+            Hvc64 => "HVC",
+            Smc64 => "SMC",
+            TrappedMsrMrs => "Trapped MSR, MRS or system instruction",
+            TrappedSve => "Trapped SVE access",
+            TrappedEret => "Trapped ERET, ERETAA or ERETAB",
+            TrappedTstart => "Trapped TSTART",
+            PointerAuthFailure => "Pointer authentication failure",
+            TrappedSme => "Trapped SME access",
+            GranuleProtectionCheck => "Granule Protection Check",
+            ImplementationDefinedEl3 => "IMPLEMENTATION DEFINED exception to EL3",
+            InstrAbortLowerEL => "Instruction Abort, lower EL",
+            InstrAbortCurrentEL => "Instruction Abort, current EL",
+            PcAlignment => "PC alignment fault",
+            DataAbortLowerEL => "Data Abort, lower EL",
+            DataAbortCurrentEL => "Data Abort, current EL",
+            SpAlignment => "SP alignment fault",
+            MemoryOperation => "Memory Operation exception",
+            TrappedFp32 => "Trapped floating-point exception (AArch32)",
+            TrappedFp64 => "Trapped floating-point exception",
+            SError => "SError interrupt",
+            BreakpointLowerEL => "Breakpoint, lower EL",
+            BreakpointCurrentEL => "Breakpoint, current EL",
+            SoftwareStepLowerEL => "Software Step, lower EL",
+            SoftwareStepCurrentEL => "Software Step, current EL",
+            WatchpointLowerEL => "Watchpoint, lower EL",
+            WatchpointCurrentEL => "Watchpoint, current EL",
+            Bkpt32 => "BKPT (AArch32)",
+            VectorCatch32 => "Vector Catch (AArch32)",
+            Brk64 => "BRK",
+        }
+    }
+
+    /// Whether FAR_ELx holds the faulting address for this class.
+    pub const fn has_fault_address(self) -> bool {
+        use ExceptionClass::*;
+
+        matches!(
+            self,
+            InstrAbortLowerEL
+                | InstrAbortCurrentEL
+                | PcAlignment
+                | DataAbortLowerEL
+                | DataAbortCurrentEL
+                | WatchpointLowerEL
+                | WatchpointCurrentEL
+        )
+    }
+}
+
+impl FaultStatus {
+    /// Decode a 6 bit DFSC or IFSC.
+    pub const fn from_raw(fsc: u8) -> Self {
+        use FaultStatus::*;
+
+        // Levels 0-3 are in the low two bits, the level -1 codes sit apart.
+        let level = (fsc & 0b11) as i8;
+        match fsc {
+            0b00_0000..=0b00_0011 => AddressSize { level },
+            0b00_0100..=0b00_0111 => Translation { level },
+            0b00_1000..=0b00_1011 => AccessFlag { level },
+            0b00_1100..=0b00_1111 => Permission { level },
+            0b01_0000 => SyncExternal,
+            0b01_0001 => SyncTagCheck,
+            0b01_0011 => SyncExternalOnWalk { level: -1 },
+            0b01_0100..=0b01_0111 => SyncExternalOnWalk { level },
+            0b01_1000 => SyncParity,
+            0b01_1011 => SyncParityOnWalk { level: -1 },
+            0b01_1100..=0b01_1111 => SyncParityOnWalk { level },
+            0b10_0001 => Alignment,
+            0b10_0010 => Debug,
+            0b10_1001 => AddressSize { level: -1 },
+            0b10_1011 => Translation { level: -1 },
+            0b11_0000 => TlbConflict,
+            0b11_0001 => UnsupportedAtomicUpdate,
+            0b11_0100 => Lockdown,
+            0b11_0101 => UnsupportedExclusive,
+            _ => Unknown(fsc),
+        }
+    }
+}
+
+impl fmt::Display for FaultStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use FaultStatus::*;
+
+        match self {
+            AddressSize { level } => write!(f, "Address size fault, level {}", level),
+            Translation { level } => write!(f, "Translation fault, level {}", level),
+            AccessFlag { level } => write!(f, "Access flag fault, level {}", level),
+            Permission { level } => write!(f, "Permission fault, level {}", level),
+            SyncExternal => write!(f, "Synchronous External abort"),
+            SyncTagCheck => write!(f, "Synchronous Tag Check fault"),
+            SyncExternalOnWalk { level } => write!(
+                f,
+                "Synchronous External abort on translation table walk, level {}",
+                level
+            ),
+            SyncParity => write!(f, "Synchronous parity or ECC error"),
+            SyncParityOnWalk { level } => write!(
+                f,
+                "Synchronous parity or ECC error on translation table walk, level {}",
+                level
+            ),
+            Alignment => write!(f, "Alignment fault"),
+            Debug => write!(f, "Debug exception"),
+            TlbConflict => write!(f, "TLB conflict abort"),
+            UnsupportedAtomicUpdate => write!(f, "Unsupported atomic hardware update fault"),
+            Lockdown => write!(f, "Lockdown (IMPLEMENTATION DEFINED fault)"),
+            UnsupportedExclusive => write!(
+                f,
+                "Unsupported Exclusive or Atomic access (IMPLEMENTATION DEFINED fault)"
+            ),
+            Unknown(fsc) => write!(f, "Unknown fault status {:#08b}", fsc),
+        }
+    }
+}
+
+impl SErrorSeverity {
+    /// What the error means for the interrupted context.
+    pub const fn description(self) -> &'static str {
+        use SErrorSeverity::*;
+
+        match self {
+            Corrected => "Corrected",
+            Restartable => "Restartable",
+            Recoverable => "Recoverable",
+            Unrecoverable => "Unrecoverable",
+            Uncontainable => "Uncontainable",
+        }
+    }
+}
+
+impl fmt::Display for SErrorSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+impl Syndrome {
+    /// Wrap a raw ESR_EL1 or ESR_EL2 value.
+    pub fn new(esr: u64) -> Self {
+        Self(LocalRegisterCopy::new(esr))
+    }
+
+    /// Raw register value.
+    pub fn get(&self) -> u64 {
+        self.0.get()
+    }
+
+    /// Raw exception class.
+    pub fn ec(&self) -> u8 {
+        self.0.read(ESR::EC) as u8
+    }
+
+    /// Exception class, `None` for unallocated ones.
+    pub fn exception_class(&self) -> Option<ExceptionClass> {
+        ExceptionClass::from_raw(self.ec())
+    }
+
+    /// Instruction specific syndrome.
+    pub fn iss(&self) -> u32 {
+        self.0.read(ESR::ISS) as u32
+    }
+
+    /// Length of the trapped instruction, in bytes.
+    pub fn instruction_length(&self) -> usize {
+        if self.0.is_set(ESR::IL) {
+            4
+        } else {
+            2
+        }
+    }
+
+    /// Fault status of an abort, `None` for other classes.
+    pub fn fault_status(&self) -> Option<FaultStatus> {
+        use ExceptionClass::*;
+
+        match self.exception_class()? {
+            InstrAbortLowerEL | InstrAbortCurrentEL | DataAbortLowerEL | DataAbortCurrentEL => {
+                Some(FaultStatus::from_raw(
+                    self.abort().read(ISS_ABORT::FSC) as u8
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether a Data Abort or watchpoint was caused by a write.
+    pub fn is_write(&self) -> bool {
+        self.abort().is_set(ISS_ABORT::WNR)
+    }
+
+    /// Severity of an SError, `None` for other classes and for SErrors without an
+    /// architected syndrome.
+    pub fn serror_severity(&self) -> Option<SErrorSeverity> {
+        if self.exception_class()? != ExceptionClass::SError {
+            return None;
+        }
+
+        let iss = self.fields::<ISS_SERROR::Register>();
+        if iss.is_set(ISS_SERROR::IDS) || iss.read(ISS_SERROR::DFSC) != 0b01_0001 {
+            return None;
+        }
+
+        match iss.read_as_enum(ISS_SERROR::AET)? {
+            ISS_SERROR::AET::Value::Uncontainable => Some(SErrorSeverity::Uncontainable),
+            ISS_SERROR::AET::Value::Unrecoverable => Some(SErrorSeverity::Unrecoverable),
+            ISS_SERROR::AET::Value::Restartable => Some(SErrorSeverity::Restartable),
+            ISS_SERROR::AET::Value::Recoverable => Some(SErrorSeverity::Recoverable),
+            ISS_SERROR::AET::Value::Corrected => Some(SErrorSeverity::Corrected),
+        }
+    }
+
+    /// Immediate of an SVC, HVC, SMC or BRK.
+    pub fn immediate(&self) -> u16 {
+        self.fields::<ISS_IMM::Register>().read(ISS_IMM::IMM16) as u16
+    }
+
+    fn fields<R: tock_registers::RegisterLongName>(&self) -> LocalRegisterCopy<u64, R> {
+        LocalRegisterCopy::new(self.0.get())
+    }
+
+    fn abort(&self) -> LocalRegisterCopy<u64, ISS_ABORT::Register> {
+        self.fields()
+    }
+
+    fn fmt_abort(&self, f: &mut fmt::Formatter, data: bool) -> fmt::Result {
+        let iss = self.abort();
+        let to_flag_str = |x| -> _ {
+            if x {
+                "Set"
+            } else {
+                "Not set"
+            }
+        };
+
+        writeln!(f)?;
+        if data {
+            write!(f, "            Fault Status (DFSC): ")?;
+        } else {
+            write!(f, "            Fault Status (IFSC): ")?;
+        }
+        write!(
+            f,
+            "{}",
+            FaultStatus::from_raw(iss.read(ISS_ABORT::FSC) as u8)
+        )?;
+
+        if data {
+            let access = if iss.is_set(ISS_ABORT::WNR) {
+                "Write"
+            } else {
+                "Read"
+            };
+            write!(f, "\n            Write not Read (WnR): {}", access)?;
+            write!(
+                f,
+                "\n            Cache Maintenance (CM): {}",
+                to_flag_str(iss.is_set(ISS_ABORT::CM))
+            )?;
+        }
+        write!(
+            f,
+            "\n            Stage 1 PT Walk (S1PTW): {}",
+            to_flag_str(iss.is_set(ISS_ABORT::S1PTW))
+        )?;
+        write!(
+            f,
+            "\n            FAR not Valid (FnV): {}",
+            to_flag_str(iss.is_set(ISS_ABORT::FNV))
+        )?;
+        write!(
+            f,
+            "\n            External Abort (EA): {}",
+            to_flag_str(iss.is_set(ISS_ABORT::EA))
+        )?;
+
+        if matches!(
+            FaultStatus::from_raw(iss.read(ISS_ABORT::FSC) as u8),
+            FaultStatus::SyncExternal
+        ) {
+            let set = match iss.read_as_enum(ISS_ABORT::SET) {
+                Some(ISS_ABORT::SET::Value::Recoverable) => "Recoverable",
+                Some(ISS_ABORT::SET::Value::Uncontainable) => "Uncontainable",
+                Some(ISS_ABORT::SET::Value::Restartable) => "Restartable",
+                None => "Unknown",
+            };
+            write!(f, "\n            Sync Error Type (SET): {}", set)?;
+        }
+
+        if data && iss.is_set(ISS_ABORT::ISV) {
+            write!(
+                f,
+                "\n            Access: {} bytes, {}, {}{}{}",
+                1 << iss.read(ISS_ABORT::SAS),
+                if iss.is_set(ISS_ABORT::SSE) {
+                    "sign extended"
+                } else {
+                    "zero extended"
+                },
+                if iss.is_set(ISS_ABORT::SF) { "x" } else { "w" },
+                iss.read(ISS_ABORT::SRT),
+                if iss.is_set(ISS_ABORT::AR) {
+                    ", acquire/release"
+                } else {
+                    ""
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    fn fmt_sysreg(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let iss = self.fields::<ISS_SYSREG::Register>();
+        let rt = Gpr(iss.read(ISS_SYSREG::RT));
+        let sysreg = SysReg {
+            op0: iss.read(ISS_SYSREG::OP0),
+            op1: iss.read(ISS_SYSREG::OP1),
+            crn: iss.read(ISS_SYSREG::CRN),
+            crm: iss.read(ISS_SYSREG::CRM),
+            op2: iss.read(ISS_SYSREG::OP2),
+        };
+
+        match iss.read_as_enum(ISS_SYSREG::DIRECTION) {
+            Some(ISS_SYSREG::DIRECTION::Value::Read) => {
+                write!(f, "\n            Instruction: mrs {}, {}", rt, sysreg)
+            }
+            _ => write!(f, "\n            Instruction: msr {}, {}", sysreg, rt),
+        }
+    }
+
+    fn fmt_serror(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let iss = self.fields::<ISS_SERROR::Register>();
+
+        if iss.is_set(ISS_SERROR::IDS) {
+            return write!(f, "\n            IMPLEMENTATION DEFINED syndrome");
+        }
+        let fsc = match iss.read(ISS_SERROR::DFSC) {
+            0b00_0000 => "Uncategorized",
+            0b01_0001 => "Asynchronous SError",
+            _ => "Reserved",
+        };
+        write!(f, "\n            Fault Status (DFSC): {}", fsc)?;
+
+        if iss.read(ISS_SERROR::DFSC) == 0b01_0001 {
+            let aet = match self.serror_severity() {
+                Some(severity) => severity.description(),
+                None => "Reserved",
+            };
+            write!(f, "\n            Async Error Type (AET): {}", aet)?;
+        }
+        write!(
+            f,
+            "\n            External Abort (EA): {}",
+            if iss.is_set(ISS_SERROR::EA) {
+                "External"
+            } else {
+                "Internal"
+            }
+        )
+    }
+
+    fn fmt_fp_exception(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let iss = self.fields::<ISS_FP_EXC::Register>();
+
+        if !iss.is_set(ISS_FP_EXC::TFV) {
+            return write!(f, "\n            Trapped exceptions not recorded");
+        }
+        write!(f, "\n            Trapped:")?;
+        for (field, name) in [
+            (ISS_FP_EXC::IOF, "invalid operation"),
+            (ISS_FP_EXC::DZF, "divide by zero"),
+            (ISS_FP_EXC::OFF, "overflow"),
+            (ISS_FP_EXC::UFF, "underflow"),
+            (ISS_FP_EXC::IXF, "inexact"),
+            (ISS_FP_EXC::IDF, "input denormal"),
+        ] {
+            if iss.is_set(field) {
+                write!(f, " {}", name)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Class specific part of the ISS.
+    fn fmt_details(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ExceptionClass::*;
+
+        let Some(class) = self.exception_class() else {
+            return Ok(());
+        };
+        match class {
+            InstrAbortLowerEL | InstrAbortCurrentEL => self.fmt_abort(f, false),
+            DataAbortLowerEL | DataAbortCurrentEL => self.fmt_abort(f, true),
+            Svc32 | Hvc32 | Svc64 | Hvc64 | Smc64 | Brk64 => {
+                write!(f, "\n            Immediate: {:#06x}", self.immediate())
+            }
+            TrappedMsrMrs => self.fmt_sysreg(f),
+            TrappedWfx => {
+                let insn = match self.fields::<ISS_WFX::Register>().read_as_enum(ISS_WFX::TI) {
+                    Some(ISS_WFX::TI::Value::Wfi) => "WFI",
+                    Some(ISS_WFX::TI::Value::Wfe) => "WFE",
+                    Some(ISS_WFX::TI::Value::Wfit) => "WFIT",
+                    Some(ISS_WFX::TI::Value::Wfet) => "WFET",
+                    None => "Unknown",
+                };
+                write!(f, "\n            Instruction: {}", insn)
+            }
+            WatchpointLowerEL | WatchpointCurrentEL => {
+                let access = if self.is_write() { "Write" } else { "Read" };
+                write!(f, "\n            Write not Read (WnR): {}", access)?;
+                if self.abort().is_set(ISS_ABORT::CM) {
+                    write!(f, "\n            Cache Maintenance (CM): Set")?;
+                }
+                Ok(())
+            }
+            SError => self.fmt_serror(f),
+            TrappedFp64 => self.fmt_fp_exception(f),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Human readable syndrome, without the register name and raw value.
+#[rustfmt::skip]
+impl fmt::Display for Syndrome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Raw print of exception class.
+        write!(f, "      Exception Class         (EC) : {:#x}", self.ec())?;
+
+        // Exception class.
+        let ec_translation = match self.exception_class() {
+            Some(class) => class.description(),
+            None => "N/A",
+        };
+        writeln!(f, " - {}", ec_translation)?;
+
+        writeln!(f, "      Instr Length            (IL) : {} bit", self.instruction_length() * 8)?;
+
+        // Raw print of instruction specific syndrome.
+        write!(f, "      Instr Specific Syndrome (ISS): {:#x}", self.iss())?;
+
+        self.fmt_details(f)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::write_to::WriteTo, core::fmt::Write};
+
+    fn decode(esr: u64, buf: &mut [u8]) -> &str {
+        let mut w = WriteTo::new(buf);
+        write!(w, "{}", Syndrome::new(esr)).unwrap();
+        w.into_str().unwrap()
+    }
+
+    /// Store to an unmapped address at EL1, level 1 translation fault.
+    #[test_case]
+    fn data_abort_is_decoded() {
+        let syndrome = Syndrome::new(0x9600_0045);
+        assert_eq!(
+            syndrome.exception_class(),
+            Some(ExceptionClass::DataAbortCurrentEL)
+        );
+        assert_eq!(syndrome.instruction_length(), 4);
+        assert_eq!(
+            syndrome.fault_status(),
+            Some(FaultStatus::Translation { level: 1 })
+        );
+        assert!(syndrome.is_write());
+
+        let mut buf = [0u8; 1024];
+        let text = decode(0x9600_0045, &mut buf);
+        assert!(text.contains("Data Abort, current EL"));
+        assert!(text.contains("Translation fault, level 1"));
+        assert!(text.contains("Write not Read (WnR): Write"));
+    }
+
+    /// Instruction fetch from EL0 hitting a level 3 permission fault.
+    #[test_case]
+    fn instruction_abort_is_decoded() {
+        let syndrome = Syndrome::new(0x8200_000f);
+        assert_eq!(
+            syndrome.exception_class(),
+            Some(ExceptionClass::InstrAbortLowerEL)
+        );
+        assert_eq!(
+            syndrome.fault_status(),
+            Some(FaultStatus::Permission { level: 3 })
+        );
+    }
+
+    #[test_case]
+    fn fault_status_codes_are_decoded() {
+        assert_eq!(
+            FaultStatus::from_raw(0b10_1011),
+            FaultStatus::Translation { level: -1 }
+        );
+        assert_eq!(
+            FaultStatus::from_raw(0b00_1011),
+            FaultStatus::AccessFlag { level: 3 }
+        );
+        assert_eq!(FaultStatus::from_raw(0b10_0001), FaultStatus::Alignment);
+        assert_eq!(FaultStatus::from_raw(0b01_0000), FaultStatus::SyncExternal);
+        assert_eq!(
+            FaultStatus::from_raw(0b11_1111),
+            FaultStatus::Unknown(0b11_1111)
+        );
+    }
+
+    #[test_case]
+    fn call_immediates_are_decoded() {
+        let svc = Syndrome::new(0x5600_1337);
+        assert_eq!(svc.exception_class(), Some(ExceptionClass::Svc64));
+        assert_eq!(svc.immediate(), 0x1337);
+
+        let hvc = Syndrome::new(0x5a00_0042);
+        assert_eq!(hvc.exception_class(), Some(ExceptionClass::Hvc64));
+        assert_eq!(hvc.immediate(), 0x42);
+    }
+
+    #[test_case]
+    fn serror_severity_is_decoded() {
+        let severity = |esr| Syndrome::new(esr).serror_severity();
+        assert_eq!(severity(0xbe00_0011), Some(SErrorSeverity::Uncontainable));
+        assert_eq!(severity(0xbe00_0411), Some(SErrorSeverity::Unrecoverable));
+        assert_eq!(severity(0xbe00_0811), Some(SErrorSeverity::Restartable));
+        assert_eq!(severity(0xbe00_0c11), Some(SErrorSeverity::Recoverable));
+        assert_eq!(severity(0xbe00_1811), Some(SErrorSeverity::Corrected));
+        // IMPLEMENTATION DEFINED syndrome, reserved AET, not an SError.
+        assert_eq!(severity(0xbf00_0000), None);
+        assert_eq!(severity(0xbe00_1011), None);
+        assert_eq!(severity(0x9600_0045), None);
+
+        let mut buf = [0u8; 1024];
+        let text = decode(0xbe00_0811, &mut buf);
+        assert!(text.contains("Async Error Type (AET): Restartable"));
+    }
+
+    /// `mrs x3, ID_AA64PFR0_EL1` trapped.
+    #[test_case]
+    fn trapped_sysreg_access_is_decoded() {
+        let mut buf = [0u8; 1024];
+        let text = decode(0x6230_0069, &mut buf);
+        assert!(text.contains("Trapped MSR, MRS or system instruction"));
+        assert!(text.contains("mrs x3, S3_0_C0_C4_0"));
+    }
+
+    #[test_case]
+    fn unallocated_class_is_not_decoded() {
+        let syndrome = Syndrome::new(0x3f << 26);
+        assert_eq!(syndrome.exception_class(), None);
+        assert_eq!(syndrome.fault_status(), None);
+    }
+}
@@ -21,9 +21,12 @@ use {
     },
     aarch64_cpu::{
         asm::barrier,
-        registers::{ID_AA64MMFR0_EL1, SCTLR_EL1, TCR_EL1, TTBR0_EL1},
+        registers::{ID_AA64MMFR0_EL1, SCTLR_EL1, TCR_EL1, TTBR0_EL1, TTBR1_EL1},
+    },
+    core::{
+        arch::{asm, global_asm},
+        intrinsics::unlikely,
     },
-    core::intrinsics::unlikely,
     tock_registers::interfaces::{ReadWriteable, Readable, Writeable},
 };
 
@@ -36,6 +39,41 @@ pub(crate) mod translation_table;
 /// Memory Management Unit type.
 struct MemoryManagementUnit;
 
+/// Translation table the MMU is first enabled with, see [`boot_tables_init`].
+///
+/// A single lvl2 table: both halves of the address space resolve through it alike.
+#[repr(C, align(64))]
+struct BootTable([u64; 2]);
+
+// Replace the TTBR1 tables, called at its physical address while the identity map is in place.
+//
+// TTBR1 walks are disabled and the TLB flushed before the switch, so neither the old tables
+// nor a half-written TTBR1 can be used.
+global_asm!(
+    r#"
+.section .text.boot, "ax"
+.global __switch_ttbr1
+__switch_ttbr1:
+    dsb     ishst
+    mrs     x1, TCR_EL1
+    orr     x2, x1, #{epd1}
+    msr     TCR_EL1, x2
+    isb
+    tlbi    vmalle1
+    dsb     nsh
+    isb
+    msr     TTBR1_EL1, x0
+    msr     TCR_EL1, x1
+    isb
+    ret
+"#,
+    epd1 = const 1 << 23,
+);
+
+extern "C" {
+    fn __switch_ttbr1(phys_tables_base_addr: u64);
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
@@ -60,6 +98,9 @@ pub mod mair {
 
 static MMU: MemoryManagementUnit = MemoryManagementUnit;
 
+/// Filled by the boot core once .bss is cleared, used by every core until it reaches the top half.
+static mut BOOT_TABLE: BootTable = BootTable([0; 2]);
+
 //--------------------------------------------------------------------------------------------------
 // Private Implementations
 //--------------------------------------------------------------------------------------------------
@@ -94,8 +135,10 @@ impl MemoryManagementUnit {
     }
 
     /// Configure various settings of stage 1 of the EL1 translation regime.
+    ///
+    /// Both halves are as large as the kernel's address space.
     fn configure_translation_control(&self) {
-        let t0sz = (64 - platform::memory::mmu::KernelVirtAddrSpace::SIZE_SHIFT) as u64;
+        let tsz = (64 - platform::memory::mmu::KernelVirtAddrSpace::SIZE_SHIFT) as u64;
 
         TCR_EL1.write(
             TCR_EL1::TBI0::Used
@@ -106,8 +149,13 @@ impl MemoryManagementUnit {
                 + TCR_EL1::IRGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
                 + TCR_EL1::EPD0::EnableTTBR0Walks
                 + TCR_EL1::A1::TTBR0 // TTBR0 defines the ASID
-                + TCR_EL1::T0SZ.val(t0sz)
-                + TCR_EL1::EPD1::DisableTTBR1Walks,
+                + TCR_EL1::T0SZ.val(tsz)
+                + TCR_EL1::TG1::KiB_64
+                + TCR_EL1::SH1::Inner
+                + TCR_EL1::ORGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+                + TCR_EL1::IRGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+                + TCR_EL1::EPD1::EnableTTBR1Walks
+                + TCR_EL1::T1SZ.val(tsz),
         );
     }
 }
@@ -121,6 +169,35 @@ pub fn mmu() -> &'static impl interface::MMU {
     &MMU
 }
 
+/// Fill the boot translation table and return its physical address.
+///
+/// The table maps the low 1 GiB of physical memory in two 512 MiB blocks of normal memory, only
+/// the first one executable. Installed for both halves, it keeps the boot code running at its
+/// physical address and makes the kernel reachable at its link address, until the kernel
+/// tables take over. The device tree, which the firmware may place above the first block, is
+/// read through it too.
+///
+/// # Safety
+///
+/// - To be called once by the boot core, with the MMU off and after .bss is cleared.
+#[link_section = ".text.boot"]
+pub unsafe fn boot_tables_init() -> Address<Physical> {
+    let table = &mut *core::ptr::addr_of_mut!(BOOT_TABLE);
+    table.0[0] = translation_table::boot_block_descriptor(0, false);
+    table.0[1] = translation_table::boot_block_descriptor(Granule512MiB::SIZE, true);
+
+    boot_tables_phys_addr()
+}
+
+/// Physical address of the boot translation table.
+///
+/// Only valid with the MMU off, where addresses come out physical.
+#[link_section = ".text.boot"]
+#[inline(always)]
+pub fn boot_tables_phys_addr() -> Address<Physical> {
+    Address::new(core::ptr::addr_of!(BOOT_TABLE) as usize)
+}
+
 //------------------------------------------------------------------------------
 // OS Interface Code
 //------------------------------------------------------------------------------
@@ -128,7 +205,8 @@ pub fn mmu() -> &'static impl interface::MMU {
 impl interface::MMU for MemoryManagementUnit {
     unsafe fn enable_mmu_and_caching(
         &self,
-        phys_tables_base_addr: Address<Physical>,
+        phys_identity_tables_base_addr: Address<Physical>,
+        phys_kernel_tables_base_addr: Address<Physical>,
     ) -> Result<(), MMUEnableError> {
         if unlikely(self.is_enabled()) {
             return Err(MMUEnableError::AlreadyEnabled);
@@ -149,8 +227,9 @@ impl interface::MMU for MemoryManagementUnit {
         //     .populate_translation_table_entries()
         //     .map_err(|err| MMUEnableError::Other { err })?;
 
-        // Set the "Translation Table Base Register".
-        TTBR0_EL1.set_baddr(phys_tables_base_addr.as_usize() as u64);
+        // Set the "Translation Table Base Registers".
+        TTBR0_EL1.set_baddr(phys_identity_tables_base_addr.as_usize() as u64);
+        TTBR1_EL1.set_baddr(phys_kernel_tables_base_addr.as_usize() as u64);
 
         self.configure_translation_control();
 
@@ -168,6 +247,24 @@ impl interface::MMU for MemoryManagementUnit {
         Ok(())
     }
 
+    unsafe fn switch_kernel_tables(&self, phys_tables_base_addr: Address<Physical>) {
+        // The switch can't run from the tables it replaces, go through the identity map.
+        let switch_ttbr1 = platform::memory::mmu::kernel_virt_to_phys(Address::new(
+            __switch_ttbr1 as *const () as usize,
+        ));
+        let switch_ttbr1: unsafe extern "C" fn(u64) = core::mem::transmute(switch_ttbr1.as_usize());
+
+        switch_ttbr1(phys_tables_base_addr.as_usize() as u64);
+    }
+
+    unsafe fn disable_identity_map(&self) {
+        TCR_EL1.modify(TCR_EL1::EPD0::DisableTTBR0Walks);
+        barrier::isb(barrier::SY);
+
+        // Drop the identity entries this core has cached.
+        asm!("tlbi vmalle1", "dsb nsh", "isb", options(nostack));
+    }
+
     #[inline(always)]
     fn is_enabled(&self) -> bool {
         SCTLR_EL1.matches_all(SCTLR_EL1::M::Enable)
@@ -8,6 +8,7 @@ use {
         },
         platform,
     },
+    aarch64_cpu::asm::barrier,
     core::convert,
     tock_registers::{
         interfaces::{Readable, Writeable},
@@ -131,9 +132,12 @@ trait BaseAddr {
 
 /// Big monolithic struct for storing the translation tables. Individual levels must be 64 KiB
 /// aligned, so the lvl3 is put first.
+///
+/// With `START_FROM_TOP`, the tables cover the top of the address space (TTBR1) instead of
+/// the bottom (TTBR0).
 #[repr(C)]
 #[repr(align(65536))]
-pub struct FixedSizeTranslationTable<const NUM_TABLES: usize> {
+pub struct FixedSizeTranslationTable<const NUM_TABLES: usize, const START_FROM_TOP: bool> {
     /// Page descriptors, covering 64 KiB windows per entry.
     lvl3: [[PageDescriptor; 8192]; NUM_TABLES],
 
@@ -152,9 +156,9 @@ pub struct FixedSizeTranslationTable<const NUM_TABLES: usize> {
 //--------------------------------------------------------------------------------------------------
 
 impl<T, const N: usize> BaseAddr for [T; N] {
-    // The binary is still identity mapped, so we don't need to convert here.
+    // Tables are statics of the kernel binary, which runs in the high half.
     fn phys_start_addr(&self) -> Address<Physical> {
-        Address::new(self as *const _ as usize)
+        platform::memory::mmu::kernel_virt_to_phys(Address::new(self as *const _ as usize))
     }
 
     fn base_addr_u64(&self) -> u64 {
@@ -268,15 +272,46 @@ impl convert::From<AttributeFields>
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+/// Create a lvl2 block descriptor, mapping 512 MiB of cacheable RW memory at `phys_addr`.
+///
+/// Used for the boot tables, which are filled with the MMU off, so only plain constants here.
+pub(super) const fn boot_block_descriptor(phys_addr: usize, execute_never: bool) -> u64 {
+    let pxn = if execute_never {
+        STAGE1_PAGE_DESCRIPTOR::PXN::NeverExecute.value
+    } else {
+        STAGE1_PAGE_DESCRIPTOR::PXN::Execute.value
+    };
+
+    (phys_addr & !Granule512MiB::MASK) as u64
+        | STAGE1_PAGE_DESCRIPTOR::UXN::NeverExecute.value
+        | pxn
+        | STAGE1_PAGE_DESCRIPTOR::AF::Accessed.value
+        | STAGE1_PAGE_DESCRIPTOR::SH::InnerShareable.value
+        | STAGE1_PAGE_DESCRIPTOR::AP::RW_EL1.value
+        | (mair::attr::NORMAL << 2) // AttrIndx
+        | STAGE1_TABLE_DESCRIPTOR::TYPE::Block.value
+        | STAGE1_PAGE_DESCRIPTOR::VALID::True.value
+}
+
 impl<const AS_SIZE: usize> memory::mmu::AssociatedTranslationTable
     for memory::mmu::AddressSpace<AS_SIZE>
 where
     [u8; Self::SIZE >> Granule512MiB::SHIFT]: Sized,
 {
-    type TableStartFromBottom = FixedSizeTranslationTable<{ Self::SIZE >> Granule512MiB::SHIFT }>;
+    type TableStartFromTop =
+        FixedSizeTranslationTable<{ Self::SIZE >> Granule512MiB::SHIFT }, true>;
+
+    type TableStartFromBottom =
+        FixedSizeTranslationTable<{ Self::SIZE >> Granule512MiB::SHIFT }, false>;
 }
 
-impl<const NUM_TABLES: usize> FixedSizeTranslationTable<NUM_TABLES> {
+impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
+    FixedSizeTranslationTable<NUM_TABLES, START_FROM_TOP>
+{
+    // Lowest address covered when the tables start from the top.
+    const START_FROM_TOP_OFFSET: Address<Virtual> =
+        Address::new((usize::MAX - (Granule512MiB::SIZE * NUM_TABLES)) + 1);
+
     /// Create an instance.
     #[allow(clippy::assertions_on_constants)]
     pub const fn new() -> Self {
@@ -298,7 +333,14 @@ impl<const NUM_TABLES: usize> FixedSizeTranslationTable<NUM_TABLES> {
         &self,
         virt_page_addr: PageAddress<Virtual>,
     ) -> Result<(usize, usize), &'static str> {
-        let addr = virt_page_addr.into_inner().as_usize();
+        let mut addr = virt_page_addr.into_inner().as_usize();
+
+        if START_FROM_TOP {
+            addr = addr
+                .checked_sub(Self::START_FROM_TOP_OFFSET.as_usize())
+                .ok_or("Virtual page is out of bounds of translation table")?;
+        }
+
         let lvl2_index = addr >> Granule512MiB::SHIFT;
         let lvl3_index = (addr & Granule512MiB::MASK) >> Granule64KiB::SHIFT;
 
@@ -334,8 +376,9 @@ impl<const NUM_TABLES: usize> FixedSizeTranslationTable<NUM_TABLES> {
 // OS Interface Code
 //------------------------------------------------------------------------------
 
-impl<const NUM_TABLES: usize> memory::mmu::translation_table::interface::TranslationTable
-    for FixedSizeTranslationTable<NUM_TABLES>
+impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
+    memory::mmu::translation_table::interface::TranslationTable
+    for FixedSizeTranslationTable<NUM_TABLES, START_FROM_TOP>
 {
     /// Iterates over all static translation table entries and fills them at once.
     ///
@@ -397,6 +440,12 @@ impl<const NUM_TABLES: usize> memory::mmu::translation_table::interface::Transla
             return Err("Tried to map outside of physical address space");
         }
 
+        if attr.mem_attributes != MemAttributes::Device
+            && !platform::memory::phys_ram_contains(phys_region)
+        {
+            return Err("Tried to map memory outside of RAM as normal memory");
+        }
+
         #[allow(clippy::useless_conversion)]
         let iter = phys_region.into_iter().zip(virt_region.into_iter());
         for (phys_page_addr, virt_page_addr) in iter {
@@ -406,6 +455,11 @@ impl<const NUM_TABLES: usize> memory::mmu::translation_table::interface::Transla
             self.set_page_descriptor_from_page_addr(virt_page, &new_desc)?;
         }
 
+        // With the MMU already on, the table walker must see the new descriptors before
+        // anything accesses the region. Only invalid entries were replaced, no TLB flush.
+        barrier::dsb(barrier::ISHST);
+        barrier::isb(barrier::SY);
+
         Ok(())
     }
 }
@@ -415,7 +469,7 @@ impl<const NUM_TABLES: usize> memory::mmu::translation_table::interface::Transla
 //--------------------------------------------------------------------------------------------------
 
 #[cfg(test)]
-pub type MinSizeTranslationTable = FixedSizeTranslationTable<1>;
+pub type MinSizeTranslationTable = FixedSizeTranslationTable<1, false>;
 
 #[cfg(test)]
 mod tests {
@@ -438,4 +492,16 @@ mod tests {
             core::mem::size_of::<u64>()
         );
     }
+
+    /// Tables starting from the top only cover the top of the address space.
+    #[test_case]
+    fn top_half_table_bounds() {
+        let tables = FixedSizeTranslationTable::<1, true>::new();
+
+        let top = PageAddress::from(usize::MAX - Granule512MiB::SIZE + 1);
+        assert_eq!(tables.lvl2_lvl3_index_from_page_addr(top), Ok((0, 0)));
+
+        let bottom = PageAddress::from(0);
+        assert!(tables.lvl2_lvl3_index_from_page_addr(bottom).is_err());
+    }
 }
@@ -7,6 +7,7 @@
 
 mod addr;
 pub mod mmu;
+pub mod probe;
 
 // pub use addr::{PhysAddr, VirtAddr};
 
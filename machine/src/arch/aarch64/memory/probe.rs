@@ -0,0 +1,114 @@
+/*
+ * SPDX-License-Identifier: BlueOak-1.0.0
+ * Copyright (c) Berkus Decker <berkus+vesper@metta.systems>
+ */
+
+//! Architectural memory probing.
+//!
+//! Single loads and stores recorded in the exception fixup table, a fault on them comes
+//! back as `None` or `false` instead of taking the kernel down.
+
+use {crate::arch::aarch64::exception::extable::asm_extable, core::arch::asm};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Define a probing load of one access size.
+macro_rules! probe_read {
+    ($(#[$attr:meta])* $name:ident, $ty:ty, $insn:literal) => {
+        $(#[$attr])*
+        ///
+        /// # Safety
+        ///
+        /// - The address must be aligned to the access size.
+        /// - Reads may have side effects on device memory.
+        pub unsafe fn $name(addr: usize) -> Option<$ty> {
+            let value: $ty;
+            let failed: u64;
+            asm!(
+                "mov {failed}, #0",
+                concat!("2: ", $insn),
+                "3:",
+                ".pushsection .text.fixup, \"ax\"",
+                "4: mov {failed}, #1",
+                "   b 3b",
+                ".popsection",
+                asm_extable!("2b", "4b"),
+                addr = in(reg) addr,
+                value = out(reg) value,
+                failed = out(reg) failed,
+                options(nostack, preserves_flags)
+            );
+            (failed == 0).then_some(value)
+        }
+    };
+}
+
+/// Define a probing store of one access size.
+macro_rules! probe_write {
+    ($(#[$attr:meta])* $name:ident, $ty:ty, $insn:literal) => {
+        $(#[$attr])*
+        ///
+        /// # Safety
+        ///
+        /// - The address must be aligned to the access size.
+        /// - Anything at all may live at the address, it is overwritten.
+        pub unsafe fn $name(addr: usize, value: $ty) -> bool {
+            let failed: u64;
+            asm!(
+                "mov {failed}, #0",
+                concat!("2: ", $insn),
+                "3:",
+                ".pushsection .text.fixup, \"ax\"",
+                "4: mov {failed}, #1",
+                "   b 3b",
+                ".popsection",
+                asm_extable!("2b", "4b"),
+                addr = in(reg) addr,
+                value = in(reg) value,
+                failed = out(reg) failed,
+                options(nostack, preserves_flags)
+            );
+            failed == 0
+        }
+    };
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+probe_read! {
+    /// Read a byte, `None` if the access faults.
+    read_u8, u8, "ldrb {value:w}, [{addr}]"
+}
+probe_read! {
+    /// Read a halfword, `None` if the access faults.
+    read_u16, u16, "ldrh {value:w}, [{addr}]"
+}
+probe_read! {
+    /// Read a word, `None` if the access faults.
+    read_u32, u32, "ldr {value:w}, [{addr}]"
+}
+probe_read! {
+    /// Read a doubleword, `None` if the access faults.
+    read_u64, u64, "ldr {value}, [{addr}]"
+}
+
+probe_write! {
+    /// Write a byte, false if the access faults.
+    write_u8, u8, "strb {value:w}, [{addr}]"
+}
+probe_write! {
+    /// Write a halfword, false if the access faults.
+    write_u16, u16, "strh {value:w}, [{addr}]"
+}
+probe_write! {
+    /// Write a word, false if the access faults.
+    write_u32, u32, "str {value:w}, [{addr}]"
+}
+probe_write! {
+    /// Write a doubleword, false if the access faults.
+    write_u64, u64, "str {value}, [{addr}]"
+}
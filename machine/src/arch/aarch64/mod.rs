@@ -5,6 +5,7 @@
 
 //! Implementation of aarch64 kernel functions.
 
+pub mod backtrace;
 pub mod cpu;
 pub mod exception;
 pub mod memory;
@@ -20,7 +20,7 @@ use {
         time::Duration,
     },
     once_cell::unsync::Lazy,
-    tock_registers::interfaces::Readable,
+    tock_registers::interfaces::{Readable, Writeable},
 };
 
 //--------------------------------------------------------------------------------------------------
@@ -37,8 +37,8 @@ struct GenericTimerCounterValue(u64);
 //--------------------------------------------------------------------------------------------------
 
 // @todo use InitStateLock here
-static ARCH_TIMER_COUNTER_FREQUENCY: synchronization::IRQSafeNullLock<Lazy<NonZeroU32>> =
-    synchronization::IRQSafeNullLock::new(Lazy::new(|| {
+static ARCH_TIMER_COUNTER_FREQUENCY: synchronization::IRQSafeSpinLock<Lazy<NonZeroU32>> =
+    synchronization::IRQSafeSpinLock::new(Lazy::new(|| {
         NonZeroU32::try_from(CNTFRQ_EL0.get() as u32).unwrap()
     }));
 
@@ -142,6 +142,16 @@ pub fn uptime() -> Duration {
     read_cntpct().into()
 }
 
+/// The raw counter value, cheaper to take than [`uptime`] when measuring short intervals.
+pub fn ticks() -> u64 {
+    read_cntpct().0
+}
+
+/// Convert a number of counter ticks to a duration.
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    GenericTimerCounterValue(ticks).into()
+}
+
 /// Spin for a given duration.
 pub fn spin_for(duration: Duration) {
     let curr_counter_value = read_cntpct();
@@ -160,3 +170,21 @@ pub fn spin_for(duration: Duration) {
     // Read CNTPCT_EL0 directly to avoid the ISB that is part of [`read_cntpct`].
     while GenericTimerCounterValue(CNTPCT_EL0.get()) < counter_value_target {}
 }
+
+/// Raise the timer interrupt once the uptime reaches `deadline`.
+///
+/// Programs the executing core's EL1 physical timer, its interrupt is CNTPNSIRQ.
+pub fn set_timer_deadline(deadline: Duration) {
+    let compare = GenericTimerCounterValue::try_from(deadline)
+        .unwrap_or(GenericTimerCounterValue::MAX)
+        .0;
+
+    // Rounded up, the deadline must have passed once the interrupt fires.
+    CNTP_CVAL_EL0.set(compare.saturating_add(1));
+    CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::SET + CNTP_CTL_EL0::IMASK::CLEAR);
+}
+
+/// Stop the executing core's timer, deasserting its interrupt.
+pub fn stop_timer() {
+    CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::CLEAR);
+}
@@ -0,0 +1,118 @@
+/*
+ * SPDX-License-Identifier: BlueOak-1.0.0
+ * Copyright (c) Berkus Decker <berkus+vesper@metta.systems>
+ */
+
+//! Symbolized stack backtraces.
+//!
+//! Frames are unwound along the frame record chain, which is only followed within the
+//! executing core's exception and kernel stacks as registered in [`crate::cpu::stack`].
+//! Without registered stacks there is nothing to walk and only the starting address is
+//! printed.
+
+#[cfg(target_arch = "aarch64")]
+use crate::arch::aarch64::backtrace as arch_backtrace;
+use {
+    crate::{cpu::stack, symbols},
+    core::fmt,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A backtrace to be printed, unwound when it is taken.
+pub struct Backtrace {
+    frames: [usize; MAX_FRAMES],
+    len: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Frames past this are not recorded, a deeper chain is most likely corrupt.
+const MAX_FRAMES: usize = 32;
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Backtrace {
+    /// Record up to [`MAX_FRAMES`] return addresses, `pc` first if it isn't a call site in
+    /// the chain.
+    fn unwind(pc: Option<usize>, fp: usize) -> Self {
+        let stacks = [stack::exception_stack(), stack::kernel_stack()];
+        let mut this = Self {
+            frames: [0; MAX_FRAMES],
+            len: 0,
+        };
+        for (slot, addr) in this.frames.iter_mut().zip(
+            pc.into_iter()
+                .chain(arch_backtrace::FrameRecords::new(fp, stacks)),
+        ) {
+            *slot = addr;
+            this.len += 1;
+        }
+        this
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Backtrace {
+    /// Backtrace of the caller.
+    ///
+    /// The chain is walked right here, while the frame records it starts from are still live.
+    #[inline(never)]
+    pub fn capture() -> Self {
+        Self::unwind(None, arch_backtrace::frame_pointer())
+    }
+
+    /// Backtrace of an interrupted context, starting at `pc` with frame pointer `fp`.
+    pub fn from_context(pc: usize, fp: usize) -> Self {
+        Self::unwind(Some(pc), fp)
+    }
+}
+
+/// One line per frame, as `#N 0xaddress symbol+0xoffset`.
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Backtrace:")?;
+        for (index, &addr) in self.frames[..self.len].iter().enumerate() {
+            write!(f, "      #{:<2} {:#018x} ", index, addr)?;
+            match symbols::lookup(addr) {
+                Some(symbol) => writeln!(f, "{}", symbol)?,
+                None => writeln!(f, "<unknown>")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::write_to};
+
+    #[inline(never)]
+    fn inner_frame() -> Backtrace {
+        Backtrace::capture()
+    }
+
+    /// The chain reaches back through the caller into the test runner, even though the
+    /// capturing frame is gone by the time it is printed.
+    #[test_case]
+    fn capture_walks_callers() {
+        let mut buf = [0u8; 2048];
+        let text = write_to::show(&mut buf, format_args!("{}", inner_frame())).unwrap();
+        assert!(text.contains("#0 "));
+        assert!(text.contains("#1 "));
+        assert!(text.contains("capture_walks_callers"));
+    }
+}
@@ -64,20 +64,29 @@ pub mod interface {
 // Global instances
 //--------------------------------------------------------------------------------------------------
 
-static CONSOLE: InitStateLock<&'static (dyn interface::All + Sync)> =
-    InitStateLock::new(&null_console::NULL_CONSOLE);
+static CONSOLE: RwSpinLock<&'static (dyn interface::All + Sync)> =
+    RwSpinLock::new(&null_console::NULL_CONSOLE);
 
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
-use crate::synchronization::{interface::ReadWriteEx, InitStateLock};
+use crate::synchronization::{interface::ReadWriteEx, RwSpinLock};
 
-/// Register a new console.
+/// Register a new console, replacing the current one.
 pub fn register_console(new_console: &'static (dyn interface::All + Sync)) {
     CONSOLE.write(|con| *con = new_console);
 }
 
+/// Unregister a console, falling back to the null console if it is the current one.
+pub fn unregister_console(old_console: &'static (dyn interface::All + Sync)) {
+    CONSOLE.write(|con| {
+        if core::ptr::addr_eq(*con, old_console) {
+            *con = &null_console::NULL_CONSOLE;
+        }
+    });
+}
+
 /// Return a reference to the currently registered console.
 ///
 /// This is the global console used by all printing macros.
@@ -8,3 +8,95 @@
 // #[cfg(target_arch = "aarch64")]
 // #[path = "../arch/aarch64/cpu/boot.rs"]
 // mod arch_boot;
+
+use {
+    crate::{
+        memory::{Address, Physical},
+        platform::BcmHost,
+    },
+    core::fmt,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Information collected by the early boot code before any of it could be clobbered.
+///
+/// Handed to the kernel's entry function by the `entry!` macro.
+#[derive(Copy, Clone, Debug)]
+pub struct BootInfo {
+    /// Physical address of the device tree blob, as passed in x0 by the firmware or QEMU `-dtb`.
+    dtb_phys_addr: Option<Address<Physical>>,
+
+    /// Id of the core that performed the boot.
+    boot_core_id: u64,
+
+    /// Exception level the core was in when it entered the kernel image.
+    entry_el: u8,
+
+    /// Physical address the kernel image was actually loaded at.
+    load_addr: Address<Physical>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl BootInfo {
+    /// Create an instance from the raw values captured at the entry point.
+    ///
+    /// A zero DTB address means no device tree was passed in.
+    pub const fn new(dtb_phys_addr: u64, boot_core_id: u64, entry_el: u8, load_addr: u64) -> Self {
+        Self {
+            dtb_phys_addr: if dtb_phys_addr == 0 {
+                None
+            } else {
+                Some(Address::new(dtb_phys_addr as usize))
+            },
+            boot_core_id,
+            entry_el,
+            load_addr: Address::new(load_addr as usize),
+        }
+    }
+
+    /// Return the physical address of the device tree blob, if there is one.
+    pub const fn dtb_phys_addr(&self) -> Option<Address<Physical>> {
+        self.dtb_phys_addr
+    }
+
+    /// Return the id of the boot core.
+    pub const fn boot_core_id(&self) -> u64 {
+        self.boot_core_id
+    }
+
+    /// Return the exception level the kernel was entered at.
+    pub const fn entry_el(&self) -> u8 {
+        self.entry_el
+    }
+
+    /// Return the physical load address of the kernel image.
+    pub const fn load_addr(&self) -> Address<Physical> {
+        self.load_addr
+    }
+
+    /// Return how far from its link-time load address the kernel image was loaded.
+    pub const fn load_offset(&self) -> isize {
+        self.load_addr.as_usize() as isize - BcmHost::kernel_load_address() as isize
+    }
+}
+
+/// Human readable print of the boot information.
+impl fmt::Display for BootInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.dtb_phys_addr {
+            Some(addr) => write!(f, "DTB at {}", addr)?,
+            None => write!(f, "no DTB")?,
+        }
+        write!(
+            f,
+            ", boot core {}, entered at EL{}, loaded at {}",
+            self.boot_core_id, self.entry_el, self.load_addr
+        )
+    }
+}
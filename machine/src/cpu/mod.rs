@@ -7,12 +7,17 @@
 #[cfg(target_arch = "aarch64")]
 use crate::arch::aarch64::cpu as arch_cpu;
 
+pub mod boot;
+pub mod per_cpu;
 pub mod smp;
+pub mod stack;
 
 //--------------------------------------------------------------------------------------------------
 // Architectural Public Reexports
 //--------------------------------------------------------------------------------------------------
-pub use arch_cpu::{endless_sleep, nop};
+pub use arch_cpu::{endless_sleep, nop, wait_for_interrupt};
+
+pub use boot::BootInfo;
 
 // #[cfg(feature = "test_build")]
 // pub use arch_cpu::{qemu_exit_failure, qemu_exit_success};
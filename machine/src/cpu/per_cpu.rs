@@ -0,0 +1,229 @@
+/*
+ * SPDX-License-Identifier: BlueOak-1.0.0
+ * Copyright (c) Berkus Decker <berkus+vesper@metta.systems>
+ */
+
+//! Per-CPU data.
+//!
+//! Statics declared with [`per_cpu!`](crate::per_cpu) are linked into the `.percpu` section,
+//! which serves as a template. The linker reserves one area of the same size per core, the
+//! boot code fills every area from the template and points each core at its own area.
+//!
+//! A core only ever touches its own copy through [`PerCpu::with`], which runs with IRQs
+//! masked. Since there is no preemption without IRQs, the code can neither migrate to
+//! another core nor be re-entered by a handler using the same copy halfway through.
+
+#[cfg(target_arch = "aarch64")]
+use crate::arch::aarch64::cpu::per_cpu as arch_per_cpu;
+use {
+    crate::{exception::asynchronous::exec_with_irq_masked, platform::cpu::MAX_CORES},
+    core::{
+        cell::{Cell, UnsafeCell},
+        slice,
+    },
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A value with one instance per core.
+///
+/// Use [`per_cpu!`](crate::per_cpu) to declare one, a `PerCpu` outside of the `.percpu`
+/// section is not replicated.
+#[repr(transparent)]
+pub struct PerCpu<T> {
+    value: UnsafeCell<T>,
+}
+
+/// Declare a static with one instance per core.
+///
+/// ```ignore
+/// per_cpu! {
+///     static IRQ_NESTING: Cell<usize> = Cell::new(0);
+/// }
+///
+/// IRQ_NESTING.set(IRQ_NESTING.get() + 1);
+/// ```
+#[macro_export]
+macro_rules! per_cpu {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;)+) => {
+        $(
+            $(#[$attr])*
+            #[link_section = ".percpu"]
+            $vis static $name: $crate::cpu::per_cpu::PerCpu<$ty> =
+                $crate::cpu::per_cpu::PerCpu::new($init);
+        )+
+    };
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+extern "Rust" {
+    // Template section and the per-core areas, provided by the linker script.
+    //
+    // Only addresses inside the image are used: this code runs before the MMU is on, away
+    // from the link addresses, where addresses are PC-relative and absolute symbols like
+    // section sizes would come out shifted.
+    static __PERCPU_START: UnsafeCell<()>;
+    static __PERCPU_END: UnsafeCell<()>;
+    static __PERCPU_AREAS_START: UnsafeCell<()>;
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Size of the template, and of each core's area.
+fn area_size() -> usize {
+    unsafe { __PERCPU_END.get() as usize - __PERCPU_START.get() as usize }
+}
+
+/// Offset from the template to the given core's area.
+fn area_offset(core: usize) -> usize {
+    unsafe {
+        __PERCPU_AREAS_START.get() as usize + core * area_size() - __PERCPU_START.get() as usize
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+// Each core only reaches its own copy, with IRQs masked. Copies of other cores are only
+// handed out for `T: Sync`.
+unsafe impl<T: Send> Sync for PerCpu<T> {}
+
+impl<T> PerCpu<T> {
+    /// Create the template value, copied to each core at boot.
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Pointer to the copy at the given offset from the template.
+    fn ptr_at(&self, offset: usize) -> *mut T {
+        self.value.get().cast::<u8>().wrapping_add(offset).cast()
+    }
+
+    /// Run `f` on the executing core's copy, with IRQs masked.
+    pub fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        exec_with_irq_masked(|| f(unsafe { &*self.ptr_at(arch_per_cpu::offset()) }))
+    }
+
+    /// The copy belonging to `core`.
+    ///
+    /// # Panics
+    ///
+    /// If `core` is out of range.
+    pub fn remote(&self, core: usize) -> &T
+    where
+        T: Sync,
+    {
+        assert!(core < MAX_CORES, "Core id out of range");
+        unsafe { &*self.ptr_at(area_offset(core)) }
+    }
+
+    /// Iterate over the copies of all cores.
+    pub fn iter(&self) -> impl Iterator<Item = &T>
+    where
+        T: Sync,
+    {
+        (0..MAX_CORES).map(|core| self.remote(core))
+    }
+}
+
+impl<T: Copy> PerCpu<Cell<T>> {
+    /// Value on the executing core.
+    pub fn get(&self) -> T {
+        self.with(Cell::get)
+    }
+
+    /// Set the value on the executing core.
+    pub fn set(&self, value: T) {
+        self.with(|cell| cell.set(value));
+    }
+}
+
+/// Fill every core's area from the template.
+///
+/// # Safety
+///
+/// - Must be called once by the boot core, after .bss is cleared and before any other
+///   per-CPU access.
+pub unsafe fn init_areas() {
+    // Copied in u64s, like the .bss clearing: this runs with the MMU off, where unaligned
+    // accesses fault. The linker script keeps the template 64 bytes aligned.
+    let template = slice::from_raw_parts(__PERCPU_START.get() as *const u64, area_size() / 8);
+
+    for core in 0..MAX_CORES {
+        let area_start = __PERCPU_START.get() as usize + area_offset(core);
+        let area = slice::from_raw_parts_mut(area_start as *mut u64, template.len());
+        for (dst, src) in area.iter_mut().zip(template) {
+            *dst = *src;
+        }
+    }
+}
+
+/// Point the executing core at its per-CPU area.
+///
+/// # Safety
+///
+/// - [`init_areas`] must have run.
+/// - `core` must be the executing core's id.
+pub unsafe fn init_core(core: usize) {
+    arch_per_cpu::set_offset(area_offset(core));
+}
+
+/// Run the executing core on the template itself.
+///
+/// For single-core binaries like chainboot, which have no per-core areas.
+///
+/// # Safety
+///
+/// - No other core may use per-CPU data.
+pub unsafe fn use_template() {
+    arch_per_cpu::set_offset(0);
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        core::sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    per_cpu! {
+        static COUNTER: Cell<usize> = Cell::new(7);
+        static SHARED: AtomicUsize = AtomicUsize::new(0);
+    }
+
+    /// Each core starts from the template value and writes only its own copy.
+    #[test_case]
+    fn per_cpu_copies_are_separate() {
+        assert_eq!(COUNTER.get(), 7);
+        COUNTER.set(8);
+        assert_eq!(COUNTER.get(), 8);
+
+        let core = crate::cpu::smp::core_id() as usize;
+        SHARED.with(|v| v.store(42, Ordering::Relaxed));
+        for (i, copy) in SHARED.iter().enumerate() {
+            let expected = if i == core { 42 } else { 0 };
+            assert_eq!(copy.load(Ordering::Relaxed), expected);
+        }
+    }
+
+    /// The executing core does not run on the template itself.
+    #[test_case]
+    fn per_cpu_offset_is_set() {
+        let core = crate::cpu::smp::core_id() as usize;
+        assert_eq!(arch_per_cpu::offset(), area_offset(core));
+    }
+}
@@ -6,8 +6,125 @@
 
 #[cfg(target_arch = "aarch64")]
 use crate::arch::aarch64::cpu::smp as arch_smp;
+use {
+    crate::{
+        memory::mmu::translation_table::interface::TranslationTable, platform, state,
+        synchronization::interface::Mutex, time,
+    },
+    core::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    },
+};
 
 //--------------------------------------------------------------------------------------------------
 // Architectural Public Reexports
 //--------------------------------------------------------------------------------------------------
 pub use arch_smp::core_id;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// How long to wait for a released core to report in.
+const START_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Entry function for each secondary core, as a plain address.
+static SECONDARY_ENTRY: [AtomicUsize; platform::cpu::MAX_CORES] =
+    [const { AtomicUsize::new(0) }; platform::cpu::MAX_CORES];
+
+/// Number of cores running kernel code, the boot core included.
+static CORES_ONLINE: AtomicUsize = AtomicUsize::new(1);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Start a secondary core and wait for it to report in.
+///
+/// # Safety
+///
+/// - See [`start_secondary`].
+unsafe fn start_secondary_and_wait(core: u64, entry: fn() -> !) -> Result<(), &'static str> {
+    let expected = cores_online() + 1;
+    start_secondary(core, entry)?;
+
+    let deadline = time::time_manager().uptime() + START_TIMEOUT;
+    while cores_online() < expected {
+        if time::time_manager().uptime() > deadline {
+            return Err("Secondary core did not come up");
+        }
+        crate::cpu::nop();
+    }
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Start a secondary core, making it run `entry` once it is set up.
+///
+/// The core goes through the same exception level switch, exception vector and MMU setup
+/// as the boot core before `entry` is called. Returns once the core has been released,
+/// see [`cores_online`] to know when it actually came up.
+///
+/// # Safety
+///
+/// - Kernel translation tables must be set up, see `memory::mmu::kernel_map_binary()`.
+/// - The core must not have been started before.
+pub unsafe fn start_secondary(core: u64, entry: fn() -> !) -> Result<(), &'static str> {
+    if !platform::cpu::core_ids().any(|id| id == core) {
+        return Err("No such core");
+    }
+    if core == core_id() {
+        return Err("Core is already running");
+    }
+
+    SECONDARY_ENTRY[core as usize].store(entry as usize, Ordering::Release);
+
+    let tables = platform::memory::mmu::kernel_translation_tables()
+        .lock(|tables| tables.phys_base_address());
+    arch_smp::start_core(core, tables)
+}
+
+/// Start all secondary cores one by one, then switch the kernel to `MultiCoreMain`.
+///
+/// Cores are brought up sequentially, so their early init never runs concurrently. A core
+/// failing to start doesn't hold back the others, the first error is returned once all were
+/// tried. The switch happens as soon as any secondary core is online.
+///
+/// # Safety
+///
+/// - See [`start_secondary`].
+/// - Must be called in the `SingleCoreMain` state.
+pub unsafe fn start_secondary_cores(entry: fn() -> !) -> Result<(), &'static str> {
+    let mut result = Ok(());
+
+    for core in platform::cpu::core_ids().filter(|&core| core != core_id()) {
+        if let Err(e) = start_secondary_and_wait(core, entry) {
+            result = result.and(Err(e));
+        }
+    }
+
+    if cores_online() > 1 {
+        state::state_manager().transition_to_multi_core_main();
+    }
+    result
+}
+
+/// Number of cores running kernel code.
+pub fn cores_online() -> usize {
+    CORES_ONLINE.load(Ordering::Acquire)
+}
+
+/// Called by the architectural code on a secondary core, once it runs with the MMU on.
+pub(crate) fn secondary_main(core: u64) -> ! {
+    let entry = SECONDARY_ENTRY[core as usize].load(Ordering::Acquire);
+
+    CORES_ONLINE.fetch_add(1, Ordering::Release);
+
+    // Safety: stored by start_secondary() from a valid function pointer.
+    let entry: fn() -> ! = unsafe { core::mem::transmute(entry) };
+    entry()
+}
@@ -0,0 +1,97 @@
+/*
+ * SPDX-License-Identifier: BlueOak-1.0.0
+ * Copyright (c) Berkus Decker <berkus+vesper@metta.systems>
+ */
+
+//! Stacks a core runs on.
+//!
+//! Kernel code runs on the core's kernel stack, exceptions are taken on a separate
+//! exception stack. Both sit above an unmapped guard page, a kernel stack overflow then
+//! faults on the guard page and is handled on the exception stack. Cores register their
+//! stacks here, for unwinding and for telling an overflow apart from other faults.
+
+use {
+    crate::{per_cpu, platform::memory::mmu::KernelGranule},
+    core::{cell::Cell, ops::Range},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+per_cpu! {
+    /// Bottom and top of the executing core's kernel stack.
+    static KERNEL_STACK: Cell<(usize, usize)> = Cell::new((0, 0));
+    /// Bottom and top of the executing core's exception stack.
+    static EXCEPTION_STACK: Cell<(usize, usize)> = Cell::new((0, 0));
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Register the executing core's kernel stack.
+///
+/// # Safety
+///
+/// - The range must be the stack the core runs kernel code on, and be mapped.
+pub unsafe fn set_kernel_stack(stack: Range<usize>) {
+    KERNEL_STACK.set((stack.start, stack.end));
+}
+
+/// The executing core's kernel stack, empty if none was registered.
+pub fn kernel_stack() -> Range<usize> {
+    let (bottom, top) = KERNEL_STACK.get();
+    bottom..top
+}
+
+/// Register the executing core's exception stack.
+///
+/// # Safety
+///
+/// - The range must be the stack the core takes exceptions on, and be mapped.
+pub unsafe fn set_exception_stack(stack: Range<usize>) {
+    EXCEPTION_STACK.set((stack.start, stack.end));
+}
+
+/// The executing core's exception stack, empty if the core takes exceptions on its kernel
+/// stack.
+pub fn exception_stack() -> Range<usize> {
+    let (bottom, top) = EXCEPTION_STACK.get();
+    bottom..top
+}
+
+/// Whether `addr` lies in the guard page below the executing core's kernel stack.
+pub fn is_kernel_stack_guard(addr: usize) -> bool {
+    let stack = kernel_stack();
+    stack.start >= KernelGranule::SIZE
+        && (stack.start - KernelGranule::SIZE..stack.start).contains(&addr)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::memory::{probe_read, Address},
+    };
+
+    /// Tests run on the registered stacks, below each of them is a hole.
+    #[test_case]
+    fn stacks_have_guard_pages() {
+        let local = 0u64;
+        let sp = &local as *const u64 as usize;
+        assert!(kernel_stack().contains(&sp));
+        assert!(!exception_stack().is_empty());
+
+        for stack in [kernel_stack(), exception_stack()] {
+            let guard = Address::new(stack.start - 8);
+            assert!(unsafe { probe_read::<u64>(guard) }.is_err());
+        }
+        assert!(is_kernel_stack_guard(kernel_stack().start - 1));
+        assert!(!is_kernel_stack_guard(kernel_stack().start));
+    }
+}
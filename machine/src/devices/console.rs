@@ -3,7 +3,7 @@
 //         console::{interface, null_console::NullConsole},
 //         devices::serial::SerialOps,
 //         platform::raspberrypi::device_driver::{mini_uart::MiniUart, pl011_uart::PL011Uart},
-//         synchronization::IRQSafeNullLock,
+//         synchronization::IRQSafeSpinLock,
 //     },
 //     core::fmt,
 // };
@@ -23,7 +23,7 @@
 //
 // /// The main struct.
 // pub struct Console {
-//     inner: IRQSafeNullLock<ConsoleInner>,
+//     inner: IRQSafeSpinLock<ConsoleInner>,
 // }
 //
 // //--------------------------------------------------------------------------------------------------
@@ -1,6 +1,6 @@
 use crate::{
     exception, println,
-    synchronization::{interface::ReadWriteEx, IRQSafeNullLock, InitStateLock},
+    synchronization::{interface::ReadWriteEx, RwSpinLock},
 };
 
 //--------------------------------------------------------------------------------------------------
@@ -13,7 +13,6 @@ struct DriverManagerInner<T>
 where
     T: 'static,
 {
-    next_index: usize,
     descriptors: [Option<DeviceDriverDescriptor<T>>; NUM_DRIVERS],
 }
 
@@ -75,7 +74,7 @@ pub struct DriverManager<T>
 where
     T: 'static,
 {
-    inner: InitStateLock<DriverManagerInner<T>>,
+    inner: RwSpinLock<DriverManagerInner<T>>,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -94,7 +93,6 @@ where
 {
     pub const fn new() -> Self {
         Self {
-            next_index: 0,
             descriptors: [None; NUM_DRIVERS],
         }
     }
@@ -129,30 +127,94 @@ where
 {
     pub const fn new() -> Self {
         Self {
-            inner: InitStateLock::new(DriverManagerInner::new()),
+            inner: RwSpinLock::new(DriverManagerInner::new()),
         }
     }
 
     /// Register a device driver with the kernel.
-    pub fn register_driver(&self, descriptor: DeviceDriverDescriptor<T>) {
+    pub fn register_driver(
+        &self,
+        descriptor: DeviceDriverDescriptor<T>,
+    ) -> Result<(), &'static str> {
         self.inner.write(|inner| {
-            assert!(inner.next_index < NUM_DRIVERS);
-            inner.descriptors[inner.next_index] = Some(descriptor);
-            inner.next_index += 1;
+            let slot = inner
+                .descriptors
+                .iter_mut()
+                .find(|slot| slot.is_none())
+                .ok_or("Storage for device drivers exhausted")?;
+            *slot = Some(descriptor);
+            Ok(())
         })
     }
 
-    /// Helper for iterating over registered drivers.
-    fn for_each_descriptor(&self, f: impl FnMut(&DeviceDriverDescriptor<T>)) {
-        self.inner.read(|inner| {
-            inner
+    /// Register a device driver after kernel init and bring it up right away.
+    ///
+    /// # Safety
+    ///
+    /// - See [`init_drivers_and_irqs`](Self::init_drivers_and_irqs).
+    pub unsafe fn register_and_init_driver(
+        &self,
+        descriptor: DeviceDriverDescriptor<T>,
+    ) -> Result<(), &'static str> {
+        self.register_driver(descriptor)?;
+
+        let result = Self::init_driver(&descriptor).and_then(|_| Self::enable_irq(&descriptor));
+        if result.is_err() {
+            let _ = self.unregister_driver(descriptor.device_driver);
+        }
+        result
+    }
+
+    /// Remove a device driver from the kernel.
+    ///
+    /// The driver's IRQ handler, if any, stays registered with the IRQ manager.
+    pub fn unregister_driver(
+        &self,
+        device_driver: &'static (dyn interface::DeviceDriver<IRQNumberType = T> + Sync),
+    ) -> Result<(), &'static str> {
+        self.inner.write(|inner| {
+            let slot = inner
                 .descriptors
-                .iter()
-                .filter_map(|x| x.as_ref())
-                .for_each(f)
+                .iter_mut()
+                .find(|slot| {
+                    slot.as_ref().is_some_and(|descriptor| {
+                        core::ptr::addr_eq(descriptor.device_driver, device_driver)
+                    })
+                })
+                .ok_or("Device driver not registered")?;
+            *slot = None;
+            Ok(())
         })
     }
 
+    /// Helper for iterating over registered drivers.
+    ///
+    /// Works on a snapshot, so that `f` may register or unregister drivers.
+    fn for_each_descriptor(&self, f: impl FnMut(&DeviceDriverDescriptor<T>)) {
+        let descriptors = self.inner.read(|inner| inner.descriptors);
+        descriptors.iter().filter_map(|x| x.as_ref()).for_each(f)
+    }
+
+    /// Initialize a driver and call its post init callback.
+    unsafe fn init_driver(descriptor: &DeviceDriverDescriptor<T>) -> Result<(), &'static str> {
+        descriptor.device_driver.init()?;
+
+        if let Some(callback) = &descriptor.post_init_callback {
+            callback()?;
+        }
+        Ok(())
+    }
+
+    /// Register and enable a driver's IRQ handler, if it has one.
+    fn enable_irq(descriptor: &DeviceDriverDescriptor<T>) -> Result<(), &'static str> {
+        match &descriptor.irq_number {
+            Some(irq_number) => descriptor
+                .device_driver
+                .register_and_enable_irq_handler(irq_number),
+            None => Ok(()),
+        }
+    }
+
     /// Fully initialize all drivers.
     ///
     /// # Safety
@@ -160,41 +222,25 @@ where
     /// - During init, drivers might do things with system-wide impact.
     pub unsafe fn init_drivers_and_irqs(&self) {
         self.for_each_descriptor(|descriptor| {
-            // 1. Initialize driver.
-            if let Err(x) = descriptor.device_driver.init() {
+            // 1. Initialize driver and call corresponding post init callback.
+            if let Err(x) = Self::init_driver(descriptor) {
                 panic!(
                     "Error initializing driver: {}: {}",
                     descriptor.device_driver.compatible(),
                     x
                 );
             }
-
-            // 2. Call corresponding post init callback.
-            if let Some(callback) = &descriptor.post_init_callback {
-                if let Err(x) = callback() {
-                    panic!(
-                        "Error during driver post-init callback: {}: {}",
-                        descriptor.device_driver.compatible(),
-                        x
-                    );
-                }
-            }
         });
 
-        // 3. After all post-init callbacks were done, the interrupt controller should be
+        // 2. After all post-init callbacks were done, the interrupt controller should be
         //    registered and functional. So let drivers register with it now.
         self.for_each_descriptor(|descriptor| {
-            if let Some(irq_number) = &descriptor.irq_number {
-                if let Err(x) = descriptor
-                    .device_driver
-                    .register_and_enable_irq_handler(irq_number)
-                {
-                    panic!(
-                        "Error during driver interrupt handler registration: {}: {}",
-                        descriptor.device_driver.compatible(),
-                        x
-                    );
-                }
+            if let Err(x) = Self::enable_irq(descriptor) {
+                panic!(
+                    "Error during driver interrupt handler registration: {}: {}",
+                    descriptor.device_driver.compatible(),
+                    x
+                );
             }
         });
     }
@@ -209,3 +255,110 @@ where
         });
     }
 }
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            memory::{self, mmu::MMIODescriptor},
+            platform, state,
+        },
+        core::sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    struct DummyDriver;
+
+    impl interface::DeviceDriver for DummyDriver {
+        type IRQNumberType = u32;
+
+        fn compatible(&self) -> &'static str {
+            "Dummy"
+        }
+    }
+
+    static DUMMY_A: DummyDriver = DummyDriver;
+    static DUMMY_B: DummyDriver = DummyDriver;
+
+    fn count(manager: &DriverManager<u32>) -> usize {
+        let mut n = 0;
+        manager.for_each_descriptor(|_| n += 1);
+        n
+    }
+
+    /// Drivers come and go after kernel init, freed slots are reused.
+    #[test_case]
+    fn drivers_register_and_unregister_at_runtime() {
+        let manager = DriverManager::<u32>::new();
+
+        for _ in 0..NUM_DRIVERS {
+            manager
+                .register_driver(DeviceDriverDescriptor::new(&DUMMY_A, None, None))
+                .unwrap();
+        }
+        assert!(manager
+            .register_driver(DeviceDriverDescriptor::new(&DUMMY_B, None, None))
+            .is_err());
+
+        manager.unregister_driver(&DUMMY_A).unwrap();
+        assert_eq!(count(&manager), NUM_DRIVERS - 1);
+        assert!(manager.unregister_driver(&DUMMY_B).is_err());
+
+        unsafe {
+            manager.register_and_init_driver(DeviceDriverDescriptor::new(&DUMMY_B, None, None))
+        }
+        .unwrap();
+        assert_eq!(count(&manager), NUM_DRIVERS);
+    }
+
+    /// Maps the system timer registers on init, which nothing else maps in the test kernel.
+    struct MmioDriver {
+        virt_addr: AtomicUsize,
+    }
+
+    impl interface::DeviceDriver for MmioDriver {
+        type IRQNumberType = u32;
+
+        fn compatible(&self) -> &'static str {
+            "MMIO test"
+        }
+
+        unsafe fn init(&self) -> Result<(), &'static str> {
+            let timer = platform::memory::board::board_memory_map().system_timer();
+            let virt_addr = memory::mmu::kernel_map_mmio(
+                self.compatible(),
+                &MMIODescriptor::new(timer.base, timer.size),
+            )?;
+            self.virt_addr
+                .store(virt_addr.as_usize(), Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    static MMIO_DRIVER: MmioDriver = MmioDriver {
+        virt_addr: AtomicUsize::new(0),
+    };
+
+    /// A driver registered after kernel init can still map its MMIO.
+    #[test_case]
+    fn mmio_driver_registers_after_init() {
+        assert!(!state::state_manager().is_init());
+
+        let manager = DriverManager::<u32>::new();
+        unsafe {
+            manager.register_and_init_driver(DeviceDriverDescriptor::new(&MMIO_DRIVER, None, None))
+        }
+        .unwrap();
+
+        let virt_addr = MMIO_DRIVER.virt_addr.load(Ordering::Relaxed);
+        assert!(platform::memory::mmu::virt_mmio_remap_region()
+            .contains(memory::Address::new(virt_addr)));
+
+        // Faults unless the mapping is live.
+        let _ = unsafe { core::ptr::read_volatile(virt_addr as *const u32) };
+    }
+}
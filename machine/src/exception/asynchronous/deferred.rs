@@ -0,0 +1,297 @@
+/*
+ * SPDX-License-Identifier: BlueOak-1.0.0
+ * Copyright (c) Berkus Decker <berkus+vesper@metta.systems>
+ */
+
+//! Deferred interrupt work.
+//!
+//! IRQ handlers queue work with [`defer`] to keep the time spent with IRQs masked short. The
+//! queue is drained with IRQs unmasked, at the end of IRQ handling and from idle loops
+//! through [`run_deferred_work`]. Each core has its own queue, so work runs on the core that
+//! queued it, by priority and in queueing order within one.
+
+use {
+    super::{local_irq_mask, local_irq_mask_save, local_irq_restore, local_irq_unmask},
+    crate::per_cpu,
+    core::{
+        cell::{Cell, RefCell},
+        fmt,
+        sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Work to run outside of IRQ context.
+pub type DeferredWork = fn();
+
+/// Order in which queued work runs.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum WorkPriority {
+    /// Runs first.
+    High,
+    /// Runs after high priority work.
+    Normal,
+    /// Runs last.
+    Low,
+}
+
+/// Counters of the deferred work queue.
+pub struct DeferredWorkStats {
+    queued: AtomicU64,
+    dropped: AtomicU64,
+    executed: AtomicU64,
+    max_depth: AtomicUsize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Work items pending at once, per core.
+const QUEUE_CAPACITY: usize = 32;
+
+#[derive(Copy, Clone)]
+struct WorkItem {
+    work: DeferredWork,
+    priority: WorkPriority,
+    /// Queueing order, older items run first within a priority.
+    sequence: u64,
+}
+
+struct WorkQueue {
+    items: [Option<WorkItem>; QUEUE_CAPACITY],
+    next_sequence: u64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static STATS: DeferredWorkStats = DeferredWorkStats {
+    queued: AtomicU64::new(0),
+    dropped: AtomicU64::new(0),
+    executed: AtomicU64::new(0),
+    max_depth: AtomicUsize::new(0),
+};
+
+per_cpu! {
+    static QUEUE: RefCell<WorkQueue> = RefCell::new(WorkQueue::new());
+
+    /// Whether the core is draining the queue further up its stack.
+    static DRAINING: Cell<bool> = Cell::new(false);
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl WorkQueue {
+    const fn new() -> Self {
+        Self {
+            items: [None; QUEUE_CAPACITY],
+            next_sequence: 0,
+        }
+    }
+
+    /// Queue `work`, returning the queue depth.
+    fn push(&mut self, work: DeferredWork, priority: WorkPriority) -> Result<usize, &'static str> {
+        let slot = self
+            .items
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or("Deferred work queue full")?;
+
+        *slot = Some(WorkItem {
+            work,
+            priority,
+            sequence: self.next_sequence,
+        });
+        self.next_sequence += 1;
+
+        Ok(self.len())
+    }
+
+    /// Take the item to run next.
+    fn pop(&mut self) -> Option<WorkItem> {
+        let slot = self
+            .items
+            .iter_mut()
+            .filter(|slot| slot.is_some())
+            .min_by_key(|slot| slot.map(|item| (item.priority, item.sequence)))?;
+
+        slot.take()
+    }
+
+    fn len(&self) -> usize {
+        self.items.iter().flatten().count()
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Queue `work` to run on the executing core, outside of IRQ context.
+///
+/// Fails if the queue is full, the work is then counted as dropped.
+pub fn defer(work: DeferredWork, priority: WorkPriority) -> Result<(), &'static str> {
+    match QUEUE.with(|queue| queue.borrow_mut().push(work, priority)) {
+        Ok(depth) => {
+            STATS.queued.fetch_add(1, Ordering::Relaxed);
+            STATS.max_depth.fetch_max(depth, Ordering::Relaxed);
+            Ok(())
+        }
+        Err(e) => {
+            STATS.dropped.fetch_add(1, Ordering::Relaxed);
+            Err(e)
+        }
+    }
+}
+
+/// Run the executing core's queued work until its queue is empty.
+///
+/// Work runs with IRQs unmasked, the mask is restored on return. Does nothing on a core that
+/// is already draining the queue, like an IRQ interrupting a work item.
+pub fn run_deferred_work() {
+    let saved = local_irq_mask_save();
+
+    if !DRAINING.get() {
+        DRAINING.set(true);
+
+        while let Some(item) = QUEUE.with(|queue| queue.borrow_mut().pop()) {
+            local_irq_unmask();
+            (item.work)();
+            local_irq_mask();
+
+            STATS.executed.fetch_add(1, Ordering::Relaxed);
+        }
+
+        DRAINING.set(false);
+    }
+
+    local_irq_restore(saved);
+}
+
+/// Return a reference to the deferred work counters.
+pub fn deferred_work_stats() -> &'static DeferredWorkStats {
+    &STATS
+}
+
+impl DeferredWorkStats {
+    /// Work queued since boot.
+    pub fn queued(&self) -> u64 {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Work refused because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Work run since boot.
+    pub fn executed(&self) -> u64 {
+        self.executed.load(Ordering::Relaxed)
+    }
+
+    /// The most work pending at once.
+    pub fn max_depth(&self) -> usize {
+        self.max_depth.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Display for DeferredWorkStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} queued, {} dropped, {} executed, max depth {}/{}",
+            self.queued(),
+            self.dropped(),
+            self.executed(),
+            self.max_depth(),
+            QUEUE_CAPACITY
+        )
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*, crate::exception::asynchronous::is_local_irq_masked,
+        core::sync::atomic::AtomicBool,
+    };
+
+    static RAN_UNMASKED: AtomicBool = AtomicBool::new(false);
+    static NESTED_RAN: AtomicBool = AtomicBool::new(false);
+    static NESTED_RAN_INLINE: AtomicBool = AtomicBool::new(false);
+
+    fn first() {}
+    fn second() {}
+    fn third() {}
+
+    fn nested() {
+        NESTED_RAN.store(true, Ordering::Relaxed);
+    }
+
+    /// Checks the IRQ mask and that draining again from work does not run queued work.
+    fn unmasked() {
+        RAN_UNMASKED.store(!is_local_irq_masked(), Ordering::Relaxed);
+
+        defer(nested, WorkPriority::High).unwrap();
+        run_deferred_work();
+        NESTED_RAN_INLINE.store(NESTED_RAN.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+
+    #[test_case]
+    fn work_runs_by_priority_then_in_order() {
+        let mut queue = WorkQueue::new();
+        queue.push(first, WorkPriority::Normal).unwrap();
+        queue.push(second, WorkPriority::Low).unwrap();
+        queue.push(third, WorkPriority::Normal).unwrap();
+        queue.push(second, WorkPriority::High).unwrap();
+
+        let order = [
+            (WorkPriority::High, 3),
+            (WorkPriority::Normal, 0),
+            (WorkPriority::Normal, 2),
+            (WorkPriority::Low, 1),
+        ];
+        for (priority, sequence) in order {
+            let item = queue.pop().unwrap();
+            assert_eq!((item.priority, item.sequence), (priority, sequence));
+        }
+        assert!(queue.pop().is_none());
+    }
+
+    #[test_case]
+    fn full_work_queue_is_refused() {
+        let mut queue = WorkQueue::new();
+        for depth in 1..=QUEUE_CAPACITY {
+            assert_eq!(queue.push(first, WorkPriority::Low), Ok(depth));
+        }
+        assert!(queue.push(first, WorkPriority::High).is_err());
+    }
+
+    #[test_case]
+    fn deferred_work_runs_with_irqs_unmasked() {
+        let saved = local_irq_mask_save();
+        let executed = deferred_work_stats().executed();
+
+        defer(unmasked, WorkPriority::Normal).unwrap();
+        run_deferred_work();
+
+        assert!(is_local_irq_masked());
+        assert!(RAN_UNMASKED.load(Ordering::Relaxed));
+        assert!(!NESTED_RAN_INLINE.load(Ordering::Relaxed));
+        assert!(NESTED_RAN.load(Ordering::Relaxed));
+        assert_eq!(deferred_work_stats().executed(), executed + 2);
+
+        local_irq_restore(saved);
+    }
+}
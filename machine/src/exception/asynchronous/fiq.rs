@@ -0,0 +1,142 @@
+/*
+ * SPDX-License-Identifier: BlueOak-1.0.0
+ * Copyright (c) Berkus Decker <berkus+vesper@metta.systems>
+ */
+
+//! Fast interrupts.
+//!
+//! One interrupt source can be routed as FIQ, bypassing IRQ handling: its handler is called
+//! straight from the FIQ vector on a minimal frame, and preempts IRQ handlers. This suits
+//! sources that must not wait, like a profiling sampler or a fast serial receiver.
+
+use {
+    super::{local_fiq_unmask, IRQNumber},
+    crate::{cpu, platform},
+    core::{
+        mem, ptr,
+        sync::atomic::{AtomicPtr, Ordering},
+    },
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Called from the FIQ vector, with all exceptions masked.
+///
+/// The FIQ may interrupt code holding any lock, so the handler must not take locks, which
+/// rules out logging too. The interrupt controller is not involved in completing the FIQ: the
+/// handler must quiet the device, and only level-triggered sources work. On the GIC the FIQ is
+/// never acknowledged nor completed through the CPU interface.
+pub type FiqHandler = fn();
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// The registered [`FiqHandler`], read without locking.
+static FIQ_HANDLER: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Store `handler` and route its source with `route`, clearing the handler if that fails.
+fn install(
+    handler: FiqHandler,
+    route: impl FnOnce() -> Result<(), &'static str>,
+) -> Result<(), &'static str> {
+    FIQ_HANDLER
+        .compare_exchange(
+            ptr::null_mut(),
+            handler as *mut (),
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        )
+        .map_err(|_| "FIQ handler already registered")?;
+
+    if let Err(e) = route() {
+        FIQ_HANDLER.store(ptr::null_mut(), Ordering::Release);
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Route `irq_number` as FIQ and call `handler` for it.
+///
+/// Only one source can be routed at a time. FIQs are taken by the boot core, which must call
+/// this and gets FIQs unmasked. The source must have no IRQ handlers.
+pub fn register_fiq_handler(
+    irq_number: IRQNumber,
+    handler: FiqHandler,
+) -> Result<(), &'static str> {
+    if platform::cpu::BOOT_CORE_ID != cpu::smp::core_id() {
+        return Err("FIQs are taken by the boot core");
+    }
+
+    install(handler, || {
+        platform::exception::asynchronous::route_fiq(&irq_number)
+    })?;
+
+    local_fiq_unmask();
+
+    Ok(())
+}
+
+/// Called from the FIQ vector.
+pub(crate) fn handle_fiq() {
+    let handler = FIQ_HANDLER.load(Ordering::Acquire);
+
+    // Nothing is routed as FIQ before the handler is stored.
+    if !handler.is_null() {
+        let handler: FiqHandler = unsafe { mem::transmute(handler) };
+        handler();
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {super::*, core::sync::atomic::AtomicBool};
+
+    static CALLED: AtomicBool = AtomicBool::new(false);
+
+    fn handler() {
+        CALLED.store(true, Ordering::Relaxed);
+    }
+
+    fn other_handler() {}
+
+    #[test_case]
+    fn failed_routing_frees_the_handler_slot() {
+        assert_eq!(install(handler, || Err("No route")), Err("No route"));
+        assert!(FIQ_HANDLER.load(Ordering::Acquire).is_null());
+    }
+
+    #[test_case]
+    fn fiq_handler_is_registered_once() {
+        assert!(install(handler, || Ok(())).is_ok());
+
+        let mut routed = false;
+        let second = install(other_handler, || {
+            routed = true;
+            Ok(())
+        });
+        assert!(second.is_err());
+        assert!(!routed);
+
+        CALLED.store(false, Ordering::Relaxed);
+        handle_fiq();
+        assert!(CALLED.load(Ordering::Relaxed));
+
+        FIQ_HANDLER.store(ptr::null_mut(), Ordering::Release);
+    }
+}
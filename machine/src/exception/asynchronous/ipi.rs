@@ -0,0 +1,179 @@
+/*
+ * SPDX-License-Identifier: BlueOak-1.0.0
+ * Copyright (c) Berkus Decker <berkus+vesper@metta.systems>
+ */
+
+//! Inter-processor interrupts.
+//!
+//! The platform delivers them through its interrupt controller, as software generated
+//! interrupts on the GIC or through the core mailboxes of the BCM2836 local controller.
+//! Receiving cores call the handler registered for the kind, in IRQ context.
+
+use crate::{
+    cpu, platform,
+    synchronization::{interface::ReadWriteEx, RwSpinLock},
+    warn,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// What the sending core asks of the receiving cores.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IpiKind {
+    /// Re-evaluate what to run.
+    Reschedule,
+    /// Invalidate stale TLB entries.
+    TlbShootdown,
+    /// Stop executing. Parks the core unless a handler is registered.
+    Stop,
+}
+
+/// Called on the receiving core, in IRQ context.
+pub type IpiHandler = fn();
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const NUM_IPI_KINDS: usize = 3;
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static IPI_HANDLERS: RwSpinLock<[Option<IpiHandler>; NUM_IPI_KINDS]> =
+    RwSpinLock::new([None; NUM_IPI_KINDS]);
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl IpiKind {
+    /// All kinds, indexed by their number.
+    pub const ALL: [IpiKind; NUM_IPI_KINDS] = [Self::Reschedule, Self::TlbShootdown, Self::Stop];
+
+    /// The kind's number, which platforms use as SGI ID or mailbox bit.
+    pub const fn number(self) -> usize {
+        self as usize
+    }
+}
+
+/// Register the handler of an IPI kind.
+pub fn register_ipi_handler(kind: IpiKind, handler: IpiHandler) -> Result<(), &'static str> {
+    IPI_HANDLERS.write(|handlers| {
+        let slot = &mut handlers[kind.number()];
+        if slot.is_some() {
+            return Err("IPI handler already registered");
+        }
+        *slot = Some(handler);
+        Ok(())
+    })
+}
+
+/// Unregister the handler of an IPI kind.
+pub fn unregister_ipi_handler(kind: IpiKind) {
+    IPI_HANDLERS.write(|handlers| handlers[kind.number()] = None);
+}
+
+/// Send an IPI to the cores in `target_mask`, bit `n` standing for core `n`.
+///
+/// Memory writes preceding the call are visible to the handlers on the target cores.
+pub fn send_ipi(target_mask: u64, kind: IpiKind) -> Result<(), &'static str> {
+    if target_mask == 0 {
+        return Err("No target core");
+    }
+    if target_mask >> platform::cpu::num_cores() != 0 {
+        return Err("No such core");
+    }
+
+    platform::exception::asynchronous::send_ipi(target_mask, kind)
+}
+
+/// Let the executing core receive IPIs.
+///
+/// The boot core is set up along with the interrupt controller. Secondary cores call this
+/// before unmasking IRQs.
+pub fn ipi_init_core() -> Result<(), &'static str> {
+    platform::exception::asynchronous::ipi_init_core()
+}
+
+/// Called by the platform on the receiving core.
+pub(crate) fn handle_ipi(kind: IpiKind) {
+    match IPI_HANDLERS.read(|handlers| handlers[kind.number()]) {
+        Some(handler) => handler(),
+        None if kind == IpiKind::Stop => cpu::endless_sleep(),
+        None => warn!("Unhandled IPI {:?} on core {}", kind, cpu::smp::core_id()),
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            exception::asynchronous::{local_irq_mask_save, local_irq_restore, local_irq_unmask},
+            time,
+        },
+        core::{
+            sync::atomic::{AtomicBool, Ordering},
+            time::Duration,
+        },
+    };
+
+    static RECEIVED: AtomicBool = AtomicBool::new(false);
+
+    fn nop() {}
+
+    fn receive() {
+        RECEIVED.store(true, Ordering::Release);
+    }
+
+    #[test_case]
+    fn ipi_kinds_are_numbered_in_order() {
+        for (number, kind) in IpiKind::ALL.iter().enumerate() {
+            assert_eq!(kind.number(), number);
+        }
+    }
+
+    #[test_case]
+    fn ipi_handler_owns_its_kind() {
+        assert!(register_ipi_handler(IpiKind::Reschedule, nop).is_ok());
+        assert!(register_ipi_handler(IpiKind::Reschedule, nop).is_err());
+
+        unregister_ipi_handler(IpiKind::Reschedule);
+        assert!(register_ipi_handler(IpiKind::Reschedule, nop).is_ok());
+        unregister_ipi_handler(IpiKind::Reschedule);
+    }
+
+    #[test_case]
+    fn ipi_targets_are_checked() {
+        assert!(send_ipi(0, IpiKind::Reschedule).is_err());
+        assert!(send_ipi(1 << 63, IpiKind::Reschedule).is_err());
+    }
+
+    /// An IPI sent to the executing core goes through the controller and reaches its handler.
+    #[test_case]
+    fn self_ipi_is_delivered() {
+        RECEIVED.store(false, Ordering::Relaxed);
+        register_ipi_handler(IpiKind::Reschedule, receive).unwrap();
+
+        let saved = local_irq_mask_save();
+        send_ipi(1 << cpu::smp::core_id(), IpiKind::Reschedule).unwrap();
+
+        let deadline = time::time_manager().uptime() + Duration::from_millis(100);
+        local_irq_unmask();
+        while !RECEIVED.load(Ordering::Acquire) && time::time_manager().uptime() < deadline {
+            core::hint::spin_loop();
+        }
+        local_irq_restore(saved);
+
+        unregister_ipi_handler(IpiKind::Reschedule);
+        assert!(RECEIVED.load(Ordering::Acquire));
+    }
+}
@@ -1,23 +1,56 @@
 #[cfg(target_arch = "aarch64")]
 use crate::arch::aarch64::exception::asynchronous as arch_asynchronous;
 
+mod deferred;
+mod fiq;
+mod ipi;
 mod null_irq_manager;
+mod stats;
 
 //--------------------------------------------------------------------------------------------------
 // Architectural Public Reexports
 //--------------------------------------------------------------------------------------------------
 pub use arch_asynchronous::{
-    is_local_irq_masked, local_irq_mask, local_irq_mask_save, local_irq_restore, local_irq_unmask,
-    print_state,
+    is_local_irq_masked, local_fiq_unmask, local_irq_mask, local_irq_mask_save, local_irq_restore,
+    local_irq_unmask, print_state,
 };
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
+pub use {
+    deferred::{
+        defer, deferred_work_stats, run_deferred_work, DeferredWork, DeferredWorkStats,
+        WorkPriority,
+    },
+    fiq::{register_fiq_handler, FiqHandler},
+    ipi::{
+        ipi_init_core, register_ipi_handler, send_ipi, unregister_ipi_handler, IpiHandler, IpiKind,
+    },
+    stats::IRQStats,
+};
+pub(crate) use {fiq::handle_fiq, ipi::handle_ipi};
+
 /// Interrupt number as defined by the BSP.
 pub type IRQNumber = crate::platform::exception::asynchronous::IRQNumber;
 
+/// Urgency of an IRQ, on controllers that let IRQs preempt each other's handlers.
+///
+/// Only a more urgent IRQ preempts a running handler, see [`interface::IRQManager::set_nesting`].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub enum IRQPriority {
+    /// Preempts every other handler.
+    Highest,
+    /// Preempts normal and low priority handlers, like timer ticks.
+    High,
+    /// Device IRQs.
+    #[default]
+    Normal,
+    /// Slow or bulk work.
+    Low,
+}
+
 /// Interrupt descriptor.
 #[derive(Copy, Clone)]
 pub struct IRQHandlerDescriptor<T>
@@ -32,6 +65,22 @@ where
 
     /// Reference to handler trait object.
     handler: &'static (dyn interface::IRQHandler + Sync),
+
+    /// Urgency of the IRQ.
+    priority: IRQPriority,
+}
+
+/// Handlers sharing one IRQ line at most.
+pub const MAX_SHARED_HANDLERS: usize = 4;
+
+/// The handlers registered for one IRQ number, called in registration order.
+#[derive(Copy, Clone)]
+pub struct IRQHandlerChain<T>
+where
+    T: Copy,
+{
+    /// Registered handlers first, free slots last.
+    handlers: [Option<IRQHandlerDescriptor<T>>; MAX_SHARED_HANDLERS],
 }
 
 /// IRQContext token.
@@ -51,7 +100,12 @@ pub mod interface {
     /// Implemented by types that handle IRQs.
     pub trait IRQHandler {
         /// Called when the corresponding interrupt is asserted.
-        fn handle(&self) -> Result<(), &'static str>;
+        ///
+        /// Returns [`HandlerResult::Unhandled`] if the device did not raise it, the line may be
+        /// shared with other devices.
+        ///
+        /// [`HandlerResult::Unhandled`]: crate::exception::HandlerResult::Unhandled
+        fn handle(&self) -> Result<crate::exception::HandlerResult, &'static str>;
     }
 
     /// IRQ management functions.
@@ -62,20 +116,30 @@ pub mod interface {
         /// The IRQ number type depends on the implementation.
         type IRQNumberType: Copy;
 
-        /// Register a handler.
+        /// Register a handler, after the handlers already registered for the same number.
         fn register_handler(
             &self,
             irq_handler_descriptor: super::IRQHandlerDescriptor<Self::IRQNumberType>,
         ) -> Result<(), &'static str>;
 
+        /// Unregister a handler, disabling the interrupt once no handler is left.
+        fn unregister_handler(
+            &self,
+            irq_handler_descriptor: super::IRQHandlerDescriptor<Self::IRQNumberType>,
+        ) -> Result<(), &'static str>;
+
         /// Enable an interrupt in the controller.
         fn enable(&self, irq_number: &Self::IRQNumberType);
 
+        /// Disable an interrupt in the controller, it is masked until enabled again.
+        fn disable(&self, irq_number: &Self::IRQNumberType);
+
         /// Handle pending interrupts.
         ///
         /// This function is called directly from the CPU's IRQ exception vector. On AArch64,
         /// this means that the respective CPU core has disabled exception handling.
-        /// This function can therefore not be preempted and runs start to finish.
+        /// This function can therefore not be preempted and runs start to finish, unless
+        /// nesting is enabled, see [`Self::set_nesting`].
         ///
         /// Takes an IRQContext token to ensure it can only be called from IRQ context.
         #[allow(clippy::trivially_copy_pass_by_ref)]
@@ -84,8 +148,25 @@ pub mod interface {
             ic: &super::IRQContext<'irq_context>,
         );
 
+        /// Let more urgent IRQs preempt the handlers of less urgent ones.
+        ///
+        /// Handlers are then called with IRQs unmasked, while the controller holds back IRQs
+        /// of the same or lower [`IRQPriority`](super::IRQPriority). Fails on controllers
+        /// without priorities.
+        fn set_nesting(&self, _enabled: bool) -> Result<(), &'static str> {
+            Err("IRQ nesting not supported")
+        }
+
         /// Print list of registered handlers.
         fn print_handler(&self) {}
+
+        /// Print the statistics of every IRQ that has a handler or was signalled.
+        fn print_stats(&self) {}
+
+        /// Statistics of an IRQ, if the manager keeps them.
+        fn stats(&self, _irq_number: &Self::IRQNumberType) -> Option<&super::IRQStats> {
+            None
+        }
     }
 }
 
@@ -93,21 +174,21 @@ pub mod interface {
 // Global instances
 //--------------------------------------------------------------------------------------------------
 
-static IRQ_MANAGER: InitStateLock<
+static IRQ_MANAGER: RwSpinLock<
     &'static (dyn interface::IRQManager<IRQNumberType = IRQNumber> + Sync),
-> = InitStateLock::new(&null_irq_manager::NULL_IRQ_MANAGER);
+> = RwSpinLock::new(&null_irq_manager::NULL_IRQ_MANAGER);
 
-use core::marker::PhantomData;
+use core::{fmt, marker::PhantomData};
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
-use crate::synchronization::{interface::ReadWriteEx, InitStateLock};
+use crate::synchronization::{interface::ReadWriteEx, RwSpinLock};
 
 impl<T> IRQHandlerDescriptor<T>
 where
     T: Copy,
 {
-    /// Create an instance.
+    /// Create an instance, of [`IRQPriority::Normal`].
     pub const fn new(
         number: T,
         name: &'static str,
@@ -117,9 +198,15 @@ where
             number,
             name,
             handler,
+            priority: IRQPriority::Normal,
         }
     }
 
+    /// Return a copy with the given priority.
+    pub const fn with_priority(self, priority: IRQPriority) -> Self {
+        Self { priority, ..self }
+    }
+
     /// Return the number.
     pub const fn number(&self) -> T {
         self.number
@@ -134,6 +221,107 @@ where
     pub const fn handler(&self) -> &'static (dyn interface::IRQHandler + Sync) {
         self.handler
     }
+
+    /// Return the priority.
+    pub const fn priority(&self) -> IRQPriority {
+        self.priority
+    }
+
+    /// Whether both descriptors refer to the same handler object.
+    fn is_same_handler(&self, other: &Self) -> bool {
+        core::ptr::eq(
+            self.handler as *const _ as *const (),
+            other.handler as *const _ as *const (),
+        )
+    }
+}
+
+impl<T> IRQHandlerChain<T>
+where
+    T: Copy,
+{
+    /// Create an empty chain.
+    pub const fn new() -> Self {
+        Self {
+            handlers: [None; MAX_SHARED_HANDLERS],
+        }
+    }
+
+    /// Append a handler, unless the same handler is registered already.
+    pub fn register(&mut self, descriptor: IRQHandlerDescriptor<T>) -> Result<(), &'static str> {
+        if self
+            .iter()
+            .any(|registered| registered.is_same_handler(&descriptor))
+        {
+            return Err("IRQ handler already registered");
+        }
+
+        let slot = self
+            .handlers
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or("IRQ handler chain full")?;
+        *slot = Some(descriptor);
+
+        Ok(())
+    }
+
+    /// Remove a handler, keeping the others in order.
+    pub fn unregister(&mut self, descriptor: IRQHandlerDescriptor<T>) -> Result<(), &'static str> {
+        let index = self
+            .iter()
+            .position(|registered| registered.is_same_handler(&descriptor))
+            .ok_or("IRQ handler not registered")?;
+
+        self.handlers[index..].rotate_left(1);
+        self.handlers[MAX_SHARED_HANDLERS - 1] = None;
+
+        Ok(())
+    }
+
+    /// Whether no handler is registered.
+    pub fn is_empty(&self) -> bool {
+        self.handlers[0].is_none()
+    }
+
+    /// The registered handlers, in calling order.
+    pub fn iter(&self) -> impl Iterator<Item = &IRQHandlerDescriptor<T>> {
+        self.handlers.iter().map_while(Option::as_ref)
+    }
+
+    /// The most urgent priority among the handlers, which the shared line takes on.
+    pub fn priority(&self) -> Option<IRQPriority> {
+        self.iter().map(IRQHandlerDescriptor::priority).min()
+    }
+}
+
+impl<T> Default for IRQHandlerChain<T>
+where
+    T: Copy,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> fmt::Display for IRQHandlerChain<T>
+where
+    T: Copy,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "<no handler>");
+        }
+
+        for (i, descriptor) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", descriptor.name())?;
+        }
+
+        Ok(())
+    }
 }
 
 impl<'irq_context> IRQContext<'irq_context> {
@@ -179,3 +367,88 @@ pub fn register_irq_manager(
 pub fn irq_manager() -> &'static dyn interface::IRQManager<IRQNumberType = IRQNumber> {
     IRQ_MANAGER.read(|manager| *manager)
 }
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::exception::HandlerResult};
+
+    struct Nop;
+
+    impl interface::IRQHandler for Nop {
+        fn handle(&self) -> Result<HandlerResult, &'static str> {
+            Ok(HandlerResult::Unhandled)
+        }
+    }
+
+    static FIRST: Nop = Nop;
+    static SECOND: Nop = Nop;
+    static THIRD: Nop = Nop;
+
+    fn names(chain: &IRQHandlerChain<usize>) -> [&'static str; MAX_SHARED_HANDLERS] {
+        let mut names = [""; MAX_SHARED_HANDLERS];
+        for (name, descriptor) in names.iter_mut().zip(chain.iter()) {
+            *name = descriptor.name();
+        }
+        names
+    }
+
+    #[test_case]
+    fn handler_chain_keeps_registration_order() {
+        let mut chain = IRQHandlerChain::new();
+        chain
+            .register(IRQHandlerDescriptor::new(0, "first", &FIRST))
+            .unwrap();
+        chain
+            .register(IRQHandlerDescriptor::new(0, "second", &SECOND))
+            .unwrap();
+        chain
+            .register(IRQHandlerDescriptor::new(0, "third", &THIRD))
+            .unwrap();
+
+        chain
+            .unregister(IRQHandlerDescriptor::new(0, "second", &SECOND))
+            .unwrap();
+        assert_eq!(names(&chain), ["first", "third", "", ""]);
+
+        chain
+            .register(IRQHandlerDescriptor::new(0, "second", &SECOND))
+            .unwrap();
+        assert_eq!(names(&chain), ["first", "third", "second", ""]);
+    }
+
+    #[test_case]
+    fn handler_chain_refuses_duplicates() {
+        let mut chain = IRQHandlerChain::new();
+        let first = IRQHandlerDescriptor::new(0, "first", &FIRST);
+
+        chain.register(first).unwrap();
+        assert!(chain.register(first).is_err());
+
+        chain.unregister(first).unwrap();
+        assert!(chain.unregister(first).is_err());
+        assert!(chain.is_empty());
+    }
+
+    #[test_case]
+    fn handler_chain_takes_most_urgent_priority() {
+        let mut chain = IRQHandlerChain::new();
+        assert_eq!(chain.priority(), None);
+
+        let first = IRQHandlerDescriptor::new(0, "first", &FIRST);
+        let second =
+            IRQHandlerDescriptor::new(0, "second", &SECOND).with_priority(IRQPriority::High);
+
+        chain.register(first).unwrap();
+        assert_eq!(chain.priority(), Some(IRQPriority::Normal));
+
+        chain.register(second).unwrap();
+        assert_eq!(chain.priority(), Some(IRQPriority::High));
+
+        chain.unregister(second).unwrap();
+        assert_eq!(chain.priority(), Some(IRQPriority::Normal));
+    }
+}
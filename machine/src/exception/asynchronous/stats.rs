@@ -0,0 +1,220 @@
+/*
+ * SPDX-License-Identifier: BlueOak-1.0.0
+ * Copyright (c) Berkus Decker <berkus+vesper@metta.systems>
+ */
+
+//! Per-IRQ statistics.
+//!
+//! Interrupt controllers keep one [`IRQStats`] per IRQ number next to their handler table and
+//! dispatch through it. Counters are atomic, any core may update them without locking.
+
+#[cfg(target_arch = "aarch64")]
+use crate::arch::aarch64::time as arch_time;
+use {
+    super::IRQHandlerChain,
+    crate::{exception::HandlerResult, warn},
+    core::{
+        fmt,
+        sync::atomic::{AtomicU64, Ordering},
+        time::Duration,
+    },
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Counters of a single IRQ.
+pub struct IRQStats {
+    /// Times the handlers were called.
+    count: AtomicU64,
+    /// Times the IRQ was signalled with no handler claiming it.
+    spurious: AtomicU64,
+    /// Handler invocations returning an error.
+    errors: AtomicU64,
+    /// Counter ticks spent in the handlers, summed up.
+    total_ticks: AtomicU64,
+    /// Counter ticks of the longest call of the handlers.
+    max_ticks: AtomicU64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl IRQStats {
+    const NEW: Self = Self::new();
+
+    /// Create an instance with all counters zeroed.
+    pub const fn new() -> Self {
+        Self {
+            count: AtomicU64::new(0),
+            spurious: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            total_ticks: AtomicU64::new(0),
+            max_ticks: AtomicU64::new(0),
+        }
+    }
+
+    /// Create a table of `N` instances, one per IRQ number.
+    pub const fn table<const N: usize>() -> [Self; N] {
+        [Self::NEW; N]
+    }
+
+    /// Call every handler of `chain` and account for it.
+    ///
+    /// The IRQ is counted as spurious if no handler claims it. Handler errors are counted and
+    /// logged, a failing handler counts as claiming. Handlers are timed on the architectural
+    /// counter directly, whichever time backend is in use.
+    pub fn dispatch<T: Copy>(&self, chain: &IRQHandlerChain<T>) {
+        if chain.is_empty() {
+            self.count_spurious();
+            return;
+        }
+
+        let start = arch_time::ticks();
+        let mut claimed = false;
+
+        for descriptor in chain.iter() {
+            match descriptor.handler().handle() {
+                Ok(HandlerResult::Handled) => claimed = true,
+                Ok(HandlerResult::Unhandled) => {}
+                Err(e) => {
+                    claimed = true;
+                    self.errors.fetch_add(1, Ordering::Relaxed);
+                    warn!("Error handling IRQ {}: {}", descriptor.name(), e);
+                }
+            }
+        }
+
+        let ticks = arch_time::ticks().wrapping_sub(start);
+
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_ticks.fetch_add(ticks, Ordering::Relaxed);
+        self.max_ticks.fetch_max(ticks, Ordering::Relaxed);
+
+        if !claimed {
+            self.count_spurious();
+        }
+    }
+
+    /// Count a spurious hit, for IRQs not dispatched through [`Self::dispatch`].
+    pub fn count_spurious(&self) {
+        self.spurious.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Times the handlers were called.
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Times the IRQ was signalled with no handler claiming it.
+    pub fn spurious(&self) -> u64 {
+        self.spurious.load(Ordering::Relaxed)
+    }
+
+    /// Handler invocations returning an error.
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    /// The longest call of the handlers.
+    pub fn max_duration(&self) -> Duration {
+        arch_time::ticks_to_duration(self.max_ticks.load(Ordering::Relaxed))
+    }
+
+    /// The average call of the handlers, zero before the first one.
+    pub fn avg_duration(&self) -> Duration {
+        let total = self.total_ticks.load(Ordering::Relaxed);
+        let avg = total.checked_div(self.count()).unwrap_or(0);
+        arch_time::ticks_to_duration(avg)
+    }
+
+    /// Whether the IRQ was never signalled.
+    pub fn is_unused(&self) -> bool {
+        self.count() == 0 && self.spurious() == 0
+    }
+}
+
+impl Default for IRQStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for IRQStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} calls, {} spurious, {} errors, max {:?}, avg {:?}",
+            self.count(),
+            self.spurious(),
+            self.errors(),
+            self.max_duration(),
+            self.avg_duration()
+        )
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::exception::asynchronous::{interface::IRQHandler, IRQHandlerDescriptor},
+    };
+
+    struct Failing;
+
+    impl IRQHandler for Failing {
+        fn handle(&self) -> Result<HandlerResult, &'static str> {
+            Err("Failing on purpose")
+        }
+    }
+
+    struct NotMine;
+
+    impl IRQHandler for NotMine {
+        fn handle(&self) -> Result<HandlerResult, &'static str> {
+            Ok(HandlerResult::Unhandled)
+        }
+    }
+
+    static FAILING: Failing = Failing;
+    static NOT_MINE: NotMine = NotMine;
+
+    #[test_case]
+    fn dispatch_is_accounted() {
+        let stats = IRQStats::new();
+        let mut chain = IRQHandlerChain::new();
+
+        stats.dispatch(&chain);
+        assert_eq!(stats.spurious(), 1);
+
+        chain
+            .register(IRQHandlerDescriptor::new(0usize, "Not mine", &NOT_MINE))
+            .unwrap();
+        stats.dispatch(&chain);
+        assert_eq!(stats.spurious(), 2);
+
+        chain
+            .register(IRQHandlerDescriptor::new(0usize, "Failing", &FAILING))
+            .unwrap();
+        stats.dispatch(&chain);
+
+        assert_eq!(stats.count(), 2);
+        assert_eq!(stats.errors(), 1);
+        assert_eq!(stats.spurious(), 2);
+        assert!(stats.avg_duration() <= stats.max_duration());
+    }
+
+    #[test_case]
+    fn unused_stats_average_zero() {
+        let stats = IRQStats::new();
+        assert!(stats.is_unused());
+        assert_eq!(stats.avg_duration(), Duration::ZERO);
+    }
+}
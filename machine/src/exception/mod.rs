@@ -12,7 +12,10 @@ pub mod asynchronous;
 //--------------------------------------------------------------------------------------------------
 // Architectural Public Reexports
 //--------------------------------------------------------------------------------------------------
-pub use arch_exception::{current_privilege_level, handling_init};
+pub use arch_exception::{
+    current_privilege_level, handling_init, register_sync_handler, syndrome::ExceptionClass,
+    unregister_sync_handler, ExceptionContext, SyncHandler,
+};
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -28,6 +31,15 @@ pub enum PrivilegeLevel {
     Unknown,
 }
 
+/// Outcome of a synchronous exception handler.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum HandlerResult {
+    /// The exception was dealt with, execution resumes at the context's ELR.
+    Handled,
+    /// Not for this handler, the exception falls through to the next stage.
+    Unhandled,
+}
+
 //--------------------------------------------------------------------------------------------------
 // Testing
 //--------------------------------------------------------------------------------------------------
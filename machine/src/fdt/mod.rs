@@ -0,0 +1,420 @@
+/*
+ * SPDX-License-Identifier: BlueOak-1.0.0
+ * Copyright (c) Berkus Decker <berkus+vesper@metta.systems>
+ */
+
+//! Flattened device tree (DTB) parser.
+//!
+//! Walks a device tree blob in place, without allocation.
+//! Format reference: <https://devicetree-specification.readthedocs.io/en/stable/flattened-format.html>
+
+use snafu::Snafu;
+
+mod node;
+
+pub use node::{
+    CellSizes, Chosen, Compatible, Node, NodeIter, Property, PropertyIter, Range, RangeIter,
+    RegIter, Region, StrList,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Magic value at the start of every DTB.
+pub const FDT_MAGIC: u32 = 0xd00d_feed;
+
+/// Oldest version of the format this parser understands.
+const FDT_LAST_COMP_VERSION: u32 = 16;
+
+/// Errors reported while validating a device tree blob.
+#[derive(Debug, Snafu, Copy, Clone, PartialEq, Eq)]
+pub enum FdtError {
+    #[snafu(display("DTB has bad magic {:#x}", magic))]
+    BadMagic { magic: u32 },
+    #[snafu(display("DTB version {} is not supported", version))]
+    UnsupportedVersion { version: u32 },
+    #[snafu(display("DTB is truncated"))]
+    Truncated,
+    #[snafu(display("DTB structure block is malformed"))]
+    BadStructure,
+}
+
+pub type Result<T, E = FdtError> = ::core::result::Result<T, E>;
+
+/// A parsed view of a device tree blob.
+#[derive(Copy, Clone)]
+pub struct Fdt<'a> {
+    data: &'a [u8],
+    structs: &'a [u8],
+    strings: &'a [u8],
+    mem_rsvmap: &'a [u8],
+    boot_cpuid_phys: u32,
+}
+
+/// Entry in the memory reservation block.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemoryReservation {
+    pub address: u64,
+    pub size: u64,
+}
+
+/// Iterator over the memory reservation block.
+pub struct MemoryReservationIter<'a> {
+    data: &'a [u8],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Header field offsets, all fields are big-endian u32.
+mod header {
+    pub const MAGIC: usize = 0x00;
+    pub const TOTALSIZE: usize = 0x04;
+    pub const OFF_DT_STRUCT: usize = 0x08;
+    pub const OFF_DT_STRINGS: usize = 0x0c;
+    pub const OFF_MEM_RSVMAP: usize = 0x10;
+    pub const VERSION: usize = 0x14;
+    pub const LAST_COMP_VERSION: usize = 0x18;
+    pub const BOOT_CPUID_PHYS: usize = 0x1c;
+    pub const SIZE_DT_STRINGS: usize = 0x20;
+    pub const SIZE_DT_STRUCT: usize = 0x24;
+    pub const SIZE: usize = 0x28;
+}
+
+/// Structure block tokens.
+mod token {
+    pub const BEGIN_NODE: u32 = 0x1;
+    pub const END_NODE: u32 = 0x2;
+    pub const PROP: u32 = 0x3;
+    pub const NOP: u32 = 0x4;
+    pub const END: u32 = 0x9;
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Read a big-endian u32 at `offset`, if it fits.
+fn be32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Read a big-endian u64 at `offset`, if it fits.
+fn be64(data: &[u8], offset: usize) -> Option<u64> {
+    Some((u64::from(be32(data, offset)?) << 32) | u64::from(be32(data, offset + 4)?))
+}
+
+/// Read a NUL-terminated string starting at `offset`.
+fn c_str(data: &[u8], offset: usize) -> Option<&str> {
+    let tail = data.get(offset..)?;
+    let len = tail.iter().position(|&b| b == 0)?;
+    core::str::from_utf8(&tail[..len]).ok()
+}
+
+/// Round up to the structure block alignment.
+const fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl<'a> Fdt<'a> {
+    /// Validate the header and create a view of the blob in `data`.
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        let field = |offset| be32(data, offset).ok_or(FdtError::Truncated);
+
+        let magic = field(header::MAGIC)?;
+        if magic != FDT_MAGIC {
+            return Err(FdtError::BadMagic { magic });
+        }
+        if data.len() < header::SIZE {
+            return Err(FdtError::Truncated);
+        }
+
+        let version = field(header::VERSION)?;
+        if version < FDT_LAST_COMP_VERSION
+            || field(header::LAST_COMP_VERSION)? > FDT_LAST_COMP_VERSION
+        {
+            return Err(FdtError::UnsupportedVersion { version });
+        }
+
+        let total_size = field(header::TOTALSIZE)? as usize;
+        let data = data.get(..total_size).ok_or(FdtError::Truncated)?;
+
+        let block = |offset_field, size_field| -> Result<&'a [u8]> {
+            let start = field(offset_field)? as usize;
+            let size = field(size_field)? as usize;
+            data.get(start..start.checked_add(size).ok_or(FdtError::Truncated)?)
+                .ok_or(FdtError::Truncated)
+        };
+
+        let structs = block(header::OFF_DT_STRUCT, header::SIZE_DT_STRUCT)?;
+        let strings = block(header::OFF_DT_STRINGS, header::SIZE_DT_STRINGS)?;
+        let mem_rsvmap = data
+            .get(field(header::OFF_MEM_RSVMAP)? as usize..)
+            .ok_or(FdtError::Truncated)?;
+
+        // The structure block must open with the root node and be terminated.
+        if be32(structs, 0) != Some(token::BEGIN_NODE)
+            || be32(structs, structs.len().saturating_sub(4)) != Some(token::END)
+        {
+            return Err(FdtError::BadStructure);
+        }
+
+        Ok(Self {
+            data,
+            structs,
+            strings,
+            mem_rsvmap,
+            boot_cpuid_phys: field(header::BOOT_CPUID_PHYS)?,
+        })
+    }
+
+    /// Create a view of the blob located at `addr`.
+    ///
+    /// # Safety
+    ///
+    /// - `addr` must point to readable memory holding a blob of the size recorded in its header,
+    ///   which must stay untouched for the rest of the kernel lifetime.
+    pub unsafe fn from_addr(addr: usize) -> Result<Fdt<'static>> {
+        let head = core::slice::from_raw_parts(addr as *const u8, header::SIZE);
+        if be32(head, header::MAGIC) != Some(FDT_MAGIC) {
+            return Fdt::new(head);
+        }
+        let total_size = be32(head, header::TOTALSIZE).ok_or(FdtError::Truncated)? as usize;
+        Fdt::new(core::slice::from_raw_parts(addr as *const u8, total_size))
+    }
+
+    /// Size of the whole blob in bytes.
+    pub fn total_size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Physical id of the boot CPU as recorded by the boot loader.
+    pub fn boot_cpuid_phys(&self) -> u32 {
+        self.boot_cpuid_phys
+    }
+
+    /// Entries of the memory reservation block.
+    pub fn memory_reservations(&self) -> MemoryReservationIter<'a> {
+        MemoryReservationIter {
+            data: self.mem_rsvmap,
+        }
+    }
+
+    /// The root node.
+    pub fn root(&self) -> Node<'a> {
+        // Validated in new().
+        Node::parse(*self, 0, CellSizes::default()).expect("DTB root node")
+    }
+
+    /// Find a node by its full path, like `/soc/serial@7e201000`.
+    ///
+    /// A path component without a unit address matches the first node with that name,
+    /// so `/memory` finds `/memory@0`. A path not starting with `/` is resolved
+    /// through `/aliases` first.
+    pub fn find_node(&self, path: &str) -> Option<Node<'a>> {
+        let (mut node, rest) = if path.starts_with('/') {
+            (self.root(), path)
+        } else {
+            let (alias, rest) = path.split_once('/').unwrap_or((path, ""));
+            let target = self.root().child("aliases")?.property(alias)?.as_str()?;
+            (self.find_node(target)?, rest)
+        };
+
+        for component in rest.split('/').filter(|c| !c.is_empty()) {
+            node = node.child(component)?;
+        }
+        Some(node)
+    }
+
+    /// Depth-first search for the first node matching `predicate`.
+    pub fn find(&self, mut predicate: impl FnMut(&Node<'a>) -> bool) -> Option<Node<'a>> {
+        fn walk<'a>(
+            node: Node<'a>,
+            predicate: &mut impl FnMut(&Node<'a>) -> bool,
+        ) -> Option<Node<'a>> {
+            if predicate(&node) {
+                return Some(node);
+            }
+            node.children().find_map(|child| walk(child, predicate))
+        }
+        walk(self.root(), &mut predicate)
+    }
+
+    /// Find the first node compatible with any of the given strings.
+    pub fn find_compatible(&self, with: &[&str]) -> Option<Node<'a>> {
+        self.find(|node| with.iter().any(|c| node.is_compatible(c)))
+    }
+
+    /// Find the node with the given phandle.
+    pub fn find_phandle(&self, phandle: u32) -> Option<Node<'a>> {
+        self.find(|node| node.phandle() == Some(phandle))
+    }
+
+    /// The `/chosen` node.
+    pub fn chosen(&self) -> Option<Chosen<'a>> {
+        self.find_node("/chosen").map(Chosen::new)
+    }
+
+    /// The first `/memory` node.
+    pub fn memory(&self) -> Option<Node<'a>> {
+        self.root().children().find(|node| {
+            node.property("device_type").and_then(|p| p.as_str()) == Some("memory")
+                || node.base_name() == "memory"
+        })
+    }
+}
+
+impl Iterator for MemoryReservationIter<'_> {
+    type Item = MemoryReservation;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let address = be64(self.data, 0)?;
+        let size = be64(self.data, 8)?;
+        // The block is terminated by an all-zero entry.
+        if address == 0 && size == 0 {
+            return None;
+        }
+        self.data = &self.data[16..];
+        Some(MemoryReservation { address, size })
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static RPI3: &[u8] = include_bytes!("../../../targets/bcm2710-rpi-3-b-plus.dtb");
+    static RPI4: &[u8] = include_bytes!("../../../targets/bcm2711-rpi-4-b.dtb");
+    static RPI400: &[u8] = include_bytes!("../../../targets/bcm2711-rpi-400.dtb");
+
+    /// All shipped blobs pass header validation and walk to the end.
+    #[test_case]
+    fn shipped_blobs_parse() {
+        for blob in [RPI3, RPI4, RPI400] {
+            let fdt = Fdt::new(blob).expect("valid DTB");
+            assert_eq!(fdt.total_size(), blob.len());
+            assert!(fdt.find(|_| false).is_none());
+        }
+    }
+
+    /// Garbage is rejected.
+    #[test_case]
+    fn bad_blobs_are_rejected() {
+        assert_eq!(
+            Fdt::new(&[0u8; 64]).err(),
+            Some(FdtError::BadMagic { magic: 0 })
+        );
+        assert_eq!(Fdt::new(&RPI3[..32]).err(), Some(FdtError::Truncated));
+        assert_eq!(Fdt::new(&RPI3[..1024]).err(), Some(FdtError::Truncated));
+    }
+
+    /// Root node carries the board model and compatible list.
+    #[test_case]
+    fn root_node_properties() {
+        let fdt = Fdt::new(RPI3).unwrap();
+        let root = fdt.root();
+        assert_eq!(root.name(), "");
+        assert!(root.is_compatible("brcm,bcm2837"));
+        assert!(root
+            .property("model")
+            .and_then(|p| p.as_str())
+            .unwrap()
+            .starts_with("Raspberry Pi 3 Model B+"));
+
+        let fdt = Fdt::new(RPI4).unwrap();
+        assert!(fdt.root().is_compatible("brcm,bcm2711"));
+        assert!(fdt.root().is_compatible("raspberrypi,4-model-b"));
+
+        let fdt = Fdt::new(RPI400).unwrap();
+        assert!(fdt.root().is_compatible("raspberrypi,400"));
+    }
+
+    /// Peripheral bus `ranges` translate into the CPU physical address space.
+    #[test_case]
+    fn soc_ranges_translate_to_peripheral_base() {
+        let fdt = Fdt::new(RPI3).unwrap();
+        let soc = fdt.find_node("/soc").unwrap();
+        let range = soc.ranges().unwrap().next().unwrap();
+        assert_eq!(range.child_address, 0x7e00_0000);
+        assert_eq!(range.parent_address, 0x3f00_0000);
+        assert_eq!(range.size, 0x0100_0000);
+        assert_eq!(soc.translate(0x7e20_1000), Some(0x3f20_1000));
+
+        let fdt = Fdt::new(RPI4).unwrap();
+        let soc = fdt.find_node("/soc").unwrap();
+        assert_eq!(soc.translate(0x7e20_1000), Some(0xfe20_1000));
+    }
+
+    /// `reg` decoding uses the parent's cell sizes.
+    #[test_case]
+    fn uart_reg_and_compatible_lookup() {
+        let fdt = Fdt::new(RPI3).unwrap();
+        let uart = fdt.find_compatible(&["arm,pl011"]).unwrap();
+        assert_eq!(uart.name(), "serial@7e201000");
+        let reg = uart.reg().unwrap().next().unwrap();
+        assert_eq!(reg.address, 0x7e20_1000);
+        assert_eq!(reg.size, Some(0x200));
+        assert_eq!(uart.translated_reg(0), Some((0x3f20_1000, 0x200)));
+
+        assert_eq!(
+            fdt.find_node("serial0").map(|n| n.name()),
+            Some("serial@7e215040")
+        );
+    }
+
+    /// Interrupt parent phandles resolve to the interrupt controller.
+    #[test_case]
+    fn phandle_lookup() {
+        let fdt = Fdt::new(RPI4).unwrap();
+        let gic = fdt.find_compatible(&["arm,gic-400"]).unwrap();
+        let phandle = gic.phandle().unwrap();
+        assert_eq!(fdt.find_phandle(phandle).unwrap().name(), gic.name());
+        assert!(fdt.find_phandle(0xffff_fff0).is_none());
+    }
+
+    /// Memory node and chosen node are reachable.
+    #[test_case]
+    fn memory_and_chosen() {
+        let fdt = Fdt::new(RPI3).unwrap();
+        let memory = fdt.memory().unwrap();
+        assert_eq!(memory.base_name(), "memory");
+        assert!(memory.reg().is_some());
+        assert!(fdt.chosen().is_some());
+        assert_eq!(fdt.find_node("/memory").unwrap().name(), memory.name());
+        assert_eq!(
+            fdt.memory_reservations().next(),
+            Some(MemoryReservation {
+                address: 0,
+                size: 0x1000
+            })
+        );
+    }
+
+    /// Secondary cores are described with spin-table release addresses.
+    #[test_case]
+    fn cpus_spin_table() {
+        let fdt = Fdt::new(RPI4).unwrap();
+        let cpus = fdt.find_node("/cpus").unwrap();
+        assert_eq!(cpus.children().count(), 4);
+        let cpu1 = cpus.child("cpu@1").unwrap();
+        assert_eq!(
+            cpu1.property("enable-method").and_then(|p| p.as_str()),
+            Some("spin-table")
+        );
+        assert_eq!(
+            cpu1.property("cpu-release-addr").and_then(|p| p.as_u64()),
+            Some(0xe0)
+        );
+    }
+}
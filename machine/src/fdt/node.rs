@@ -0,0 +1,474 @@
+/*
+ * SPDX-License-Identifier: BlueOak-1.0.0
+ * Copyright (c) Berkus Decker <berkus+vesper@metta.systems>
+ */
+
+//! Device tree nodes and properties.
+
+use super::{align4, be32, c_str, token, Fdt};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Values of `#address-cells` and `#size-cells` that apply to a node's children.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CellSizes {
+    pub address_cells: usize,
+    pub size_cells: usize,
+}
+
+/// A node in the structure block.
+#[derive(Copy, Clone)]
+pub struct Node<'a> {
+    fdt: Fdt<'a>,
+    name: &'a str,
+    /// Offset of the first token after the node name.
+    props_offset: usize,
+    /// Cell sizes of the parent, used to decode this node's `reg`.
+    parent_cells: CellSizes,
+}
+
+/// A property of a node.
+#[derive(Copy, Clone)]
+pub struct Property<'a> {
+    name: &'a str,
+    value: &'a [u8],
+}
+
+/// Iterator over a node's properties.
+pub struct PropertyIter<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+}
+
+/// Iterator over a node's direct children.
+pub struct NodeIter<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+    cells: CellSizes,
+}
+
+/// Iterator over a string list property value.
+#[derive(Clone)]
+pub struct StrList<'a> {
+    data: &'a [u8],
+}
+
+/// Value of a `compatible` property.
+pub type Compatible<'a> = StrList<'a>;
+
+/// A decoded `reg` entry.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub address: u64,
+    /// `None` when the parent has `#size-cells = <0>`.
+    pub size: Option<u64>,
+}
+
+/// Iterator over `reg` entries.
+pub struct RegIter<'a> {
+    data: &'a [u8],
+    cells: CellSizes,
+}
+
+/// A decoded `ranges` entry.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Range {
+    pub child_address: u64,
+    pub parent_address: u64,
+    pub size: u64,
+}
+
+/// Iterator over `ranges` entries.
+pub struct RangeIter<'a> {
+    data: &'a [u8],
+    child_cells: CellSizes,
+    parent_address_cells: usize,
+}
+
+/// Accessors for the `/chosen` node.
+#[derive(Copy, Clone)]
+pub struct Chosen<'a> {
+    node: Node<'a>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Read a number made of `cells` big-endian u32 cells. Values over 64 bits keep the low part.
+fn read_cells(data: &[u8], cells: usize) -> Option<u64> {
+    (0..cells).try_fold(0u64, |acc, i| {
+        Some(acc.checked_shl(32).unwrap_or(0) | u64::from(be32(data, i * 4)?))
+    })
+}
+
+/// Skip NOP tokens starting at `offset`.
+fn skip_nops(structs: &[u8], mut offset: usize) -> usize {
+    while be32(structs, offset) == Some(token::NOP) {
+        offset += 4;
+    }
+    offset
+}
+
+/// Skip the node starting with BEGIN_NODE at `offset`, return offset past its END_NODE.
+fn skip_node(structs: &[u8], mut offset: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    loop {
+        match be32(structs, offset)? {
+            token::BEGIN_NODE => {
+                depth += 1;
+                offset = align4(offset + 4 + c_str(structs, offset + 4)?.len() + 1);
+            }
+            token::END_NODE => {
+                depth -= 1;
+                offset += 4;
+                if depth == 0 {
+                    return Some(offset);
+                }
+            }
+            token::PROP => {
+                let len = be32(structs, offset + 4)? as usize;
+                offset = align4(offset + 12 + len);
+            }
+            token::NOP => offset += 4,
+            _ => return None,
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Default for CellSizes {
+    /// Defaults mandated by the specification when the properties are absent.
+    fn default() -> Self {
+        Self {
+            address_cells: 2,
+            size_cells: 1,
+        }
+    }
+}
+
+impl<'a> Node<'a> {
+    /// Parse the node whose BEGIN_NODE token is at `offset` in the structure block.
+    pub(super) fn parse(fdt: Fdt<'a>, offset: usize, parent_cells: CellSizes) -> Option<Self> {
+        if be32(fdt.structs, offset)? != token::BEGIN_NODE {
+            return None;
+        }
+        let name = c_str(fdt.structs, offset + 4)?;
+        Some(Self {
+            fdt,
+            name,
+            props_offset: align4(offset + 4 + name.len() + 1),
+            parent_cells,
+        })
+    }
+
+    /// Full node name, including the unit address.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Node name without the unit address.
+    pub fn base_name(&self) -> &'a str {
+        self.name.split('@').next().unwrap_or(self.name)
+    }
+
+    /// Unit address part of the name, if any.
+    pub fn unit_address(&self) -> Option<&'a str> {
+        self.name.split_once('@').map(|(_, addr)| addr)
+    }
+
+    /// Iterate over all properties of the node.
+    pub fn properties(&self) -> PropertyIter<'a> {
+        PropertyIter {
+            fdt: self.fdt,
+            offset: self.props_offset,
+        }
+    }
+
+    /// Find a property by name.
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|p| p.name == name)
+    }
+
+    /// Iterate over the direct children of the node.
+    pub fn children(&self) -> NodeIter<'a> {
+        // Children follow all the properties.
+        let mut props = self.properties();
+        while props.next().is_some() {}
+        NodeIter {
+            fdt: self.fdt,
+            offset: props.offset,
+            cells: self.cell_sizes(),
+        }
+    }
+
+    /// Find a direct child by name. A name without unit address matches any unit address.
+    pub fn child(&self, name: &str) -> Option<Node<'a>> {
+        let with_unit = name.contains('@');
+        self.children()
+            .find(|child| child.name == name || (!with_unit && child.base_name() == name))
+    }
+
+    /// Cell sizes this node specifies for its children.
+    pub fn cell_sizes(&self) -> CellSizes {
+        let default = CellSizes::default();
+        let cells = |name, default| {
+            self.property(name)
+                .and_then(|p| p.as_u32())
+                .map_or(default, |v| v as usize)
+        };
+        CellSizes {
+            address_cells: cells("#address-cells", default.address_cells),
+            size_cells: cells("#size-cells", default.size_cells),
+        }
+    }
+
+    /// The `compatible` string list.
+    pub fn compatible(&self) -> Option<Compatible<'a>> {
+        self.property("compatible").map(|p| p.as_str_list())
+    }
+
+    /// Check whether `compatible` contains the given string.
+    pub fn is_compatible(&self, with: &str) -> bool {
+        self.compatible().is_some_and(|mut c| c.any(|s| s == with))
+    }
+
+    /// The node's phandle, if it has one.
+    pub fn phandle(&self) -> Option<u32> {
+        self.property("phandle")
+            .or_else(|| self.property("linux,phandle"))
+            .and_then(|p| p.as_u32())
+    }
+
+    /// Decode the `reg` property using the parent's cell sizes.
+    pub fn reg(&self) -> Option<RegIter<'a>> {
+        self.property("reg").map(|p| RegIter {
+            data: p.value,
+            cells: self.parent_cells,
+        })
+    }
+
+    /// Decode the `ranges` property. An empty `ranges` (identity mapping) yields no entries.
+    pub fn ranges(&self) -> Option<RangeIter<'a>> {
+        self.property("ranges").map(|p| RangeIter {
+            data: p.value,
+            child_cells: self.cell_sizes(),
+            parent_address_cells: self.parent_cells.address_cells,
+        })
+    }
+
+    /// Translate an address from this node's child bus into its parent's address space.
+    ///
+    /// Returns the address as is for an empty `ranges`, `None` if no range covers it
+    /// or the node has no `ranges` at all.
+    pub fn translate(&self, address: u64) -> Option<u64> {
+        let ranges = self.property("ranges")?;
+        if ranges.value.is_empty() {
+            return Some(address);
+        }
+        self.ranges()?.find_map(|r| {
+            let offset = address.checked_sub(r.child_address)?;
+            (offset < r.size).then(|| r.parent_address + offset)
+        })
+    }
+
+    /// Return `(address, size)` of the `index`th `reg` entry, translated through the
+    /// `ranges` of the nodes on the path from the root to this node.
+    ///
+    /// Nodes don't keep parent links, so the ancestors are found by walking from the root.
+    pub fn translated_reg(&self, index: usize) -> Option<(u64, u64)> {
+        let region = self.reg()?.nth(index)?;
+        let mut address = region.address;
+
+        // Collect the ancestors of this node, innermost last.
+        const MAX_DEPTH: usize = 16;
+        let mut path: [Option<Node<'a>>; MAX_DEPTH] = [None; MAX_DEPTH];
+        let mut depth = 0;
+        if !self
+            .fdt
+            .root()
+            .find_path_to(self.props_offset, &mut path, &mut depth)
+        {
+            return None;
+        }
+
+        // Translate upwards, skipping the root itself which has no parent bus. The root's own
+        // `reg`, with no ancestors at all, is returned untranslated.
+        let ancestors = path.get(1..depth).unwrap_or(&[]);
+        for ancestor in ancestors.iter().rev().flatten() {
+            address = ancestor.translate(address)?;
+        }
+        Some((address, region.size.unwrap_or(0)))
+    }
+
+    /// Record the chain of ancestors leading to the node at `target`.
+    fn find_path_to(
+        &self,
+        target: usize,
+        path: &mut [Option<Node<'a>>],
+        depth: &mut usize,
+    ) -> bool {
+        if self.props_offset == target {
+            return true;
+        }
+        if *depth >= path.len() {
+            return false;
+        }
+        path[*depth] = Some(*self);
+        *depth += 1;
+        for child in self.children() {
+            if child.find_path_to(target, path, depth) {
+                return true;
+            }
+        }
+        *depth -= 1;
+        false
+    }
+}
+
+impl<'a> Iterator for PropertyIter<'a> {
+    type Item = Property<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let structs = self.fdt.structs;
+        self.offset = skip_nops(structs, self.offset);
+        if be32(structs, self.offset)? != token::PROP {
+            return None;
+        }
+        let len = be32(structs, self.offset + 4)? as usize;
+        let name_offset = be32(structs, self.offset + 8)? as usize;
+        let start = self.offset + 12;
+        let value = structs.get(start..start + len)?;
+        let name = c_str(self.fdt.strings, name_offset)?;
+        self.offset = align4(start + len);
+        Some(Property { name, value })
+    }
+}
+
+impl<'a> Iterator for NodeIter<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let structs = self.fdt.structs;
+        self.offset = skip_nops(structs, self.offset);
+        let node = Node::parse(self.fdt, self.offset, self.cells)?;
+        self.offset = skip_node(structs, self.offset)?;
+        Some(node)
+    }
+}
+
+impl<'a> Property<'a> {
+    /// Property name.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Raw property value.
+    pub fn value(&self) -> &'a [u8] {
+        self.value
+    }
+
+    /// Value as a single u32 cell.
+    pub fn as_u32(&self) -> Option<u32> {
+        (self.value.len() == 4)
+            .then(|| be32(self.value, 0))
+            .flatten()
+    }
+
+    /// Value as one or two cells.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.value.len() {
+            4 | 8 => read_cells(self.value, self.value.len() / 4),
+            _ => None,
+        }
+    }
+
+    /// Value as a NUL-terminated string.
+    pub fn as_str(&self) -> Option<&'a str> {
+        c_str(self.value, 0)
+    }
+
+    /// Value as a list of NUL-terminated strings.
+    pub fn as_str_list(&self) -> StrList<'a> {
+        StrList { data: self.value }
+    }
+}
+
+impl<'a> Iterator for StrList<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let s = c_str(self.data, 0)?;
+        self.data = &self.data[s.len() + 1..];
+        Some(s)
+    }
+}
+
+impl Iterator for RegIter<'_> {
+    type Item = Region;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (address_cells, size_cells) = (self.cells.address_cells, self.cells.size_cells);
+        let entry = (address_cells + size_cells) * 4;
+        if entry == 0 || self.data.len() < entry {
+            return None;
+        }
+        let address = read_cells(self.data, address_cells)?;
+        let size = if size_cells == 0 {
+            None
+        } else {
+            Some(read_cells(&self.data[address_cells * 4..], size_cells)?)
+        };
+        self.data = &self.data[entry..];
+        Some(Region { address, size })
+    }
+}
+
+impl Iterator for RangeIter<'_> {
+    type Item = Range;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let child = self.child_cells.address_cells * 4;
+        let parent = self.parent_address_cells * 4;
+        let size = self.child_cells.size_cells * 4;
+        if self.data.len() < child + parent + size || child + parent + size == 0 {
+            return None;
+        }
+        let range = Range {
+            child_address: read_cells(self.data, self.child_cells.address_cells)?,
+            parent_address: read_cells(&self.data[child..], self.parent_address_cells)?,
+            size: read_cells(&self.data[child + parent..], self.child_cells.size_cells)?,
+        };
+        self.data = &self.data[child + parent + size..];
+        Some(range)
+    }
+}
+
+impl<'a> Chosen<'a> {
+    pub(super) fn new(node: Node<'a>) -> Self {
+        Self { node }
+    }
+
+    /// The underlying node.
+    pub fn node(&self) -> Node<'a> {
+        self.node
+    }
+
+    /// Kernel command line.
+    pub fn bootargs(&self) -> Option<&'a str> {
+        self.node.property("bootargs").and_then(|p| p.as_str())
+    }
+
+    /// Path of the console device, without the `:options` suffix.
+    pub fn stdout_path(&self) -> Option<&'a str> {
+        self.node
+            .property("stdout-path")
+            .and_then(|p| p.as_str())
+            .map(|s| s.split(':').next().unwrap_or(s))
+    }
+}
@@ -38,12 +38,14 @@ use architecture_not_supported_sorry;
 /// Architecture-specific code.
 #[macro_use]
 pub mod arch;
+pub mod backtrace;
 pub mod console;
 pub mod cpu;
 pub mod debug;
 pub mod devices;
 pub mod drivers;
 pub mod exception;
+pub mod fdt;
 pub mod macros;
 pub mod memory;
 mod mm;
@@ -51,6 +53,7 @@ pub mod panic;
 pub mod platform;
 pub mod qemu;
 pub mod state;
+pub mod symbols;
 mod synchronization;
 pub mod tests;
 pub mod time;
@@ -97,20 +100,24 @@ mod lib_tests {
 
     /// Main for running tests.
     #[no_mangle]
-    pub unsafe fn main() -> ! {
+    pub unsafe fn main(boot_info: cpu::BootInfo) -> ! {
         exception::handling_init();
 
+        platform::init_from_device_tree(boot_info.dtb_phys_addr());
+
         let phys_kernel_tables_base_addr = match memory::mmu::kernel_map_binary() {
             Err(string) => panic!("Error mapping kernel binary: {}", string),
             Ok(addr) => addr,
         };
 
-        if let Err(e) = memory::mmu::enable_mmu_and_caching(phys_kernel_tables_base_addr) {
-            panic!("Enabling MMU failed: {}", e);
-        }
+        memory::mmu::switch_to_kernel_tables(phys_kernel_tables_base_addr);
 
         memory::mmu::post_enable_init();
         platform::drivers::qemu_bring_up_console();
+        platform::drivers::qemu_bring_up_interrupts();
+
+        // Run the tests in the state the kernel's main code runs in.
+        state::state_manager().transition_to_single_core_main();
 
         test_main();
 
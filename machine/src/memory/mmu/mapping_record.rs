@@ -11,7 +11,7 @@ use {
     },
     crate::{
         info, mm, platform,
-        synchronization::{self, InitStateLock},
+        synchronization::{self, RwSpinLock},
         warn,
     },
 };
@@ -32,15 +32,14 @@ struct MappingRecordEntry {
 }
 
 struct MappingRecord {
-    inner: [Option<MappingRecordEntry>; 12],
+    inner: [Option<MappingRecordEntry>; 24],
 }
 
 //--------------------------------------------------------------------------------------------------
 // Global instances
 //--------------------------------------------------------------------------------------------------
 
-static KERNEL_MAPPING_RECORD: InitStateLock<MappingRecord> =
-    InitStateLock::new(MappingRecord::new());
+static KERNEL_MAPPING_RECORD: RwSpinLock<MappingRecord> = RwSpinLock::new(MappingRecord::new());
 
 //--------------------------------------------------------------------------------------------------
 // Private Code
@@ -79,7 +78,7 @@ impl MappingRecordEntry {
 
 impl MappingRecord {
     pub const fn new() -> Self {
-        Self { inner: [None; 12] }
+        Self { inner: [None; 24] }
     }
 
     fn size(&self) -> usize {
@@ -131,6 +130,11 @@ impl MappingRecord {
         phys_region: &MemoryRegion<Physical>,
         attr: &AttributeFields,
     ) -> Result<(), &'static str> {
+        // The kernel only maps into the top half, the bottom half belongs to user space.
+        if virt_region.start_addr().as_usize() < platform::memory::mmu::KERNEL_VIRT_OFFSET {
+            return Err("Kernel mapping outside of the kernel's top half");
+        }
+
         let x = self.find_next_free()?;
 
         *x = Some(MappingRecordEntry::new(
@@ -149,7 +153,7 @@ impl MappingRecord {
         info!("      -------------------------------------------------------------------------------------------------------------------------------------------");
         info!(
             "      {:^44}     {:^30}   {:^7}   {:^9}   {:^35}",
-            "Virtual", "Physical", "Size", "Attr", "Entity"
+            "Virtual (TTBR1)", "Physical", "Size", "Attr", "Entity"
         );
         info!("      -------------------------------------------------------------------------------------------------------------------------------------------");
 
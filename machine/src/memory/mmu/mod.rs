@@ -48,14 +48,34 @@ pub mod interface {
     pub trait MMU {
         /// Turns on the MMU for the first time and enables data and instruction caching.
         ///
+        /// The identity tables translate the bottom half of the address space, which keeps
+        /// the caller running at its physical address. The kernel tables translate the top
+        /// half, where the kernel binary is linked.
+        ///
         /// # Safety
         ///
         /// - Changes the hardware's global state.
         unsafe fn enable_mmu_and_caching(
             &self,
-            phys_tables_base_addr: Address<Physical>,
+            phys_identity_tables_base_addr: Address<Physical>,
+            phys_kernel_tables_base_addr: Address<Physical>,
         ) -> Result<(), MMUEnableError>;
 
+        /// Replace the translation tables of the kernel's top half.
+        ///
+        /// # Safety
+        ///
+        /// - The identity tables must still be in place, the switch runs from them.
+        /// - The new tables must map the kernel binary at its link addresses.
+        unsafe fn switch_kernel_tables(&self, phys_tables_base_addr: Address<Physical>);
+
+        /// Stop translating the bottom half of the address space, dropping the identity map.
+        ///
+        /// # Safety
+        ///
+        /// - Nothing in the bottom half may be accessed afterwards.
+        unsafe fn disable_identity_map(&self);
+
         /// Returns true if the MMU is enabled, false otherwise.
         fn is_enabled(&self) -> bool;
 
@@ -71,6 +91,11 @@ pub struct AddressSpace<const AS_SIZE: usize>;
 
 /// Intended to be implemented for [`AddressSpace`].
 pub trait AssociatedTranslationTable {
+    /// A translation table whose address range is:
+    ///
+    /// [u64::MAX, (u64::MAX - AS_SIZE) + 1]
+    type TableStartFromTop;
+
     /// A translation table whose address range is:
     ///
     /// [AS_SIZE - 1, 0]
@@ -107,7 +132,7 @@ unsafe fn kernel_map_at_unchecked(
     attr: &AttributeFields,
 ) -> Result<(), &'static str> {
     platform::memory::mmu::kernel_translation_tables()
-        .write(|tables| tables.map_at(virt_region, phys_region, attr))?;
+        .lock(|tables| tables.map_at(virt_region, phys_region, attr))?;
 
     if let Err(x) = mapping_record::kernel_add(name, virt_region, phys_region, attr) {
         warn!("{}", x);
@@ -234,7 +259,7 @@ pub unsafe fn kernel_map_mmio(
 /// - See [`bsp::memory::mmu::kernel_map_binary()`].
 pub unsafe fn kernel_map_binary() -> Result<Address<Physical>, &'static str> {
     let phys_kernel_tables_base_addr =
-        platform::memory::mmu::kernel_translation_tables().write(|tables| {
+        platform::memory::mmu::kernel_translation_tables().lock(|tables| {
             tables.init();
             tables.phys_base_address()
         });
@@ -244,16 +269,20 @@ pub unsafe fn kernel_map_binary() -> Result<Address<Physical>, &'static str> {
     Ok(phys_kernel_tables_base_addr)
 }
 
-/// Enable the MMU and data + instruction caching.
+/// Switch the kernel to the tables built by [`kernel_map_binary`] and drop the boot identity map.
+///
+/// The MMU is already on at this point, the boot code enables it with minimal tables to get the
+/// kernel running in the top half. Afterwards the bottom half, and TTBR0 with it, is free for
+/// user space.
 ///
 /// # Safety
 ///
 /// - Crucial function during kernel init. Changes the the complete memory view of the processor.
+/// - Nothing reachable only through the identity map, like the device tree, may be used after.
 #[inline]
-pub unsafe fn enable_mmu_and_caching(
-    phys_tables_base_addr: Address<Physical>,
-) -> Result<(), MMUEnableError> {
-    arch_mmu::mmu().enable_mmu_and_caching(phys_tables_base_addr)
+pub unsafe fn switch_to_kernel_tables(phys_tables_base_addr: Address<Physical>) {
+    arch_mmu::mmu().switch_kernel_tables(phys_tables_base_addr);
+    arch_mmu::mmu().disable_identity_map();
 }
 
 /// Finish initialization of the MMU subsystem.
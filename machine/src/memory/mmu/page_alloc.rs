@@ -8,7 +8,7 @@ use {
     super::MemoryRegion,
     crate::{
         memory::{AddressType, Virtual},
-        synchronization::IRQSafeNullLock,
+        synchronization::IRQSafeSpinLock,
         warn,
     },
     core::num::NonZeroUsize,
@@ -27,15 +27,15 @@ pub struct PageAllocator<ATYPE: AddressType> {
 // Global instances
 //--------------------------------------------------------------------------------------------------
 
-static KERNEL_MMIO_VA_ALLOCATOR: IRQSafeNullLock<PageAllocator<Virtual>> =
-    IRQSafeNullLock::new(PageAllocator::new());
+static KERNEL_MMIO_VA_ALLOCATOR: IRQSafeSpinLock<PageAllocator<Virtual>> =
+    IRQSafeSpinLock::new(PageAllocator::new());
 
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
 /// Return a reference to the kernel's MMIO virtual address allocator.
-pub fn kernel_mmio_va_allocator() -> &'static IRQSafeNullLock<PageAllocator<Virtual>> {
+pub fn kernel_mmio_va_allocator() -> &'static IRQSafeSpinLock<PageAllocator<Virtual>> {
     &KERNEL_MMIO_VA_ALLOCATOR
 }
 
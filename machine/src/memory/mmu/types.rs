@@ -194,6 +194,11 @@ impl<ATYPE: AddressType> MemoryRegion<ATYPE> {
             || self_range.contains(&other_region.end_inclusive_page_addr())
     }
 
+    /// Returns the range of addresses covered by this region.
+    pub fn addr_range(&self) -> Range<usize> {
+        self.start_addr().as_usize()..self.end_exclusive.into_inner().as_usize()
+    }
+
     /// Returns the number of pages contained in this region.
     pub fn num_pages(&self) -> usize {
         PageAddress::steps_between(&self.start, &self.end_exclusive).unwrap()
@@ -14,6 +14,9 @@ use {
 };
 
 pub mod mmu;
+pub mod probe;
+
+pub use probe::{probe_read, probe_write, Fault};
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
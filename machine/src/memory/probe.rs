@@ -0,0 +1,172 @@
+/*
+ * SPDX-License-Identifier: BlueOak-1.0.0
+ * Copyright (c) Berkus Decker <berkus+vesper@metta.systems>
+ */
+
+//! Memory accesses that may fault.
+//!
+//! A fault on a probed address, unmapped or not permitted, is reported back to the caller
+//! instead of ending up in the exception handler's panic.
+
+#[cfg(target_arch = "aarch64")]
+use crate::arch::aarch64::memory::probe as arch_probe;
+use {
+    super::{Address, Virtual},
+    core::{fmt, mem::MaybeUninit},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A probed access faulted.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Fault {
+    address: Address<Virtual>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Copy `size` bytes from `src`, which may fault, to `dst`.
+///
+/// Naturally aligned 1, 2, 4 and 8 byte sizes are read in one access, everything else
+/// byte by byte.
+unsafe fn read_bytes(src: usize, dst: *mut u8, size: usize) -> Result<(), Fault> {
+    let fault = || Fault {
+        address: Address::new(src),
+    };
+
+    match size {
+        1 => dst.write(arch_probe::read_u8(src).ok_or_else(fault)?),
+        2 if src % 2 == 0 => dst
+            .cast::<u16>()
+            .write_unaligned(arch_probe::read_u16(src).ok_or_else(fault)?),
+        4 if src % 4 == 0 => dst
+            .cast::<u32>()
+            .write_unaligned(arch_probe::read_u32(src).ok_or_else(fault)?),
+        8 if src % 8 == 0 => dst
+            .cast::<u64>()
+            .write_unaligned(arch_probe::read_u64(src).ok_or_else(fault)?),
+        _ => {
+            for offset in 0..size {
+                dst.add(offset)
+                    .write(arch_probe::read_u8(src + offset).ok_or_else(fault)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Copy `size` bytes from `src` to `dst`, which may fault.
+///
+/// Same access sizes as [`read_bytes`].
+unsafe fn write_bytes(src: *const u8, dst: usize, size: usize) -> Result<(), Fault> {
+    let written = match size {
+        1 => arch_probe::write_u8(dst, src.read()),
+        2 if dst % 2 == 0 => arch_probe::write_u16(dst, src.cast::<u16>().read_unaligned()),
+        4 if dst % 4 == 0 => arch_probe::write_u32(dst, src.cast::<u32>().read_unaligned()),
+        8 if dst % 8 == 0 => arch_probe::write_u64(dst, src.cast::<u64>().read_unaligned()),
+        _ => (0..size).all(|offset| arch_probe::write_u8(dst + offset, src.add(offset).read())),
+    };
+
+    if written {
+        Ok(())
+    } else {
+        Err(Fault {
+            address: Address::new(dst),
+        })
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Fault {
+    /// Start of the probed access.
+    pub fn address(&self) -> Address<Virtual> {
+        self.address
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Memory access at {} faulted", self.address)
+    }
+}
+
+/// Read a `T` from `addr`, or report the fault if it isn't readable.
+///
+/// # Safety
+///
+/// - Any bit pattern read must be a valid `T`.
+/// - Reads may have side effects on device memory.
+pub unsafe fn probe_read<T: Copy>(addr: Address<Virtual>) -> Result<T, Fault> {
+    let mut value = MaybeUninit::<T>::uninit();
+    read_bytes(
+        addr.as_usize(),
+        value.as_mut_ptr().cast(),
+        core::mem::size_of::<T>(),
+    )?;
+    Ok(value.assume_init())
+}
+
+/// Write `value` to `addr`, or report the fault if it isn't writable.
+///
+/// A faulting multi-byte write may have stored some of its bytes.
+///
+/// # Safety
+///
+/// - Anything at all may live at `addr`, it is overwritten.
+pub unsafe fn probe_write<T: Copy>(addr: Address<Virtual>, value: T) -> Result<(), Fault> {
+    write_bytes(
+        (&value as *const T).cast(),
+        addr.as_usize(),
+        core::mem::size_of::<T>(),
+    )
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lower half addresses are not translated once the kernel runs on its own tables.
+    const UNMAPPED: Address<Virtual> = Address::new(3 * 1024 * 1024 * 1024);
+
+    #[test_case]
+    fn probe_of_unmapped_address_faults() {
+        assert_eq!(
+            unsafe { probe_read::<u64>(UNMAPPED) },
+            Err(Fault { address: UNMAPPED })
+        );
+        assert!(unsafe { probe_write(UNMAPPED, 0u32) }.is_err());
+    }
+
+    #[test_case]
+    fn probe_of_mapped_address_succeeds() {
+        let mut slot = [0u8; 16];
+        let base = slot.as_mut_ptr() as usize;
+
+        unsafe {
+            probe_write(Address::new(base), 0x1122_3344_5566_7788u64).unwrap();
+            // Unaligned, goes byte by byte.
+            probe_write(Address::new(base + 9), 0xaabb_ccddu32).unwrap();
+
+            assert_eq!(
+                probe_read::<u64>(Address::new(base)),
+                Ok(0x1122_3344_5566_7788)
+            );
+            assert_eq!(probe_read::<u32>(Address::new(base + 9)), Ok(0xaabb_ccdd));
+            assert_eq!(
+                probe_read::<[u8; 3]>(Address::new(base + 1)),
+                Ok([0x77, 0x66, 0x55])
+            );
+        }
+    }
+}
@@ -1,5 +1,5 @@
 //! A panic handler for hardware and for QEMU.
-use core::panic::PanicInfo;
+use {crate::backtrace::Backtrace, core::panic::PanicInfo};
 
 fn print_panic_info(info: &PanicInfo) {
     let (location, line, column) = match info.location() {
@@ -17,6 +17,7 @@ fn print_panic_info(info: &PanicInfo) {
         column,
         info.message().unwrap_or(&format_args!("")),
     );
+    crate::info!("\n{}", Backtrace::capture());
 }
 
 pub fn handler(info: &PanicInfo) -> ! {
@@ -1 +1,198 @@
+//! BSP Processor code.
+
+use crate::{
+    fdt::Fdt,
+    synchronization::{interface::ReadWriteEx, InitStateLock},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Used by `arch` code to find the early boot core.
 pub const BOOT_CORE_ID: u64 = 0;
+
+/// Number of cores on all supported boards.
+pub const MAX_CORES: usize = 4;
+
+/// Conduit for PSCI calls.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PsciConduit {
+    Hvc,
+    Smc,
+}
+
+/// How a secondary core is released from the firmware.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EnableMethod {
+    /// Firmware spins on `release_addr` waiting for an entry point to be written there.
+    SpinTable { release_addr: usize },
+    /// Firmware implements PSCI CPU_ON.
+    Psci {
+        conduit: PsciConduit,
+        cpu_on: u32,
+        mpidr: u64,
+    },
+    /// Core is absent or can't be started.
+    None,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Spin table location used by the Raspberry Pi armstubs and QEMU.
+const SPIN_TABLE_BASE: usize = 0xd8;
+
+/// PSCI 0.2+ CPU_ON function id, SMC64 calling convention.
+const PSCI_CPU_ON_64: u32 = 0xc400_0003;
+
+struct CpuTopology {
+    num_cores: usize,
+    enable_methods: [EnableMethod; MAX_CORES],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static CPU_TOPOLOGY: InitStateLock<CpuTopology> = InitStateLock::new(CpuTopology::built_in());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl CpuTopology {
+    /// All cores present and parked on the armstub spin table.
+    const fn built_in() -> Self {
+        Self {
+            num_cores: MAX_CORES,
+            enable_methods: [
+                EnableMethod::SpinTable {
+                    release_addr: SPIN_TABLE_BASE,
+                },
+                EnableMethod::SpinTable {
+                    release_addr: SPIN_TABLE_BASE + 8,
+                },
+                EnableMethod::SpinTable {
+                    release_addr: SPIN_TABLE_BASE + 16,
+                },
+                EnableMethod::SpinTable {
+                    release_addr: SPIN_TABLE_BASE + 24,
+                },
+            ],
+        }
+    }
+
+    fn from_device_tree(fdt: &Fdt) -> Option<Self> {
+        let cpus = fdt.find_node("/cpus")?;
+        let psci = fdt.find_compatible(&["arm,psci-1.0", "arm,psci-0.2", "arm,psci"]);
+
+        let mut this = Self {
+            num_cores: 0,
+            enable_methods: [EnableMethod::None; MAX_CORES],
+        };
+
+        for cpu in cpus
+            .children()
+            .filter(|node| node.property("device_type").and_then(|p| p.as_str()) == Some("cpu"))
+        {
+            let Some(mpidr) = cpu.reg().and_then(|mut reg| reg.next()).map(|r| r.address) else {
+                continue;
+            };
+            let core = (mpidr & 0x3) as usize;
+            if core >= MAX_CORES {
+                continue;
+            }
+
+            let method = cpu.property("enable-method").and_then(|p| p.as_str());
+            this.enable_methods[core] = match method {
+                Some("psci") => {
+                    let Some(psci) = psci else {
+                        continue;
+                    };
+                    let conduit = match psci.property("method").and_then(|p| p.as_str()) {
+                        Some("hvc") => PsciConduit::Hvc,
+                        _ => PsciConduit::Smc,
+                    };
+                    EnableMethod::Psci {
+                        conduit,
+                        cpu_on: psci
+                            .property("cpu_on")
+                            .and_then(|p| p.as_u32())
+                            .unwrap_or(PSCI_CPU_ON_64),
+                        mpidr,
+                    }
+                }
+                // Missing release address means the armstub default.
+                Some("spin-table") | None => EnableMethod::SpinTable {
+                    release_addr: cpu
+                        .property("cpu-release-addr")
+                        .and_then(|p| p.as_u64())
+                        .map_or(SPIN_TABLE_BASE + core * 8, |addr| addr as usize),
+                },
+                Some(_) => EnableMethod::None,
+            };
+            this.num_cores += 1;
+        }
+
+        (this.num_cores > 0).then_some(this)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Record the CPU topology described by the device tree.
+///
+/// # Safety
+///
+/// - Must be called during kernel init.
+pub(super) unsafe fn init(fdt: &Fdt) {
+    if let Some(topology) = CpuTopology::from_device_tree(fdt) {
+        CPU_TOPOLOGY.write(|cpus| *cpus = topology);
+    }
+}
+
+/// Number of cores present on the board.
+pub fn num_cores() -> usize {
+    CPU_TOPOLOGY.read(|cpus| cpus.num_cores)
+}
+
+/// Ids of the cores present on the board that can be started.
+pub fn core_ids() -> impl Iterator<Item = u64> {
+    let enable_methods = CPU_TOPOLOGY.read(|cpus| cpus.enable_methods);
+    (0..MAX_CORES as u64).filter(move |&core| enable_methods[core as usize] != EnableMethod::None)
+}
+
+/// How to release the given core from the firmware.
+pub fn enable_method(core: u64) -> EnableMethod {
+    CPU_TOPOLOGY.read(|cpus| {
+        cpus.enable_methods
+            .get(core as usize)
+            .copied()
+            .unwrap_or(EnableMethod::None)
+    })
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The shipped device trees describe four spin-table cores.
+    #[test_case]
+    fn topology_from_device_tree() {
+        let fdt = Fdt::new(include_bytes!("../../../../targets/bcm2711-rpi-4-b.dtb")).unwrap();
+        let topology = CpuTopology::from_device_tree(&fdt).unwrap();
+        assert_eq!(topology.num_cores, 4);
+        assert_eq!(
+            topology.enable_methods[3],
+            EnableMethod::SpinTable { release_addr: 0xf0 }
+        );
+    }
+}
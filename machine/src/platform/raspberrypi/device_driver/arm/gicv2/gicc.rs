@@ -11,7 +11,7 @@ use {
         platform::device_driver::common::MMIODerefWrapper,
     },
     tock_registers::{
-        interfaces::{Readable, Writeable},
+        interfaces::{ReadWriteable, Readable, Writeable},
         register_bitfields, register_structs,
         registers::ReadWrite,
     },
@@ -25,8 +25,14 @@ register_bitfields! {
     u32,
 
     /// CPU Interface Control Register
+    ///
+    /// With Non-secure access only `Enable` is there, enabling Group 1. With Secure access it
+    /// enables Group 0, and the other fields are there too.
     CTLR [
-        Enable OFFSET(0) NUMBITS(1) []
+        Enable OFFSET(0) NUMBITS(1) [],
+        EnableGrp1 OFFSET(1) NUMBITS(1) [],
+        AckCtl OFFSET(2) NUMBITS(1) [],
+        FIQEn OFFSET(3) NUMBITS(1) []
     ],
 
     /// Interrupt Priority Mask Register
@@ -34,13 +40,20 @@ register_bitfields! {
         Priority OFFSET(0) NUMBITS(8) []
     ],
 
+    /// Binary Point Register
+    BPR [
+        BinaryPoint OFFSET(0) NUMBITS(3) []
+    ],
+
     /// Interrupt Acknowledge Register
     IAR [
+        CPUID OFFSET(10) NUMBITS(3) [],
         InterruptID OFFSET(0) NUMBITS(10) []
     ],
 
     /// End of Interrupt Register
     EOIR [
+        CPUID OFFSET(10) NUMBITS(3) [],
         EOIINTID OFFSET(0) NUMBITS(10) []
     ]
 }
@@ -50,7 +63,7 @@ register_structs! {
     pub RegisterBlock {
         (0x000 => CTLR: ReadWrite<u32, CTLR::Register>),
         (0x004 => PMR: ReadWrite<u32, PMR::Register>),
-        (0x008 => _reserved1),
+        (0x008 => BPR: ReadWrite<u32, BPR::Register>),
         (0x00C => IAR: ReadWrite<u32, IAR::Register>),
         (0x010 => EOIR: ReadWrite<u32, EOIR::Register>),
         (0x014  => @END),
@@ -100,6 +113,20 @@ impl GICC {
         self.registers.PMR.write(PMR::Priority.val(255)); // Comment in arch spec.
     }
 
+    /// Let every priority bit take part in preemption.
+    ///
+    /// The binary point splits priorities into a group priority, which decides whether an IRQ
+    /// preempts an active one, and a subpriority, which only orders pending IRQs. Writing zero
+    /// sets the smallest binary point the implementation supports.
+    ///
+    /// # Safety
+    ///
+    /// - GICC MMIO registers are banked per CPU core. It is therefore safe to have `&self` instead
+    ///   of `&mut self`.
+    pub fn preempt_on_all_priority_bits(&self) {
+        self.registers.BPR.write(BPR::BinaryPoint.val(0));
+    }
+
     /// Enable the interface - start accepting IRQs.
     ///
     /// # Safety
@@ -107,10 +134,27 @@ impl GICC {
     /// - GICC MMIO registers are banked per CPU core. It is therefore safe to have `&self` instead
     ///   of `&mut self`.
     pub fn enable(&self) {
-        self.registers.CTLR.write(CTLR::Enable::SET);
+        // With Secure access, also enable Group 1 and let the IAR acknowledge its interrupts.
+        // The IAR then returns the Group 0 FIQ source as well, which the driver skips.
+        self.registers
+            .CTLR
+            .write(CTLR::Enable::SET + CTLR::EnableGrp1::SET + CTLR::AckCtl::SET);
+    }
+
+    /// Signal Group 0 interrupts as FIQ instead of IRQ.
+    ///
+    /// Only effective with Secure access, like Group 0 itself.
+    ///
+    /// # Safety
+    ///
+    /// - GICC MMIO registers are banked per CPU core. It is therefore safe to have `&self` instead
+    ///   of `&mut self`.
+    pub fn enable_fiq(&self) {
+        self.registers.CTLR.modify(CTLR::FIQEn::SET);
     }
 
-    /// Extract the number of the highest-priority pending IRQ.
+    /// Extract the number of the highest-priority pending IRQ, and for SGIs the number of the
+    /// requesting core's CPU interface.
     ///
     /// Can only be called from IRQ context, which is ensured by taking an `IRQContext` token.
     ///
@@ -119,18 +163,20 @@ impl GICC {
     /// - GICC MMIO registers are banked per CPU core. It is therefore safe to have `&self` instead
     ///   of `&mut self`.
     #[allow(clippy::trivially_copy_pass_by_ref)]
-    pub fn pending_irq_number<'irq_context>(
+    pub fn pending_irq<'irq_context>(
         &self,
         _ic: &exception::asynchronous::IRQContext<'irq_context>,
-    ) -> usize {
-        self.registers.IAR.read(IAR::InterruptID) as usize
+    ) -> (usize, u32) {
+        let iar = self.registers.IAR.extract();
+
+        (iar.read(IAR::InterruptID) as usize, iar.read(IAR::CPUID))
     }
 
     /// Complete handling of the currently active IRQ.
     ///
     /// Can only be called from IRQ context, which is ensured by taking an `IRQContext` token.
     ///
-    /// To be called after `pending_irq_number()`.
+    /// To be called after `pending_irq()`, with the values it returned.
     ///
     /// # Safety
     ///
@@ -140,8 +186,11 @@ impl GICC {
     pub fn mark_comleted<'irq_context>(
         &self,
         irq_number: u32,
+        source_cpu: u32,
         _ic: &exception::asynchronous::IRQContext<'irq_context>,
     ) {
-        self.registers.EOIR.write(EOIR::EOIINTID.val(irq_number));
+        self.registers
+            .EOIR
+            .write(EOIR::EOIINTID.val(irq_number) + EOIR::CPUID.val(source_cpu));
     }
 }
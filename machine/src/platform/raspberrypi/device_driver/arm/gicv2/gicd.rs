@@ -12,12 +12,12 @@ use {
         memory::{Address, Virtual},
         platform::device_driver::common::MMIODerefWrapper,
         state,
-        synchronization::{self, IRQSafeNullLock},
+        synchronization::{self, IRQSafeSpinLock},
     },
     tock_registers::{
         interfaces::{Readable, Writeable},
         register_bitfields, register_structs,
-        registers::{ReadOnly, ReadWrite},
+        registers::{ReadOnly, ReadWrite, WriteOnly},
     },
 };
 
@@ -38,6 +38,17 @@ register_bitfields! {
         ITLinesNumber OFFSET(0)  NUMBITS(5) []
     ],
 
+    /// Software Generated Interrupt Register
+    SGIR [
+        TargetListFilter OFFSET(24) NUMBITS(2) [
+            TargetList = 0b00,
+            AllOther = 0b01,
+            Requester = 0b10
+        ],
+        CPUTargetList OFFSET(16) NUMBITS(8) [],
+        SGIINTID OFFSET(0) NUMBITS(4) []
+    ],
+
     /// Interrupt Processor Targets Registers
     ITARGETSR [
         Offset3 OFFSET(24) NUMBITS(8) [],
@@ -53,10 +64,20 @@ register_structs! {
         (0x000 => CTLR: ReadWrite<u32, CTLR::Register>),
         (0x004 => TYPER: ReadOnly<u32, TYPER::Register>),
         (0x008 => _reserved1),
+        (0x084 => IGROUPR: [ReadWrite<u32>; 31]),
+        (0x100 => _reserved2),
         (0x104 => ISENABLER: [ReadWrite<u32>; 31]),
-        (0x180 => _reserved2),
+        (0x180 => _reserved3),
+        (0x184 => ICENABLER: [WriteOnly<u32>; 31]),
+        (0x200 => _reserved4),
+        (0x420 => IPRIORITYR: [ReadWrite<u32>; 248]),
+        (0x800 => _reserved5),
         (0x820 => ITARGETSR: [ReadWrite<u32, ITARGETSR::Register>; 248]),
-        (0xC00 => @END),
+        (0xC00 => _reserved6),
+        (0xC08 => ICFGR: [ReadOnly<u32>; 62]),
+        (0xD00 => _reserved7),
+        (0xF00 => SGIR: WriteOnly<u32, SGIR::Register>),
+        (0xF04 => @END),
     }
 }
 
@@ -64,10 +85,18 @@ register_structs! {
     #[allow(non_snake_case)]
     BankedRegisterBlock {
         (0x000 => _reserved1),
+        (0x080 => IGROUPR: ReadWrite<u32>),
+        (0x084 => _reserved2),
         (0x100 => ISENABLER: ReadWrite<u32>),
-        (0x104 => _reserved2),
+        (0x104 => _reserved3),
+        (0x180 => ICENABLER: WriteOnly<u32>),
+        (0x184 => _reserved4),
+        (0x400 => IPRIORITYR: [ReadWrite<u32>; 8]),
+        (0x420 => _reserved5),
         (0x800 => ITARGETSR: [ReadOnly<u32, ITARGETSR::Register>; 8]),
-        (0x820 => @END),
+        (0x820 => _reserved6),
+        (0xC00 => ICFGR: [ReadOnly<u32>; 2]),
+        (0xC08 => @END),
     }
 }
 
@@ -84,7 +113,7 @@ type BankedRegisters = MMIODerefWrapper<BankedRegisterBlock>;
 /// Representation of the GIC Distributor.
 pub struct GICD {
     /// Access to shared registers is guarded with a lock.
-    shared_registers: IRQSafeNullLock<SharedRegisters>,
+    shared_registers: IRQSafeSpinLock<SharedRegisters>,
 
     /// Access to banked registers is unguarded.
     banked_registers: BankedRegisters,
@@ -104,6 +133,15 @@ impl SharedRegisters {
         ((self.TYPER.read(TYPER::ITLinesNumber) as usize) + 1) * 32
     }
 
+    /// Return a slice of the implemented shared IGROUPR.
+    #[inline(always)]
+    fn implemented_igroup_slice(&mut self) -> &[ReadWrite<u32>] {
+        // One bit per IRQ, the first 32 IRQs are private.
+        let spi_igroupr_count = (self.num_irqs() - 32) >> 5;
+
+        &self.IGROUPR[0..spi_igroupr_count]
+    }
+
     /// Return a slice of the implemented ITARGETSR.
     #[inline(always)]
     fn implemented_itargets_slice(&mut self) -> &[ReadWrite<u32, ITARGETSR::Register>] {
@@ -134,7 +172,7 @@ impl GICD {
     /// - The user must ensure to provide a correct MMIO start address.
     pub const unsafe fn new(mmio_start_addr: Address<Virtual>) -> Self {
         Self {
-            shared_registers: IRQSafeNullLock::new(SharedRegisters::new(mmio_start_addr)),
+            shared_registers: IRQSafeSpinLock::new(SharedRegisters::new(mmio_start_addr)),
             banked_registers: BankedRegisters::new(mmio_start_addr),
         }
     }
@@ -169,10 +207,57 @@ impl GICD {
                 );
             }
 
+            // Signal every SPI as IRQ, see `set_local_group1()`.
+            for i in regs.implemented_igroup_slice().iter() {
+                i.set(u32::MAX);
+            }
+
             regs.CTLR.write(CTLR::Enable::SET);
         });
     }
 
+    /// Put the executing core's private interrupts in Group 1, signalled as IRQ.
+    ///
+    /// Groups are configurable with Secure access only, out of reset every interrupt is in
+    /// Group 0. With Non-secure access the writes are ignored, firmware moved them already.
+    pub fn set_local_group1(&self) {
+        self.banked_registers.IGROUPR.set(u32::MAX);
+    }
+
+    /// Put an interrupt in Group 0, which the CPU interface signals as FIQ.
+    ///
+    /// Fails with Non-secure access, which reads the group bits as zero. That is detected by
+    /// setting the bit first.
+    pub fn set_group0(&self, irq_num: &super::IRQNumber) -> Result<(), &'static str> {
+        let irq_num = irq_num.get();
+
+        // Same layout as ISENABLER.
+        let group_reg_index = irq_num >> 5;
+        let group_bit: u32 = 1u32 << (irq_num % 32);
+
+        let update = |group_reg: &ReadWrite<u32>| {
+            group_reg.set(group_reg.get() | group_bit);
+            if group_reg.get() & group_bit == 0 {
+                return Err("GIC groups need Secure access");
+            }
+
+            group_reg.set(group_reg.get() & !group_bit);
+            Ok(())
+        };
+
+        match irq_num {
+            // Private.
+            0..=31 => update(&self.banked_registers.IGROUPR),
+            // Shared.
+            _ => {
+                let group_reg_index_shared = group_reg_index - 1;
+
+                self.shared_registers
+                    .lock(|regs| update(&regs.IGROUPR[group_reg_index_shared]))
+            }
+        }
+    }
+
     /// Enable an interrupt.
     pub fn enable(&self, irq_num: &super::IRQNumber) {
         let irq_num = irq_num.get();
@@ -200,4 +285,138 @@ impl GICD {
             }
         }
     }
+
+    /// Disable an interrupt.
+    pub fn disable(&self, irq_num: &super::IRQNumber) {
+        let irq_num = irq_num.get();
+
+        // Same layout as ISENABLER. Writing a 1 disables the IRQ, zeros have no effect.
+        let disable_reg_index = irq_num >> 5;
+        let disable_bit: u32 = 1u32 << (irq_num % 32);
+
+        match irq_num {
+            // Private.
+            0..=31 => self.banked_registers.ICENABLER.set(disable_bit),
+            // Shared.
+            _ => {
+                let disable_reg_index_shared = disable_reg_index - 1;
+
+                self.shared_registers
+                    .lock(|regs| regs.ICENABLER[disable_reg_index_shared].set(disable_bit));
+            }
+        }
+    }
+
+    /// Whether an interrupt is edge-triggered rather than level-sensitive.
+    ///
+    /// SGIs are always edge-triggered, the configuration of private interrupts is banked.
+    pub fn is_edge_triggered(&self, irq_num: &super::IRQNumber) -> bool {
+        let irq_num = irq_num.get();
+
+        // Each u32 configuration register holds the two-bit fields of 16 IRQs, the upper bit
+        // set meaning edge-triggered.
+        let config_reg_index = irq_num >> 4;
+        let edge_bit: u32 = 1u32 << ((irq_num % 16) * 2 + 1);
+
+        match irq_num {
+            // Private.
+            0..=31 => self.banked_registers.ICFGR[config_reg_index].get() & edge_bit != 0,
+            // Shared.
+            _ => {
+                let config_reg_index_shared = config_reg_index - 2;
+
+                self.shared_registers
+                    .lock(|regs| regs.ICFGR[config_reg_index_shared].get() & edge_bit != 0)
+            }
+        }
+    }
+
+    /// Set the priority of an interrupt, lower values being more urgent.
+    ///
+    /// Priorities of private interrupts are banked, this sets them for the executing core only.
+    /// Implementations may ignore low-order bits of `priority`.
+    pub fn set_priority(&self, irq_num: &super::IRQNumber, priority: u8) {
+        let irq_num = irq_num.get();
+
+        // Each u32 priority register holds the byte-sized fields of four IRQs.
+        let priority_reg_index = irq_num >> 2;
+        let priority_shift = (irq_num % 4) * 8;
+        let priority_mask: u32 = 0xff << priority_shift;
+        let priority_bits = u32::from(priority) << priority_shift;
+
+        let update = |priority_reg: &ReadWrite<u32>| {
+            priority_reg.set((priority_reg.get() & !priority_mask) | priority_bits);
+        };
+
+        match irq_num {
+            // Private.
+            0..=31 => update(&self.banked_registers.IPRIORITYR[priority_reg_index]),
+            // Shared.
+            _ => {
+                let priority_reg_index_shared = priority_reg_index - 8;
+
+                self.shared_registers
+                    .lock(|regs| update(&regs.IPRIORITYR[priority_reg_index_shared]));
+            }
+        }
+    }
+
+    /// Raise SGI `sgi` on the CPU interfaces whose bits are set in `target_mask`.
+    pub fn send_sgi(&self, sgi: usize, target_mask: u8) {
+        self.shared_registers.lock(|regs| {
+            regs.SGIR.write(
+                SGIR::TargetListFilter::TargetList
+                    + SGIR::CPUTargetList.val(u32::from(target_mask))
+                    + SGIR::SGIINTID.val(sgi as u32),
+            )
+        });
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::platform::device_driver::IRQNumber};
+
+    /// Covers the shared registers up to GICD_SGIR.
+    const FAKE_REGS: usize = 0x1000 / 4;
+
+    fn fake_gicd(reg: &mut [u32; FAKE_REGS]) -> GICD {
+        unsafe { GICD::new(Address::<Virtual>::new(reg as *mut _ as usize)) }
+    }
+
+    /// Group 0 clears the interrupt's bit, in the banked register for private interrupts.
+    #[test_case]
+    fn set_group0_clears_group_bit() {
+        let mut reg = [0u32; FAKE_REGS];
+        for group_reg in reg[0x080 / 4..0x100 / 4].iter_mut() {
+            *group_reg = u32::MAX;
+        }
+        let gicd = fake_gicd(&mut reg);
+
+        assert!(gicd.set_group0(&IRQNumber::new(5)).is_ok());
+        assert!(gicd.set_group0(&IRQNumber::new(72)).is_ok());
+
+        assert_eq!(reg[0x080 / 4], !(1 << 5));
+        assert_eq!(reg[0x084 / 4], u32::MAX);
+        assert_eq!(reg[0x088 / 4], !(1 << (72 - 64)));
+        assert_eq!(reg[0x08c / 4], u32::MAX);
+    }
+
+    /// The trigger mode is read from the upper bit of the interrupt's configuration field.
+    #[test_case]
+    fn edge_triggered_reads_config_field() {
+        let mut reg = [0u32; FAKE_REGS];
+        reg[0xc04 / 4] = 0b10 << ((27 % 16) * 2);
+        reg[0xc10 / 4] = 0b10 << ((72 % 16) * 2);
+        let gicd = fake_gicd(&mut reg);
+
+        assert!(gicd.is_edge_triggered(&IRQNumber::new(27)));
+        assert!(!gicd.is_edge_triggered(&IRQNumber::new(26)));
+        assert!(gicd.is_edge_triggered(&IRQNumber::new(72)));
+        assert!(!gicd.is_edge_triggered(&IRQNumber::new(73)));
+    }
 }
@@ -75,23 +75,42 @@
 //!         CPU interface number. Of the banked interrupt IDs:
 //!           - 00..15 SGIs
 //!           - 16..31 PPIs
+//!
+//! # Nesting
+//!
+//! Acknowledging an IRQ raises the CPU interface's running priority to the IRQ's priority until
+//! its completion. Once nesting is enabled, handlers run with IRQs unmasked, and only IRQs of a
+//! more urgent [`IRQPriority`] reach the core. Each priority level nests at most once, which
+//! bounds the exception stack use.
 
 mod gicc;
 mod gicd;
 
-use crate::{
-    cpu, drivers, exception,
-    memory::{Address, Virtual},
-    platform::{self, cpu::BOOT_CORE_ID, device_driver::common::BoundedUsize},
-    synchronization::{self, InitStateLock},
+use {
+    crate::{
+        cpu, drivers,
+        exception::{self, asynchronous::IRQPriority},
+        memory::{Address, Virtual},
+        platform::{self, cpu::BOOT_CORE_ID, device_driver::common::BoundedUsize},
+        synchronization::{self, RwSpinLock},
+    },
+    core::sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
 
 //--------------------------------------------------------------------------------------------------
 // Private Definitions
 //--------------------------------------------------------------------------------------------------
 
-type HandlerTable = [Option<exception::asynchronous::IRQHandlerDescriptor<IRQNumber>>;
-    IRQNumber::MAX_INCLUSIVE + 1];
+type HandlerTable =
+    [exception::asynchronous::IRQHandlerChain<IRQNumber>; IRQNumber::MAX_INCLUSIVE + 1];
+
+type StatsTable = [exception::asynchronous::IRQStats; IRQNumber::MAX_INCLUSIVE + 1];
+
+/// GIC priority of the FIQ source, above every IRQ.
+const FIQ_PRIORITY: u8 = 0x00;
+
+/// No interrupt is routed as FIQ.
+const NO_FIQ: usize = usize::MAX;
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -108,8 +127,59 @@ pub struct GICv2 {
     /// The CPU Interface.
     gicc: gicc::GICC,
 
-    /// Stores registered IRQ handlers. Writable only during kernel init. RO afterwards.
-    handler_table: InitStateLock<HandlerTable>,
+    /// Stores registered IRQ handlers. Handlers can be added and removed at any time.
+    handler_table: RwSpinLock<HandlerTable>,
+
+    /// Per-IRQ statistics, updated without locking.
+    stats: StatsTable,
+
+    /// Counts IRQs acknowledged with an ID beyond the handler table, like the spurious ID 1023.
+    unknown: exception::asynchronous::IRQStats,
+
+    /// Whether handlers run with IRQs unmasked.
+    nesting: AtomicBool,
+
+    /// The interrupt routed as FIQ, or [`NO_FIQ`].
+    fiq_irq: AtomicUsize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// The GIC priority of a handler chain, lower values being more urgent.
+///
+/// Levels are multiples of 0x20, which every implementation tells apart, including the halved
+/// range Non-secure accesses see. They start one level below 0x00, which is reserved for
+/// [`FIQ_PRIORITY`] so that the FIQ source stays more urgent than every IRQ.
+const fn gic_priority(priority: Option<IRQPriority>) -> u8 {
+    match priority {
+        Some(IRQPriority::Highest) => 0x20,
+        Some(IRQPriority::High) => 0x40,
+        Some(IRQPriority::Normal) | None => 0x60,
+        Some(IRQPriority::Low) => 0x80,
+    }
+}
+
+impl GICv2 {
+    /// Set the executing core's banked priorities of private interrupts from the handler table.
+    ///
+    /// Those without handlers get the default priority instead of staying at the reset value,
+    /// the most urgent one.
+    fn apply_local_priorities(&self) {
+        let priorities = self.handler_table.read(|table| {
+            let mut priorities = [None; 32];
+            for (priority, chain) in priorities.iter_mut().zip(table.iter()) {
+                *priority = chain.priority();
+            }
+            priorities
+        });
+
+        for (irq_number, priority) in priorities.into_iter().enumerate() {
+            self.gicd
+                .set_priority(&IRQNumber::new(irq_number), gic_priority(priority));
+        }
+    }
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -133,9 +203,63 @@ impl GICv2 {
         Self {
             gicd: gicd::GICD::new(gicd_mmio_start_addr),
             gicc: gicc::GICC::new(gicc_mmio_start_addr),
-            handler_table: InitStateLock::new([None; IRQNumber::MAX_INCLUSIVE + 1]),
+            handler_table: RwSpinLock::new(
+                [exception::asynchronous::IRQHandlerChain::new(); IRQNumber::MAX_INCLUSIVE + 1],
+            ),
+            stats: exception::asynchronous::IRQStats::table(),
+            unknown: exception::asynchronous::IRQStats::new(),
+            nesting: AtomicBool::new(false),
+            fiq_irq: AtomicUsize::new(NO_FIQ),
         }
     }
+
+    /// Set up the executing core's CPU interface.
+    ///
+    /// The driver's init does this for the boot core, other cores call it themselves.
+    /// Priorities of private interrupts are banked, handlers registered on the boot core have
+    /// theirs applied here on the other cores.
+    pub fn init_cpu_interface(&self) {
+        self.gicd.set_local_group1();
+        self.apply_local_priorities();
+        self.gicc.priority_accept_all();
+        self.gicc.preempt_on_all_priority_bits();
+        self.gicc.enable();
+    }
+
+    /// Signal `irq_number` as FIQ, on the cores it targets, the boot core for SPIs.
+    ///
+    /// Puts it in Group 0, at a priority above every IRQ. This needs Secure access to the GIC,
+    /// which the kernel does not have when the firmware drops to Non-secure.
+    ///
+    /// The FIQ is neither acknowledged nor completed, it goes inactive once its handler quiets
+    /// the device. Edge-triggered sources would be lost when showing up on the IRQ path while
+    /// FIQs are masked, they are refused, as are sources with IRQ handlers.
+    pub fn route_fiq(&self, irq_number: &IRQNumber) -> Result<(), &'static str> {
+        if self.gicd.is_edge_triggered(irq_number) {
+            return Err("FIQ source must be level-triggered");
+        }
+
+        // Under the table lock, no IRQ handler registers meanwhile.
+        self.handler_table.write(|table| {
+            if !table[irq_number.get()].is_empty() {
+                return Err("FIQ source has IRQ handlers");
+            }
+            self.gicd.set_group0(irq_number)?;
+            self.fiq_irq.store(irq_number.get(), Ordering::Relaxed);
+            Ok(())
+        })?;
+
+        self.gicd.set_priority(irq_number, FIQ_PRIORITY);
+        self.gicc.enable_fiq();
+        self.gicd.enable(irq_number);
+
+        Ok(())
+    }
+
+    /// Raise SGI `sgi` on the cores whose bits are set in `target_mask`.
+    pub fn send_sgi(&self, sgi: usize, target_mask: u8) {
+        self.gicd.send_sgi(sgi, target_mask);
+    }
 }
 
 //------------------------------------------------------------------------------
@@ -155,8 +279,7 @@ impl drivers::interface::DeviceDriver for GICv2 {
             self.gicd.boot_core_init();
         }
 
-        self.gicc.priority_accept_all();
-        self.gicc.enable();
+        self.init_cpu_interface();
 
         Ok(())
     }
@@ -169,62 +292,147 @@ impl exception::asynchronous::interface::IRQManager for GICv2 {
         &self,
         irq_handler_descriptor: exception::asynchronous::IRQHandlerDescriptor<Self::IRQNumberType>,
     ) -> Result<(), &'static str> {
-        self.handler_table.write(|table| {
-            let irq_number = irq_handler_descriptor.number().get();
+        let irq_number = irq_handler_descriptor.number();
 
-            if table[irq_number].is_some() {
-                return Err("IRQ handler already registered");
+        let priority = self.handler_table.write(|table| {
+            if irq_number.get() == self.fiq_irq.load(Ordering::Relaxed) {
+                return Err("IRQ is routed as FIQ");
             }
+            let chain = &mut table[irq_number.get()];
+            chain.register(irq_handler_descriptor)?;
+            Ok(chain.priority())
+        })?;
 
-            table[irq_number] = Some(irq_handler_descriptor);
+        self.gicd.set_priority(&irq_number, gic_priority(priority));
 
-            Ok(())
-        })
+        Ok(())
+    }
+
+    fn unregister_handler(
+        &self,
+        irq_handler_descriptor: exception::asynchronous::IRQHandlerDescriptor<Self::IRQNumberType>,
+    ) -> Result<(), &'static str> {
+        let irq_number = irq_handler_descriptor.number();
+
+        let priority = self.handler_table.write(|table| {
+            if irq_number.get() == self.fiq_irq.load(Ordering::Relaxed) {
+                return Err("IRQ is routed as FIQ");
+            }
+            let chain = &mut table[irq_number.get()];
+            chain.unregister(irq_handler_descriptor)?;
+            Ok(chain.priority())
+        })?;
+
+        match priority {
+            None => self.gicd.disable(&irq_number),
+            // The remaining handlers may be less urgent.
+            Some(_) => self.gicd.set_priority(&irq_number, gic_priority(priority)),
+        }
+
+        Ok(())
     }
 
     fn enable(&self, irq_number: &Self::IRQNumberType) {
         self.gicd.enable(irq_number);
     }
 
+    fn disable(&self, irq_number: &Self::IRQNumberType) {
+        self.gicd.disable(irq_number);
+    }
+
     fn handle_pending_irqs<'irq_context>(
         &'irq_context self,
         ic: &exception::asynchronous::IRQContext<'irq_context>,
     ) {
         // Extract the highest priority pending IRQ number from the Interrupt Acknowledge Register
         // (IAR).
-        let irq_number = self.gicc.pending_irq_number(ic);
+        let (irq_number, source_cpu) = self.gicc.pending_irq(ic);
 
         // Guard against spurious interrupts.
         if irq_number > GICv2::MAX_IRQ_NUMBER {
+            self.unknown.count_spurious();
+
+            // IDs 1020 to 1023 are special, they are not acknowledged and need no completion.
+            if irq_number < 1020 {
+                self.gicc.mark_comleted(irq_number as u32, source_cpu, ic);
+            }
             return;
         }
 
-        // Call the IRQ handler. Panic if there is none.
+        // With Secure access the IAR acknowledges Group 0 interrupts too, so the FIQ source shows
+        // up here while FIQs are masked. Complete it without handling: being level-triggered, as
+        // `route_fiq()` ensures, it is pending again and taken as FIQ once FIQs are unmasked.
+        if irq_number == self.fiq_irq.load(Ordering::Relaxed) {
+            self.gicc.mark_comleted(irq_number as u32, source_cpu, ic);
+            return;
+        }
+
+        // Call the IRQ handlers outside of the table lock, so that they may register handlers.
+        let chain = self.handler_table.read(|table| table[irq_number]);
+
+        // The running priority is raised already, only more urgent IRQs preempt the handlers.
+        let nesting = self.nesting.load(Ordering::Relaxed);
+        if nesting {
+            exception::asynchronous::local_irq_unmask();
+        }
+
+        self.stats[irq_number].dispatch(&chain);
+
+        if nesting {
+            exception::asynchronous::local_irq_mask();
+        }
+
+        // Signal completion of handling.
+        self.gicc.mark_comleted(irq_number as u32, source_cpu, ic);
+    }
+
+    fn set_nesting(&self, enabled: bool) -> Result<(), &'static str> {
+        self.nesting.store(enabled, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn print_handler(&self) {
+        use crate::info;
+
         self.handler_table.read(|table| {
-            match table[irq_number] {
-                None => panic!("No handler registered for IRQ {}", irq_number),
-                Some(descriptor) => {
-                    // Call the IRQ handler. Panics on failure.
-                    descriptor.handler().handle().expect("Error handling IRQ");
+            info!("      Private handler:");
+
+            for (i, chain) in table.iter().take(32).enumerate() {
+                if !chain.is_empty() {
+                    info!("            {: >3}. {}", i, chain);
                 }
             }
-        });
 
-        // Signal completion of handling.
-        self.gicc.mark_comleted(irq_number as u32, ic);
+            info!("      Peripheral handler:");
+
+            for (i, chain) in table.iter().skip(32).enumerate() {
+                if !chain.is_empty() {
+                    info!("            {: >3}. {}", i + 32, chain);
+                }
+            }
+        });
     }
 
-    fn print_handler(&self) {
+    fn print_stats(&self) {
         use crate::info;
 
-        info!("      Peripheral handler:");
+        info!("      GICv2 IRQ stats:");
 
         self.handler_table.read(|table| {
-            for (i, opt) in table.iter().skip(32).enumerate() {
-                if let Some(handler) = opt {
-                    info!("            {: >3}. {}", i + 32, handler.name());
+            for (i, (chain, stats)) in table.iter().zip(self.stats.iter()).enumerate() {
+                if !chain.is_empty() || !stats.is_unused() {
+                    info!("            {: >3}. {}: {}", i, chain, stats);
                 }
             }
         });
+
+        info!("            Unknown IDs: {}", self.unknown.spurious());
+    }
+
+    fn stats(
+        &self,
+        irq_number: &Self::IRQNumberType,
+    ) -> Option<&exception::asynchronous::IRQStats> {
+        Some(&self.stats[irq_number.get()])
     }
 }
@@ -12,7 +12,7 @@ use {
             device_driver::{common::MMIODerefWrapper, IRQNumber},
             BcmHost,
         },
-        synchronization::{interface::Mutex, IRQSafeNullLock},
+        synchronization::{interface::Mutex, IRQSafeSpinLock},
         time,
     },
     core::{marker::PhantomData, time::Duration},
@@ -102,7 +102,7 @@ struct GPIOInner {
 
 /// Public interface to the GPIO MMIO area
 pub struct GPIO {
-    inner: IRQSafeNullLock<GPIOInner>,
+    inner: IRQSafeSpinLock<GPIOInner>,
 }
 
 impl GPIOInner {
@@ -218,7 +218,7 @@ impl GPIO {
     /// Unsafe, duh!
     pub const unsafe fn new(mmio_base_addr: Address<Virtual>) -> Self {
         Self {
-            inner: IRQSafeNullLock::new(GPIOInner::new(mmio_base_addr)),
+            inner: IRQSafeSpinLock::new(GPIOInner::new(mmio_base_addr)),
         }
     }
 
@@ -274,7 +274,7 @@ impl ::core::convert::From<PullUpDown> for u32 {
 /// `into_alt` methods before it can be used.
 pub struct Pin<'outer, State> {
     pin: usize,
-    inner: &'outer IRQSafeNullLock<GPIOInner>,
+    inner: &'outer IRQSafeSpinLock<GPIOInner>,
     _state: PhantomData<State>,
 }
 
@@ -305,7 +305,7 @@ impl<'outer> Pin<'outer, Uninitialized> {
     /// Panics if `pin` > `53`.
     unsafe fn new(
         pin: usize,
-        inner: &'outer IRQSafeNullLock<GPIOInner>,
+        inner: &'outer IRQSafeSpinLock<GPIOInner>,
     ) -> Pin<'outer, Uninitialized> {
         if pin > 53 {
             panic!("gpio::Pin::new(): pin {pin} exceeds maximum of 53");
@@ -0,0 +1,469 @@
+/*
+ * SPDX-License-Identifier: BlueOak-1.0.0
+ * Copyright (c) Berkus Decker <berkus+vesper@metta.systems>
+ */
+
+//! Local Interrupt Controller Driver, the BCM2836 ARM local peripherals.
+//!
+//! Every interrupt reaching a core passes through here. Each core has its own sources: the
+//! four generic timer interrupts, four mailboxes, the PMU interrupt and the local timer. The
+//! interrupt of the peripheral controller (the "GPU" interrupt) is routed to one core only.
+//!
+//! Local IRQ numbers are the bits of the per-core IRQ source register, they are banked per
+//! core like GIC PPIs: enabling one enables it on the executing core.
+//!
+//! # Resources
+//!
+//! - <https://datasheets.raspberrypi.com/bcm2836/bcm2836-peripherals.pdf>
+
+use {
+    super::{LocalIRQ, PendingIRQs},
+    crate::{
+        cpu, exception,
+        memory::{Address, Virtual},
+        platform::{cpu::MAX_CORES, device_driver::common::MMIODerefWrapper},
+        synchronization::{
+            interface::{Mutex, ReadWriteEx},
+            IRQSafeSpinLock, RwSpinLock,
+        },
+    },
+    tock_registers::{
+        interfaces::{Readable, Writeable},
+        register_structs,
+        registers::{ReadOnly, ReadWrite, WriteOnly},
+    },
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => CONTROL: ReadWrite<u32>),
+        (0x04 => _reserved1),
+        (0x08 => CORE_TIMER_PRESCALER: ReadWrite<u32>),
+        (0x0c => GPU_INT_ROUTING: ReadWrite<u32>),
+        (0x10 => PMU_INT_ROUTING_SET: WriteOnly<u32>),
+        (0x14 => PMU_INT_ROUTING_CLR: WriteOnly<u32>),
+        (0x18 => _reserved2),
+        (0x24 => LOCAL_TIMER_INT_ROUTING: ReadWrite<u32>),
+        (0x28 => _reserved3),
+        (0x34 => LOCAL_TIMER_CONTROL: ReadWrite<u32>),
+        (0x38 => _reserved4),
+        (0x40 => CORE_TIMER_INT_CONTROL: [ReadWrite<u32>; 4]),
+        (0x50 => CORE_MAILBOX_INT_CONTROL: [ReadWrite<u32>; 4]),
+        (0x60 => CORE_IRQ_SOURCE: [ReadOnly<u32>; 4]),
+        (0x70 => CORE_FIQ_SOURCE: [ReadOnly<u32>; 4]),
+        (0x80 => CORE_MAILBOX_WRITE_SET: [WriteOnly<u32>; 16]),
+        (0xc0 => CORE_MAILBOX_READ_CLEAR: [ReadWrite<u32>; 16]),
+        (0x100 => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+type HandlerTable =
+    [exception::asynchronous::IRQHandlerChain<LocalIRQ>; LocalIRQ::MAX_INCLUSIVE + 1];
+
+type StatsTable = [exception::asynchronous::IRQStats; LocalIRQ::MAX_INCLUSIVE + 1];
+
+/// Mailboxes per core.
+const NUM_MAILBOXES: usize = 4;
+
+/// Interrupt enable bit of the local timer control register.
+const LOCAL_TIMER_INT_ENABLE: u32 = 1 << 29;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Representation of the local interrupt controller.
+pub struct LocalIC {
+    /// Read-modify-write access to the routing and control registers is guarded with a lock.
+    rmw_registers: IRQSafeSpinLock<Registers>,
+
+    /// Source and mailbox registers are accessed unguarded, a write only affects the bits set.
+    registers: Registers,
+
+    /// Stores registered IRQ handlers. Handlers can be added and removed at any time.
+    handler_table: RwSpinLock<HandlerTable>,
+
+    /// Per-IRQ statistics, updated without locking.
+    stats: StatsTable,
+
+    /// Counts pending bits past the last source, which are reserved.
+    reserved: exception::asynchronous::IRQStats,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl LocalIC {
+    /// Secure physical timer, CNTPSIRQ.
+    pub const CNTPS_IRQ: usize = 0;
+    /// Non-secure physical timer, CNTPNSIRQ.
+    pub const CNTPNS_IRQ: usize = 1;
+    /// Hypervisor timer, CNTHPIRQ.
+    pub const CNTHP_IRQ: usize = 2;
+    /// Virtual timer, CNTVIRQ.
+    pub const CNTV_IRQ: usize = 3;
+    /// Mailbox 0, mailboxes 1 to 3 follow.
+    pub const MAILBOX0_IRQ: usize = 4;
+    /// Peripheral interrupt controller.
+    pub const GPU_IRQ: usize = 8;
+    /// Performance monitors.
+    pub const PMU_IRQ: usize = 9;
+    /// AXI outstanding writes, only ever on core 0.
+    pub const AXI_IRQ: usize = 10;
+    /// Local timer.
+    pub const LOCAL_TIMER_IRQ: usize = 11;
+
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct MMIO start address.
+    pub const unsafe fn new(mmio_start_addr: Address<Virtual>) -> Self {
+        Self {
+            rmw_registers: IRQSafeSpinLock::new(Registers::new(mmio_start_addr)),
+            registers: Registers::new(mmio_start_addr),
+            handler_table: RwSpinLock::new(
+                [exception::asynchronous::IRQHandlerChain::new(); LocalIRQ::MAX_INCLUSIVE + 1],
+            ),
+            stats: exception::asynchronous::IRQStats::table(),
+            reserved: exception::asynchronous::IRQStats::new(),
+        }
+    }
+
+    /// Route the peripheral controller's interrupt to `core`.
+    pub fn route_gpu_irq(&self, core: usize) {
+        self.rmw_registers
+            .lock(|regs| Self::write_gpu_irq_route(regs, core));
+    }
+
+    /// Route the peripheral controller's FIQ to `core`.
+    pub fn route_gpu_fiq(&self, core: usize) {
+        self.rmw_registers.lock(|regs| {
+            let routing = regs.GPU_INT_ROUTING.get() & !0b1100;
+            regs.GPU_INT_ROUTING
+                .set(routing | ((core as u32 & 0b11) << 2));
+        });
+    }
+
+    /// Whether the peripheral controller's interrupt is pending on the executing core.
+    pub fn is_gpu_irq_pending(&self) -> bool {
+        self.registers.CORE_IRQ_SOURCE[Self::core()].get() & (1 << Self::GPU_IRQ) != 0
+    }
+
+    /// Set `bits` in mailbox `mailbox` of `core`.
+    ///
+    /// The mailbox IRQ is asserted on `core` as long as any bit is set, if enabled there.
+    pub fn send_mailbox(&self, core: usize, mailbox: usize, bits: u32) {
+        assert!(core < MAX_CORES && mailbox < NUM_MAILBOXES);
+        self.registers.CORE_MAILBOX_WRITE_SET[core * NUM_MAILBOXES + mailbox].set(bits);
+    }
+
+    /// Read and clear mailbox `mailbox` of the executing core.
+    pub fn take_mailbox(&self, mailbox: usize) -> u32 {
+        assert!(mailbox < NUM_MAILBOXES);
+        let reg = &self.registers.CORE_MAILBOX_READ_CLEAR[Self::core() * NUM_MAILBOXES + mailbox];
+        let bits = reg.get();
+        // Writing ones clears them, bits set meanwhile stay pending.
+        reg.set(bits);
+        bits
+    }
+
+    /// The executing core, which indexes the banked registers.
+    fn core() -> usize {
+        cpu::smp::core_id() as usize
+    }
+
+    /// Route the GPU IRQ to `core`, keeping the FIQ routing in bits [3:2].
+    fn write_gpu_irq_route(regs: &Registers, core: usize) {
+        let routing = regs.GPU_INT_ROUTING.get() & !0b11;
+        regs.GPU_INT_ROUTING.set(routing | (core as u32 & 0b11));
+    }
+
+    /// Query the list of pending IRQs of the executing core.
+    fn pending_irqs(&self) -> PendingIRQs {
+        PendingIRQs::new(u64::from(
+            self.registers.CORE_IRQ_SOURCE[Self::core()].get(),
+        ))
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl exception::asynchronous::interface::IRQManager for LocalIC {
+    type IRQNumberType = LocalIRQ;
+
+    fn register_handler(
+        &self,
+        irq_handler_descriptor: exception::asynchronous::IRQHandlerDescriptor<Self::IRQNumberType>,
+    ) -> Result<(), &'static str> {
+        let irq_number = irq_handler_descriptor.number().get();
+        if irq_number == Self::GPU_IRQ {
+            return Err("GPU IRQ is dispatched to the peripheral interrupt controller");
+        }
+
+        self.handler_table
+            .write(|table| table[irq_number].register(irq_handler_descriptor))
+    }
+
+    fn unregister_handler(
+        &self,
+        irq_handler_descriptor: exception::asynchronous::IRQHandlerDescriptor<Self::IRQNumberType>,
+    ) -> Result<(), &'static str> {
+        use exception::asynchronous::interface::IRQManager;
+
+        let irq = irq_handler_descriptor.number();
+
+        let now_empty = self.handler_table.write(|table| {
+            let mut chain = table[irq.get()];
+            chain.unregister(irq_handler_descriptor)?;
+            if chain.is_empty() && irq.get() == Self::AXI_IRQ {
+                return Err("AXI IRQ cannot be masked");
+            }
+
+            table[irq.get()] = chain;
+            Ok(chain.is_empty())
+        })?;
+
+        if now_empty {
+            self.disable(&irq);
+        }
+
+        Ok(())
+    }
+
+    fn enable(&self, irq: &Self::IRQNumberType) {
+        let core = Self::core();
+
+        self.rmw_registers.lock(|regs| match irq.get() {
+            irq @ Self::CNTPS_IRQ..=Self::CNTV_IRQ => {
+                let control = &regs.CORE_TIMER_INT_CONTROL[core];
+                control.set(control.get() | (1 << irq));
+            }
+            irq @ Self::MAILBOX0_IRQ..=7 => {
+                let control = &regs.CORE_MAILBOX_INT_CONTROL[core];
+                control.set(control.get() | (1 << (irq - Self::MAILBOX0_IRQ)));
+            }
+            Self::GPU_IRQ => Self::write_gpu_irq_route(regs, core),
+            Self::PMU_IRQ => regs.PMU_INT_ROUTING_SET.set(1 << core),
+            // Wired to core 0, there is nothing to route.
+            Self::AXI_IRQ => {}
+            Self::LOCAL_TIMER_IRQ => {
+                regs.LOCAL_TIMER_INT_ROUTING.set(core as u32);
+                let control = &regs.LOCAL_TIMER_CONTROL;
+                control.set(control.get() | LOCAL_TIMER_INT_ENABLE);
+            }
+            _ => unreachable!(),
+        });
+    }
+
+    /// Disables in the executing core's bank, like [`Self::enable`] enables.
+    ///
+    /// The GPU and AXI IRQs are routed rather than enabled, they cannot be masked here. The GPU
+    /// IRQ has no handlers here and [`Self::unregister_handler`] keeps the last AXI handler.
+    fn disable(&self, irq: &Self::IRQNumberType) {
+        let core = Self::core();
+
+        self.rmw_registers.lock(|regs| match irq.get() {
+            irq @ Self::CNTPS_IRQ..=Self::CNTV_IRQ => {
+                let control = &regs.CORE_TIMER_INT_CONTROL[core];
+                control.set(control.get() & !(1 << irq));
+            }
+            irq @ Self::MAILBOX0_IRQ..=7 => {
+                let control = &regs.CORE_MAILBOX_INT_CONTROL[core];
+                control.set(control.get() & !(1 << (irq - Self::MAILBOX0_IRQ)));
+            }
+            Self::PMU_IRQ => regs.PMU_INT_ROUTING_CLR.set(1 << core),
+            Self::LOCAL_TIMER_IRQ => {
+                let control = &regs.LOCAL_TIMER_CONTROL;
+                control.set(control.get() & !LOCAL_TIMER_INT_ENABLE);
+            }
+            Self::GPU_IRQ | Self::AXI_IRQ => {}
+            _ => unreachable!(),
+        });
+    }
+
+    /// Handle the pending local IRQs, except for the GPU IRQ, see [`Self::is_gpu_irq_pending`].
+    fn handle_pending_irqs<'irq_context>(
+        &'irq_context self,
+        _ic: &exception::asynchronous::IRQContext<'irq_context>,
+    ) {
+        for irq_number in self.pending_irqs().filter(|&irq| irq != Self::GPU_IRQ) {
+            // Bits past the last source are reserved.
+            if irq_number > LocalIRQ::MAX_INCLUSIVE {
+                self.reserved.count_spurious();
+                continue;
+            }
+
+            // Call the IRQ handlers outside of the table lock, so that they may register handlers.
+            let chain = self.handler_table.read(|table| table[irq_number]);
+            self.stats[irq_number].dispatch(&chain);
+        }
+    }
+
+    fn print_handler(&self) {
+        use crate::info;
+
+        info!("      Local handler:");
+
+        self.handler_table.read(|table| {
+            for (i, chain) in table.iter().enumerate() {
+                if !chain.is_empty() {
+                    info!("            {: >3}. {}", i, chain);
+                }
+            }
+        });
+    }
+
+    fn print_stats(&self) {
+        use crate::info;
+
+        info!("      Local IRQ stats:");
+
+        self.handler_table.read(|table| {
+            for (i, (chain, stats)) in table.iter().zip(self.stats.iter()).enumerate() {
+                if !chain.is_empty() || !stats.is_unused() {
+                    info!("            {: >3}. {}: {}", i, chain, stats);
+                }
+            }
+        });
+
+        info!("            Reserved bits: {}", self.reserved.spurious());
+    }
+
+    fn stats(&self, irq: &Self::IRQNumberType) -> Option<&exception::asynchronous::IRQStats> {
+        Some(&self.stats[irq.get()])
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {super::*, exception::asynchronous::interface::IRQManager};
+
+    struct Nop;
+
+    impl exception::asynchronous::interface::IRQHandler for Nop {
+        fn handle(&self) -> Result<exception::HandlerResult, &'static str> {
+            Ok(exception::HandlerResult::Handled)
+        }
+    }
+
+    static NOP: Nop = Nop;
+
+    fn fake_ic(reg: &mut [u32; 64]) -> LocalIC {
+        unsafe { LocalIC::new(Address::<Virtual>::new(reg as *mut _ as usize)) }
+    }
+
+    /// Local IRQs are enabled in the executing core's bank.
+    #[test_case]
+    fn enable_sets_executing_core_bits() {
+        let mut reg = [0u32; 64];
+        let ic = fake_ic(&mut reg);
+        let core = LocalIC::core();
+
+        ic.enable(&LocalIRQ::new(LocalIC::CNTPNS_IRQ));
+        ic.enable(&LocalIRQ::new(LocalIC::MAILBOX0_IRQ + 2));
+        ic.enable(&LocalIRQ::new(LocalIC::GPU_IRQ));
+        ic.enable(&LocalIRQ::new(LocalIC::PMU_IRQ));
+
+        assert_eq!(reg[0x40 / 4 + core], 0b10);
+        assert_eq!(reg[0x50 / 4 + core], 0b100);
+        assert_eq!(reg[0x0c / 4], core as u32);
+        assert_eq!(reg[0x10 / 4], 1 << core);
+    }
+
+    #[test_case]
+    fn disable_clears_executing_core_bits() {
+        let mut reg = [0u32; 64];
+        let ic = fake_ic(&mut reg);
+        let core = LocalIC::core();
+
+        ic.enable(&LocalIRQ::new(LocalIC::CNTPS_IRQ));
+        ic.enable(&LocalIRQ::new(LocalIC::CNTPNS_IRQ));
+        ic.disable(&LocalIRQ::new(LocalIC::CNTPS_IRQ));
+        ic.disable(&LocalIRQ::new(LocalIC::PMU_IRQ));
+
+        assert_eq!(reg[0x40 / 4 + core], 0b10);
+        assert_eq!(reg[0x14 / 4], 1 << core);
+    }
+
+    /// Enabling the GPU IRQ leaves its FIQ routing alone.
+    #[test_case]
+    fn gpu_irq_enable_keeps_fiq_routing() {
+        let mut reg = [0u32; 64];
+        let ic = fake_ic(&mut reg);
+        ic.route_gpu_fiq(3);
+
+        ic.enable(&LocalIRQ::new(LocalIC::GPU_IRQ));
+        assert_eq!(reg[0x0c / 4], (3 << 2) | LocalIC::core() as u32);
+    }
+
+    #[test_case]
+    fn local_timer_interrupt_is_masked() {
+        let mut reg = [0u32; 64];
+        let ic = fake_ic(&mut reg);
+        reg[0x34 / 4] = 0x1000_0000 | 1_000;
+
+        ic.enable(&LocalIRQ::new(LocalIC::LOCAL_TIMER_IRQ));
+        assert_eq!(reg[0x34 / 4], 0x3000_0000 | 1_000);
+
+        ic.disable(&LocalIRQ::new(LocalIC::LOCAL_TIMER_IRQ));
+        assert_eq!(reg[0x34 / 4], 0x1000_0000 | 1_000);
+    }
+
+    #[test_case]
+    fn mailboxes_are_addressed_per_core() {
+        let mut reg = [0u32; 64];
+        let ic = fake_ic(&mut reg);
+
+        ic.send_mailbox(2, 1, 0x8000_0001);
+        assert_eq!(reg[0x80 / 4 + 2 * 4 + 1], 0x8000_0001);
+
+        let core = LocalIC::core();
+        reg[0xc0 / 4 + core * 4 + 3] = 0b101;
+        assert_eq!(ic.take_mailbox(3), 0b101);
+    }
+
+    #[test_case]
+    fn gpu_irq_is_not_registered_locally() {
+        let mut reg = [0u32; 64];
+        let ic = fake_ic(&mut reg);
+        let gpu = exception::asynchronous::IRQHandlerDescriptor::new(
+            LocalIRQ::new(LocalIC::GPU_IRQ),
+            "GPU",
+            &NOP,
+        );
+        assert!(ic.register_handler(gpu).is_err());
+    }
+
+    /// The AXI IRQ can't be masked, so its last handler stays.
+    #[test_case]
+    fn last_axi_handler_is_kept() {
+        let mut reg = [0u32; 64];
+        let ic = fake_ic(&mut reg);
+        let axi = exception::asynchronous::IRQHandlerDescriptor::new(
+            LocalIRQ::new(LocalIC::AXI_IRQ),
+            "AXI",
+            &NOP,
+        );
+        ic.register_handler(axi).unwrap();
+        assert!(ic.unregister_handler(axi).is_err());
+        assert!(ic
+            .handler_table
+            .read(|table| !table[LocalIC::AXI_IRQ].is_empty()));
+    }
+}
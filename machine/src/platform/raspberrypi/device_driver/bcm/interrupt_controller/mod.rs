@@ -3,7 +3,11 @@
 // Copyright (c) 2020-2022 Andre Richter <andre.o.richter@gmail.com>
 
 //! Interrupt Controller Driver.
+//!
+//! Combines the per-core local controller, which every interrupt passes through, with the
+//! peripheral controller behind the local controller's GPU interrupt.
 
+mod local_ic;
 mod peripheral_ic;
 
 use {
@@ -11,7 +15,7 @@ use {
         drivers,
         exception::{self, asynchronous::IRQHandlerDescriptor},
         memory::{Address, Virtual},
-        platform::device_driver::common::BoundedUsize,
+        platform::{cpu::BOOT_CORE_ID, device_driver::common::BoundedUsize},
     },
     core::fmt,
 };
@@ -42,6 +46,7 @@ pub enum IRQNumber {
 
 /// Representation of the Interrupt Controller.
 pub struct InterruptController {
+    local: local_ic::LocalIC,
     periph: peripheral_ic::PeripheralIC,
 }
 
@@ -83,8 +88,7 @@ impl fmt::Display for IRQNumber {
 }
 
 impl InterruptController {
-    // Restrict to 3 for now. This makes future code for local_ic.rs more straight forward.
-    const MAX_LOCAL_IRQ_NUMBER: usize = 3;
+    const MAX_LOCAL_IRQ_NUMBER: usize = local_ic::LocalIC::LOCAL_TIMER_IRQ;
     const MAX_PERIPHERAL_IRQ_NUMBER: usize = 63;
 
     pub const COMPATIBLE: &'static str = "BCM Interrupt Controller";
@@ -94,11 +98,41 @@ impl InterruptController {
     /// # Safety
     ///
     /// - The user must ensure to provide a correct MMIO start address.
-    pub const unsafe fn new(periph_mmio_start_addr: Address<Virtual>) -> Self {
+    pub const unsafe fn new(
+        local_mmio_start_addr: Address<Virtual>,
+        periph_mmio_start_addr: Address<Virtual>,
+    ) -> Self {
         Self {
+            local: local_ic::LocalIC::new(local_mmio_start_addr),
             periph: peripheral_ic::PeripheralIC::new(periph_mmio_start_addr),
         }
     }
+
+    /// Signal a peripheral IRQ as FIQ, taken by the boot core.
+    ///
+    /// Local sources are not supported.
+    pub fn route_fiq(&self, irq: &IRQNumber) -> Result<(), &'static str> {
+        match irq {
+            IRQNumber::Local(_) => Err("Only peripheral IRQs can be routed as FIQ"),
+            IRQNumber::Peripheral(pirq) => {
+                self.periph.route_fiq(pirq);
+                self.local.route_gpu_fiq(BOOT_CORE_ID as usize);
+                Ok(())
+            }
+        }
+    }
+
+    /// Set `bits` in mailbox `mailbox` of `core`, raising its mailbox IRQ if enabled there.
+    pub fn send_mailbox(&self, core: usize, mailbox: usize, bits: u32) {
+        self.local.send_mailbox(core, mailbox, bits);
+    }
+
+    /// Read and clear mailbox `mailbox` of the executing core.
+    ///
+    /// Handlers of the mailbox IRQs must do this, the IRQ stays asserted otherwise.
+    pub fn take_mailbox(&self, mailbox: usize) -> u32 {
+        self.local.take_mailbox(mailbox)
+    }
 }
 
 //------------------------------------------------------------------------------
@@ -111,6 +145,12 @@ impl drivers::interface::DeviceDriver for InterruptController {
     fn compatible(&self) -> &'static str {
         Self::COMPATIBLE
     }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        self.local.route_gpu_irq(BOOT_CORE_ID as usize);
+
+        Ok(())
+    }
 }
 
 impl exception::asynchronous::interface::IRQManager for InterruptController {
@@ -121,35 +161,95 @@ impl exception::asynchronous::interface::IRQManager for InterruptController {
         irq_handler_descriptor: exception::asynchronous::IRQHandlerDescriptor<Self::IRQNumberType>,
     ) -> Result<(), &'static str> {
         match irq_handler_descriptor.number() {
-            IRQNumber::Local(_) => unimplemented!("Local IRQ controller not implemented."),
+            IRQNumber::Local(lirq) => {
+                let local_descriptor = IRQHandlerDescriptor::new(
+                    lirq,
+                    irq_handler_descriptor.name(),
+                    irq_handler_descriptor.handler(),
+                )
+                .with_priority(irq_handler_descriptor.priority());
+
+                self.local.register_handler(local_descriptor)
+            }
             IRQNumber::Peripheral(pirq) => {
                 let periph_descriptor = IRQHandlerDescriptor::new(
                     pirq,
                     irq_handler_descriptor.name(),
                     irq_handler_descriptor.handler(),
-                );
+                )
+                .with_priority(irq_handler_descriptor.priority());
 
                 self.periph.register_handler(periph_descriptor)
             }
         }
     }
 
+    fn unregister_handler(
+        &self,
+        irq_handler_descriptor: exception::asynchronous::IRQHandlerDescriptor<Self::IRQNumberType>,
+    ) -> Result<(), &'static str> {
+        match irq_handler_descriptor.number() {
+            IRQNumber::Local(lirq) => {
+                let local_descriptor = IRQHandlerDescriptor::new(
+                    lirq,
+                    irq_handler_descriptor.name(),
+                    irq_handler_descriptor.handler(),
+                );
+
+                self.local.unregister_handler(local_descriptor)
+            }
+            IRQNumber::Peripheral(pirq) => {
+                let periph_descriptor = IRQHandlerDescriptor::new(
+                    pirq,
+                    irq_handler_descriptor.name(),
+                    irq_handler_descriptor.handler(),
+                );
+
+                self.periph.unregister_handler(periph_descriptor)
+            }
+        }
+    }
+
     fn enable(&self, irq: &Self::IRQNumberType) {
         match irq {
-            IRQNumber::Local(_) => unimplemented!("Local IRQ controller not implemented."),
+            IRQNumber::Local(lirq) => self.local.enable(lirq),
             IRQNumber::Peripheral(pirq) => self.periph.enable(pirq),
         }
     }
 
+    fn disable(&self, irq: &Self::IRQNumberType) {
+        match irq {
+            IRQNumber::Local(lirq) => self.local.disable(lirq),
+            IRQNumber::Peripheral(pirq) => self.periph.disable(pirq),
+        }
+    }
+
     fn handle_pending_irqs<'irq_context>(
         &'irq_context self,
         ic: &exception::asynchronous::IRQContext<'irq_context>,
     ) {
-        // It can only be a peripheral IRQ pending because enable() does not support local IRQs yet.
-        self.periph.handle_pending_irqs(ic)
+        self.local.handle_pending_irqs(ic);
+
+        // Only the core the GPU IRQ is routed to sees peripheral IRQs.
+        if self.local.is_gpu_irq_pending() {
+            self.periph.handle_pending_irqs(ic);
+        }
     }
 
     fn print_handler(&self) {
+        self.local.print_handler();
         self.periph.print_handler();
     }
+
+    fn print_stats(&self) {
+        self.local.print_stats();
+        self.periph.print_stats();
+    }
+
+    fn stats(&self, irq: &Self::IRQNumberType) -> Option<&exception::asynchronous::IRQStats> {
+        match irq {
+            IRQNumber::Local(lirq) => self.local.stats(lirq),
+            IRQNumber::Peripheral(pirq) => self.periph.stats(pirq),
+        }
+    }
 }
@@ -13,7 +13,7 @@ use {
     crate::{
         exception,
         platform::device_driver::common::MMIODerefWrapper,
-        synchronization::{self, IRQSafeNullLock, InitStateLock},
+        synchronization::{self, IRQSafeSpinLock, RwSpinLock},
     },
     tock_registers::{
         interfaces::{Readable, Writeable},
@@ -30,9 +30,13 @@ register_structs! {
     #[allow(non_snake_case)]
     WORegisterBlock {
         (0x00 => _reserved1),
+        (0x0c => FIQ_CONTROL: WriteOnly<u32>),
         (0x10 => ENABLE_1: WriteOnly<u32>),
         (0x14 => ENABLE_2: WriteOnly<u32>),
-        (0x18 => @END),
+        (0x18 => _reserved2),
+        (0x1c => DISABLE_1: WriteOnly<u32>),
+        (0x20 => DISABLE_2: WriteOnly<u32>),
+        (0x24 => @END),
     }
 }
 
@@ -52,8 +56,10 @@ type WriteOnlyRegisters = MMIODerefWrapper<WORegisterBlock>;
 /// Abstraction for the ReadOnly parts of the associated MMIO registers.
 type ReadOnlyRegisters = MMIODerefWrapper<RORegisterBlock>;
 
-type HandlerTable = [Option<exception::asynchronous::IRQHandlerDescriptor<PeripheralIRQ>>;
-    PeripheralIRQ::MAX_INCLUSIVE + 1];
+type HandlerTable =
+    [exception::asynchronous::IRQHandlerChain<PeripheralIRQ>; PeripheralIRQ::MAX_INCLUSIVE + 1];
+
+type StatsTable = [exception::asynchronous::IRQStats; PeripheralIRQ::MAX_INCLUSIVE + 1];
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -62,13 +68,16 @@ type HandlerTable = [Option<exception::asynchronous::IRQHandlerDescriptor<Periph
 /// Representation of the peripheral interrupt controller.
 pub struct PeripheralIC {
     /// Access to write registers is guarded with a lock.
-    wo_registers: IRQSafeNullLock<WriteOnlyRegisters>,
+    wo_registers: IRQSafeSpinLock<WriteOnlyRegisters>,
 
     /// Register read access is unguarded.
     ro_registers: ReadOnlyRegisters,
 
-    /// Stores registered IRQ handlers. Writable only during kernel init. RO afterwards.
-    handler_table: InitStateLock<HandlerTable>,
+    /// Stores registered IRQ handlers. Handlers can be added and removed at any time.
+    handler_table: RwSpinLock<HandlerTable>,
+
+    /// Per-IRQ statistics, updated without locking.
+    stats: StatsTable,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -83,12 +92,34 @@ impl PeripheralIC {
     /// - The user must ensure to provide a correct MMIO start address.
     pub const unsafe fn new(mmio_start_addr: Address<Virtual>) -> Self {
         Self {
-            wo_registers: IRQSafeNullLock::new(WriteOnlyRegisters::new(mmio_start_addr)),
+            wo_registers: IRQSafeSpinLock::new(WriteOnlyRegisters::new(mmio_start_addr)),
             ro_registers: ReadOnlyRegisters::new(mmio_start_addr),
-            handler_table: InitStateLock::new([None; PeripheralIRQ::MAX_INCLUSIVE + 1]),
+            handler_table: RwSpinLock::new(
+                [exception::asynchronous::IRQHandlerChain::new(); PeripheralIRQ::MAX_INCLUSIVE + 1],
+            ),
+            stats: exception::asynchronous::IRQStats::table(),
         }
     }
 
+    /// Signal `irq` as FIQ instead of IRQ.
+    ///
+    /// The FIQ control register selects a single source, replacing any routed before.
+    pub fn route_fiq(&self, irq: &PeripheralIRQ) {
+        const FIQ_ENABLE: u32 = 1 << 7;
+
+        self.wo_registers.lock(|regs| {
+            let disable_reg = if irq.get() <= 31 {
+                &regs.DISABLE_1
+            } else {
+                &regs.DISABLE_2
+            };
+
+            // Sources 0 to 63 are the peripheral IRQs, numbered alike.
+            disable_reg.set(1 << (irq.get() % 32));
+            regs.FIQ_CONTROL.set(FIQ_ENABLE | irq.get() as u32);
+        });
+    }
+
     /// Query the list of pending IRQs.
     fn pending_irqs(&self) -> PendingIRQs {
         let pending_mask: u64 = (u64::from(self.ro_registers.PENDING_2.get()) << 32)
@@ -116,14 +147,29 @@ impl exception::asynchronous::interface::IRQManager for PeripheralIC {
         self.handler_table.write(|table| {
             let irq_number = irq_handler_descriptor.number().get();
 
-            if table[irq_number].is_some() {
-                return Err("IRQ handler already registered");
-            }
+            table[irq_number].register(irq_handler_descriptor)
+        })
+    }
 
-            table[irq_number] = Some(irq_handler_descriptor);
+    fn unregister_handler(
+        &self,
+        irq_handler_descriptor: exception::asynchronous::IRQHandlerDescriptor<Self::IRQNumberType>,
+    ) -> Result<(), &'static str> {
+        use exception::asynchronous::interface::IRQManager;
 
-            Ok(())
-        })
+        let irq = irq_handler_descriptor.number();
+
+        let now_empty = self.handler_table.write(|table| {
+            let chain = &mut table[irq.get()];
+            chain.unregister(irq_handler_descriptor)?;
+            Ok(chain.is_empty())
+        })?;
+
+        if now_empty {
+            self.disable(&irq);
+        }
+
+        Ok(())
     }
 
     fn enable(&self, irq: &Self::IRQNumberType) {
@@ -142,34 +188,88 @@ impl exception::asynchronous::interface::IRQManager for PeripheralIC {
         });
     }
 
+    fn disable(&self, irq: &Self::IRQNumberType) {
+        self.wo_registers.lock(|regs| {
+            let disable_reg = if irq.get() <= 31 {
+                &regs.DISABLE_1
+            } else {
+                &regs.DISABLE_2
+            };
+
+            // Like enabling, writing a 1 only clears the corresponding IRQ enable bit.
+            disable_reg.set(1 << (irq.get() % 32));
+        });
+    }
+
     fn handle_pending_irqs<'irq_context>(
         &'irq_context self,
         _ic: &exception::asynchronous::IRQContext<'irq_context>,
     ) {
+        for irq_number in self.pending_irqs() {
+            // Call the IRQ handlers outside of the table lock, so that they may register handlers.
+            let chain = self.handler_table.read(|table| table[irq_number]);
+            self.stats[irq_number].dispatch(&chain);
+        }
+    }
+
+    fn print_handler(&self) {
+        use crate::info;
+
+        info!("      Peripheral handler:");
+
         self.handler_table.read(|table| {
-            for irq_number in self.pending_irqs() {
-                match table[irq_number] {
-                    None => panic!("No handler registered for IRQ {}", irq_number),
-                    Some(descriptor) => {
-                        // Call the IRQ handler. Panics on failure.
-                        descriptor.handler().handle().expect("Error handling IRQ");
-                    }
+            for (i, chain) in table.iter().enumerate() {
+                if !chain.is_empty() {
+                    info!("            {: >3}. {}", i, chain);
                 }
             }
-        })
+        });
     }
 
-    fn print_handler(&self) {
+    fn print_stats(&self) {
         use crate::info;
 
-        info!("      Peripheral handler:");
+        info!("      Peripheral IRQ stats:");
 
         self.handler_table.read(|table| {
-            for (i, opt) in table.iter().enumerate() {
-                if let Some(handler) = opt {
-                    info!("            {: >3}. {}", i, handler.name());
+            for (i, (chain, stats)) in table.iter().zip(self.stats.iter()).enumerate() {
+                if !chain.is_empty() || !stats.is_unused() {
+                    info!("            {: >3}. {}: {}", i, chain, stats);
                 }
             }
         });
     }
+
+    fn stats(&self, irq: &Self::IRQNumberType) -> Option<&exception::asynchronous::IRQStats> {
+        Some(&self.stats[irq.get()])
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_ic(reg: &mut [u32; 16]) -> PeripheralIC {
+        unsafe { PeripheralIC::new(Address::<Virtual>::new(reg as *mut _ as usize)) }
+    }
+
+    /// The routed source is disabled as IRQ and selected in the FIQ control register.
+    #[test_case]
+    fn route_fiq_selects_source() {
+        let mut reg = [0u32; 16];
+        let ic = fake_ic(&mut reg);
+
+        ic.route_fiq(&PeripheralIRQ::new(57));
+        assert_eq!(reg[0x0c / 4], (1 << 7) | 57);
+        assert_eq!(reg[0x20 / 4], 1 << (57 - 32));
+        assert_eq!(reg[0x1c / 4], 0);
+
+        ic.route_fiq(&PeripheralIRQ::new(3));
+        assert_eq!(reg[0x0c / 4], (1 << 7) | 3);
+        assert_eq!(reg[0x1c / 4], 1 << 3);
+    }
 }
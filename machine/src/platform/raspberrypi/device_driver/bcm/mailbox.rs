@@ -12,7 +12,7 @@
 
 #![allow(dead_code)]
 
-use crate::synchronization::IRQSafeNullLock;
+use crate::synchronization::IRQSafeSpinLock;
 use {
     super::BcmHost,
     crate::{
@@ -43,7 +43,7 @@ struct MailboxInner {
 
 /// Mailbox driver
 pub struct Mailbox {
-    inner: IRQSafeNullLock<MailboxInner>,
+    inner: IRQSafeSpinLock<MailboxInner>,
 }
 
 /// Public interface to the mailbox.
@@ -17,7 +17,7 @@ use {
             device_driver::{common::MMIODerefWrapper, gpio},
             BcmHost,
         },
-        synchronization::{interface::Mutex, IRQSafeNullLock},
+        synchronization::{interface::Mutex, IRQSafeSpinLock},
     },
     cfg_if::cfg_if,
     core::{
@@ -159,7 +159,7 @@ struct MiniUartInner {
 }
 
 pub struct MiniUart {
-    inner: IRQSafeNullLock<MiniUartInner>,
+    inner: IRQSafeSpinLock<MiniUartInner>,
 }
 
 /// Divisor values for common baud rates
@@ -198,7 +198,7 @@ impl MiniUart {
     /// - The user must ensure to provide a correct MMIO start address.
     pub const unsafe fn new(mmio_base_addr: Address<Virtual>) -> Self {
         Self {
-            inner: IRQSafeNullLock::new(MiniUartInner::new(mmio_base_addr)),
+            inner: IRQSafeSpinLock::new(MiniUartInner::new(mmio_base_addr)),
         }
     }
 
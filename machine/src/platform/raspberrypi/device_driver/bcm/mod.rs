@@ -10,8 +10,9 @@ pub mod interrupt_controller;
 // pub mod mailbox;
 pub mod mini_uart;
 pub mod pl011_uart;
+pub mod system_timer;
 // pub mod power;
 
 #[cfg(feature = "rpi3")]
 pub use interrupt_controller::*;
-pub use {gpio::*, mini_uart::*, pl011_uart::*};
+pub use {gpio::*, mini_uart::*, pl011_uart::*, system_timer::*};
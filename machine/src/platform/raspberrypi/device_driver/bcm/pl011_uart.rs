@@ -16,7 +16,7 @@ use {
         exception,
         memory::{Address, Virtual},
         platform::device_driver::{common::MMIODerefWrapper, gpio, IRQNumber},
-        synchronization::{interface::Mutex, IRQSafeNullLock},
+        synchronization::{interface::Mutex, IRQSafeSpinLock},
     },
     core::fmt::{self, Arguments},
     snafu::Snafu,
@@ -271,7 +271,7 @@ struct PL011UartInner {
 //--------------------------------------------------------------------------------------------------
 
 pub struct PL011Uart {
-    inner: IRQSafeNullLock<PL011UartInner>,
+    inner: IRQSafeSpinLock<PL011UartInner>,
 }
 
 pub struct RateDivisors {
@@ -325,7 +325,7 @@ impl PL011Uart {
     /// - The user must ensure to provide a correct MMIO start address.
     pub const unsafe fn new(mmio_base_addr: Address<Virtual>) -> Self {
         Self {
-            inner: IRQSafeNullLock::new(PL011UartInner::new(mmio_base_addr)),
+            inner: IRQSafeSpinLock::new(PL011UartInner::new(mmio_base_addr)),
         }
     }
 
@@ -566,12 +566,17 @@ impl interface::ConsoleOps for PL011Uart {
 impl interface::All for PL011Uart {}
 
 impl exception::asynchronous::interface::IRQHandler for PL011Uart {
-    fn handle(&self) -> Result<(), &'static str> {
+    fn handle(&self) -> Result<exception::HandlerResult, &'static str> {
         use interface::ConsoleOps;
 
-        self.inner.lock(|inner| {
+        Ok(self.inner.lock(|inner| {
             let pending = inner.registers.MaskedInterruptStatus.extract();
 
+            // Not raised by this UART, the line may be shared.
+            if pending.get() == 0 {
+                return exception::HandlerResult::Unhandled;
+            }
+
             // Clear all pending IRQs.
             inner.registers.InterruptClear.write(ICR::ALL::SET);
 
@@ -582,9 +587,9 @@ impl exception::asynchronous::interface::IRQHandler for PL011Uart {
                 //     inner.write_char(c)
                 // }
             }
-        });
 
-        Ok(())
+            exception::HandlerResult::Handled
+        }))
     }
 }
 
@@ -0,0 +1,286 @@
+/*
+ * SPDX-License-Identifier: BlueOak-1.0.0
+ * Copyright (c) Berkus Decker <berkus+vesper@metta.systems>
+ */
+
+//! BCM2835 system timer.
+//!
+//! A free-running 1 MHz counter, shared by all cores and the VideoCore, with four compare
+//! channels raising an interrupt when the low 32 bits of the counter match. The GPU firmware
+//! uses channels 0 and 2, the driver uses channel 1.
+//!
+//! Serves as an alternative [`time`] backend, see [`time::TimeManager::set_backend`].
+
+use {
+    crate::{
+        exception,
+        memory::{Address, Virtual},
+        platform::device_driver::{common::MMIODerefWrapper, IRQNumber},
+        time::{self, interface},
+    },
+    core::time::Duration,
+    tock_registers::{
+        interfaces::{Readable, Writeable},
+        register_bitfields, register_structs,
+        registers::{ReadOnly, ReadWrite},
+    },
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Descriptions taken from
+// https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf
+register_bitfields! {
+    u32,
+
+    /// System Timer Control/Status
+    CS [
+        /// Channel 3 matched, write 1 to clear.
+        M3 OFFSET(3) NUMBITS(1) [],
+        /// Channel 2 matched, write 1 to clear.
+        M2 OFFSET(2) NUMBITS(1) [],
+        /// Channel 1 matched, write 1 to clear.
+        M1 OFFSET(1) NUMBITS(1) [],
+        /// Channel 0 matched, write 1 to clear.
+        M0 OFFSET(0) NUMBITS(1) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => CS: ReadWrite<u32, CS::Register>),
+        (0x04 => CLO: ReadOnly<u32>),
+        (0x08 => CHI: ReadOnly<u32>),
+        (0x0c => C: [ReadWrite<u32>; 4]),
+        (0x1c => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+/// The compare channel in use, the others belong to the GPU or are left free.
+const CHANNEL: usize = 1;
+
+/// Least distance of a deadline from the counter, leaving time for the compare write to land.
+const MIN_DELTA_TICKS: u64 = 2;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Representation of the BCM system timer.
+pub struct SystemTimer {
+    /// Only the clock event writes registers, which the time manager serializes.
+    registers: Registers,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// The counter value to program for expiring at `target`, with the counter at `now`.
+///
+/// Passed deadlines expire right away, far ones are brought within the 32-bit compare range
+/// and expire early, which the time manager handles by rearming.
+fn compare_target(target: u64, now: u64) -> u64 {
+    target.clamp(now + MIN_DELTA_TICKS, now + u64::from(u32::MAX))
+}
+
+/// Whether two durations measured over the same interval agree within 5%.
+fn agrees(reference: Duration, measured: Duration) -> bool {
+    reference.abs_diff(measured) <= reference / 20
+}
+
+impl SystemTimer {
+    /// The 64-bit counter, in microseconds.
+    fn counter(&self) -> u64 {
+        // The two halves are read separately, retry if the low half wrapped in between.
+        loop {
+            let hi = self.registers.CHI.get();
+            let lo = self.registers.CLO.get();
+            if hi == self.registers.CHI.get() {
+                return (u64::from(hi) << 32) | u64::from(lo);
+            }
+        }
+    }
+
+    fn clear_match(&self) {
+        self.registers.CS.write(CS::M1::SET);
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl SystemTimer {
+    pub const COMPATIBLE: &'static str = "BCM System Timer";
+
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct MMIO start address.
+    pub const unsafe fn new(mmio_base_addr: Address<Virtual>) -> Self {
+        Self {
+            registers: Registers::new(mmio_base_addr),
+        }
+    }
+
+    /// Measure an interval of about 10 ms on both this timer and the ARM generic timer.
+    ///
+    /// Returns the interval as seen by the generic timer and by this timer, or an error if
+    /// they disagree by more than 5%.
+    pub fn cross_check(&self) -> Result<(Duration, Duration), &'static str> {
+        use interface::ClockSource;
+
+        const INTERVAL: Duration = Duration::from_millis(10);
+
+        let arch_timer = time::arch_timer();
+
+        let arch_start = arch_timer.ticks();
+        let start = self.ticks();
+        time::time_manager().spin_for(INTERVAL);
+        let end = self.ticks();
+        let arch_end = arch_timer.ticks();
+
+        let reference = arch_timer.ticks_to_duration(arch_end - arch_start);
+        let measured = self.ticks_to_duration(end - start);
+
+        if !agrees(reference, measured) {
+            return Err("System timer disagrees with CNTPCT");
+        }
+
+        Ok((reference, measured))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// OS Interface Code
+//--------------------------------------------------------------------------------------------------
+
+impl interface::ClockSource for SystemTimer {
+    fn resolution(&self) -> Duration {
+        Duration::from_micros(1)
+    }
+
+    fn uptime(&self) -> Duration {
+        Duration::from_micros(self.counter())
+    }
+
+    fn ticks(&self) -> u64 {
+        self.counter()
+    }
+
+    fn ticks_to_duration(&self, ticks: u64) -> Duration {
+        Duration::from_micros(ticks)
+    }
+}
+
+impl interface::ClockEvent for SystemTimer {
+    fn set_deadline(&self, deadline: Duration) {
+        let target = u64::try_from(deadline.as_micros()).unwrap_or(u64::MAX);
+
+        self.clear_match();
+
+        // Only the low half is compared, a match the counter passed before the write landed
+        // would come around again only after 2^32 µs. Reprogram until the write is in time.
+        loop {
+            let compare = compare_target(target, self.counter());
+            self.registers.C[CHANNEL].set(compare as u32);
+
+            if self.counter() < compare {
+                break;
+            }
+        }
+    }
+
+    fn stop(&self) {
+        self.clear_match();
+
+        // Matching cannot be disabled, move it as far away as the compare range allows.
+        let now = self.registers.CLO.get();
+        self.registers.C[CHANNEL].set(now.wrapping_sub(1));
+    }
+}
+
+impl interface::TimerBackend for SystemTimer {
+    fn name(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+}
+
+impl crate::drivers::interface::DeviceDriver for SystemTimer {
+    type IRQNumberType = IRQNumber;
+
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        interface::ClockEvent::stop(self);
+        Ok(())
+    }
+
+    fn register_and_enable_irq_handler(
+        &'static self,
+        irq_number: &Self::IRQNumberType,
+    ) -> Result<(), &'static str> {
+        use exception::asynchronous::{irq_manager, IRQHandlerDescriptor, IRQPriority};
+
+        let descriptor = IRQHandlerDescriptor::new(*irq_number, Self::COMPATIBLE, self)
+            .with_priority(IRQPriority::High);
+
+        irq_manager().register_handler(descriptor)?;
+        irq_manager().enable(irq_number);
+
+        Ok(())
+    }
+}
+
+impl exception::asynchronous::interface::IRQHandler for SystemTimer {
+    fn handle(&self) -> Result<exception::HandlerResult, &'static str> {
+        if !self.registers.CS.is_set(CS::M1) {
+            return Ok(exception::HandlerResult::Unhandled);
+        }
+
+        self.clear_match();
+
+        // A stopped channel still matches once per counter wrap, with no timeouts to run.
+        let time_manager = time::time_manager();
+        if time_manager.backend_name() == Self::COMPATIBLE {
+            time_manager.handle_clock_event();
+        }
+
+        Ok(exception::HandlerResult::Handled)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn compare_target_stays_within_range() {
+        let now = 0x1_0000_0000;
+        assert_eq!(compare_target(now + 100, now), now + 100);
+        assert_eq!(compare_target(now - 100, now), now + MIN_DELTA_TICKS);
+        assert_eq!(compare_target(u64::MAX, now), now + u64::from(u32::MAX));
+    }
+
+    #[test_case]
+    fn cross_check_tolerates_five_percent() {
+        let reference = Duration::from_millis(10);
+        assert!(agrees(reference, Duration::from_micros(10_400)));
+        assert!(agrees(reference, Duration::from_micros(9_600)));
+        assert!(!agrees(reference, Duration::from_micros(11_000)));
+    }
+}
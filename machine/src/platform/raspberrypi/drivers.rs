@@ -4,7 +4,8 @@ use {
         console, drivers,
         exception::{self as generic_exception},
         memory::{self, mmu::MMIODescriptor},
-        platform::{device_driver, memory::map::mmio},
+        platform::{device_driver, memory::board::board_memory_map},
+        time,
     },
     core::{
         mem::MaybeUninit,
@@ -42,11 +43,20 @@ pub unsafe fn init() -> Result<(), &'static str> {
     driver_uart()?;
     driver_gpio()?;
     driver_interrupt_controller()?;
+    driver_timer()?;
+    driver_system_timer()?;
 
     INIT_DONE.store(true, Ordering::Relaxed);
     Ok(())
 }
 
+/// The interrupt controller, once it is instantiated.
+pub(in crate::platform) fn interrupt_controller() -> Option<&'static InterruptController> {
+    INTERRUPT_CONTROLLER_READY
+        .load(Ordering::Acquire)
+        .then(|| unsafe { INTERRUPT_CONTROLLER.assume_init_ref() })
+}
+
 /// Minimal code needed to bring up the console in QEMU (for testing only). This is often less steps
 /// than on real hardware due to QEMU's abstractions.
 #[cfg(test)]
@@ -57,19 +67,41 @@ pub fn qemu_bring_up_console() {
     };
 }
 
+/// Minimal code needed to take IPIs in QEMU (for testing only): the interrupt controller is
+/// brought up and registered as IRQ manager, other drivers are left alone.
+#[cfg(test)]
+pub fn qemu_bring_up_interrupts() {
+    use crate::drivers::interface::DeviceDriver;
+
+    unsafe {
+        instantiate_interrupt_controller()
+            .and_then(|()| INTERRUPT_CONTROLLER.assume_init_ref().init())
+            .and_then(|()| post_init_interrupt_controller())
+            .unwrap_or_else(|_| crate::qemu::semihosting::exit_failure());
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(feature = "rpi3")]
+type InterruptController = device_driver::InterruptController;
+
+#[cfg(feature = "rpi4")]
+type InterruptController = device_driver::GICv2;
+
 //--------------------------------------------------------------------------------------------------
 // Global instances
 //--------------------------------------------------------------------------------------------------
 
 static mut PL011_UART: MaybeUninit<device_driver::PL011Uart> = MaybeUninit::uninit();
 static mut GPIO: MaybeUninit<device_driver::GPIO> = MaybeUninit::uninit();
+static mut SYSTEM_TIMER: MaybeUninit<device_driver::SystemTimer> = MaybeUninit::uninit();
 
-#[cfg(feature = "rpi3")]
-static mut INTERRUPT_CONTROLLER: MaybeUninit<device_driver::InterruptController> =
-    MaybeUninit::uninit();
+static mut INTERRUPT_CONTROLLER: MaybeUninit<InterruptController> = MaybeUninit::uninit();
 
-#[cfg(feature = "rpi4")]
-static mut INTERRUPT_CONTROLLER: MaybeUninit<device_driver::GICv2> = MaybeUninit::uninit();
+static INTERRUPT_CONTROLLER_READY: AtomicBool = AtomicBool::new(false);
 
 //--------------------------------------------------------------------------------------------------
 // Private Code
@@ -77,7 +109,8 @@ static mut INTERRUPT_CONTROLLER: MaybeUninit<device_driver::GICv2> = MaybeUninit
 
 /// This must be called only after successful init of the memory subsystem.
 unsafe fn instantiate_uart() -> Result<(), &'static str> {
-    let mmio_descriptor = MMIODescriptor::new(mmio::PL011_UART_BASE, mmio::PL011_UART_SIZE);
+    let uart = board_memory_map().pl011_uart();
+    let mmio_descriptor = MMIODescriptor::new(uart.base, uart.size);
     let virt_addr =
         memory::mmu::kernel_map_mmio(device_driver::PL011Uart::COMPATIBLE, &mmio_descriptor)?;
 
@@ -95,7 +128,8 @@ unsafe fn post_init_pl011_uart() -> Result<(), &'static str> {
 
 /// This must be called only after successful init of the memory subsystem.
 unsafe fn instantiate_gpio() -> Result<(), &'static str> {
-    let mmio_descriptor = MMIODescriptor::new(mmio::GPIO_BASE, mmio::GPIO_SIZE);
+    let gpio = board_memory_map().gpio();
+    let mmio_descriptor = MMIODescriptor::new(gpio.base, gpio.size);
     let virt_addr =
         memory::mmu::kernel_map_mmio(device_driver::GPIO::COMPATIBLE, &mmio_descriptor)?;
 
@@ -110,17 +144,63 @@ unsafe fn post_init_gpio() -> Result<(), &'static str> {
     Ok(())
 }
 
+/// This must be called only after successful init of the memory subsystem.
+unsafe fn instantiate_system_timer() -> Result<(), &'static str> {
+    let timer = board_memory_map().system_timer();
+    let mmio_descriptor = MMIODescriptor::new(timer.base, timer.size);
+    let virt_addr =
+        memory::mmu::kernel_map_mmio(device_driver::SystemTimer::COMPATIBLE, &mmio_descriptor)?;
+
+    SYSTEM_TIMER.write(device_driver::SystemTimer::new(virt_addr));
+
+    Ok(())
+}
+
+/// This must be called only after successful init of the system timer driver.
+///
+/// A system timer that disagrees with the ARM generic timer is left unused, without failing
+/// the boot. With the `system_timer` feature, one that agrees becomes the time backend.
+unsafe fn post_init_system_timer() -> Result<(), &'static str> {
+    let system_timer = SYSTEM_TIMER.assume_init_ref();
+
+    match system_timer.cross_check() {
+        Ok((reference, measured)) => {
+            crate::info!(
+                "System timer measured {:?} over {:?} of CNTPCT",
+                measured,
+                reference
+            );
+
+            #[cfg(feature = "system_timer")]
+            time::time_manager().set_backend(system_timer)?;
+        }
+        Err(e) => crate::warn!("{}, not using it", e),
+    }
+
+    Ok(())
+}
+
 /// This must be called only after successful init of the memory subsystem.
 #[cfg(feature = "rpi3")]
 unsafe fn instantiate_interrupt_controller() -> Result<(), &'static str> {
-    let periph_mmio_descriptor =
-        MMIODescriptor::new(mmio::PERIPHERAL_IC_BASE, mmio::PERIPHERAL_IC_SIZE);
+    let board_map = board_memory_map();
+
+    let local_mmio_descriptor =
+        MMIODescriptor::new(board_map.local_ic().base, board_map.local_ic().size);
+    let local_virt_addr = memory::mmu::kernel_map_mmio("BCM Local IC", &local_mmio_descriptor)?;
+
+    let periph = board_map.peripheral_ic();
+    let periph_mmio_descriptor = MMIODescriptor::new(periph.base, periph.size);
     let periph_virt_addr = memory::mmu::kernel_map_mmio(
         device_driver::InterruptController::COMPATIBLE,
         &periph_mmio_descriptor,
     )?;
 
-    INTERRUPT_CONTROLLER.write(device_driver::InterruptController::new(periph_virt_addr));
+    INTERRUPT_CONTROLLER.write(device_driver::InterruptController::new(
+        local_virt_addr,
+        periph_virt_addr,
+    ));
+    INTERRUPT_CONTROLLER_READY.store(true, Ordering::Release);
 
     Ok(())
 }
@@ -128,13 +208,16 @@ unsafe fn instantiate_interrupt_controller() -> Result<(), &'static str> {
 /// This must be called only after successful init of the memory subsystem.
 #[cfg(feature = "rpi4")]
 unsafe fn instantiate_interrupt_controller() -> Result<(), &'static str> {
-    let gicd_mmio_descriptor = MMIODescriptor::new(mmio::GICD_BASE, mmio::GICD_SIZE);
+    let board_map = board_memory_map();
+
+    let gicd_mmio_descriptor = MMIODescriptor::new(board_map.gicd().base, board_map.gicd().size);
     let gicd_virt_addr = memory::mmu::kernel_map_mmio("GICv2 GICD", &gicd_mmio_descriptor)?;
 
-    let gicc_mmio_descriptor = MMIODescriptor::new(mmio::GICC_BASE, mmio::GICC_SIZE);
+    let gicc_mmio_descriptor = MMIODescriptor::new(board_map.gicc().base, board_map.gicc().size);
     let gicc_virt_addr = memory::mmu::kernel_map_mmio("GICV2 GICC", &gicc_mmio_descriptor)?;
 
     INTERRUPT_CONTROLLER.write(device_driver::GICv2::new(gicd_virt_addr, gicc_virt_addr));
+    INTERRUPT_CONTROLLER_READY.store(true, Ordering::Release);
 
     Ok(())
 }
@@ -143,7 +226,7 @@ unsafe fn instantiate_interrupt_controller() -> Result<(), &'static str> {
 unsafe fn post_init_interrupt_controller() -> Result<(), &'static str> {
     generic_exception::asynchronous::register_irq_manager(INTERRUPT_CONTROLLER.assume_init_ref());
 
-    Ok(())
+    exception::asynchronous::ipi_init()
 }
 
 /// Function needs to ensure that driver registration happens only after correct instantiation.
@@ -155,7 +238,7 @@ unsafe fn driver_uart() -> Result<(), &'static str> {
         Some(post_init_pl011_uart),
         Some(exception::asynchronous::irq_map::PL011_UART),
     );
-    drivers::driver_manager().register_driver(uart_descriptor);
+    drivers::driver_manager().register_driver(uart_descriptor)?;
 
     Ok(())
 }
@@ -166,7 +249,7 @@ unsafe fn driver_gpio() -> Result<(), &'static str> {
 
     let gpio_descriptor =
         drivers::DeviceDriverDescriptor::new(GPIO.assume_init_ref(), Some(post_init_gpio), None);
-    drivers::driver_manager().register_driver(gpio_descriptor);
+    drivers::driver_manager().register_driver(gpio_descriptor)?;
 
     Ok(())
 }
@@ -180,7 +263,33 @@ unsafe fn driver_interrupt_controller() -> Result<(), &'static str> {
         Some(post_init_interrupt_controller),
         None,
     );
-    drivers::driver_manager().register_driver(interrupt_controller_descriptor);
+    drivers::driver_manager().register_driver(interrupt_controller_descriptor)?;
+
+    Ok(())
+}
+
+/// The timer needs no instantiation, it is part of every core.
+unsafe fn driver_timer() -> Result<(), &'static str> {
+    let timer_descriptor = drivers::DeviceDriverDescriptor::new(
+        time::time_manager(),
+        None,
+        Some(exception::asynchronous::irq_map::PHYS_TIMER),
+    );
+    drivers::driver_manager().register_driver(timer_descriptor)?;
+
+    Ok(())
+}
+
+/// Function needs to ensure that driver registration happens only after correct instantiation.
+unsafe fn driver_system_timer() -> Result<(), &'static str> {
+    instantiate_system_timer()?;
+
+    let system_timer_descriptor = drivers::DeviceDriverDescriptor::new(
+        SYSTEM_TIMER.assume_init_ref(),
+        Some(post_init_system_timer),
+        Some(exception::asynchronous::irq_map::SYSTEM_TIMER),
+    );
+    drivers::driver_manager().register_driver(system_timer_descriptor)?;
 
     Ok(())
 }
@@ -4,6 +4,20 @@
 
 //! Platform asynchronous exception handling.
 
+use {
+    crate::{
+        exception::{
+            asynchronous::{
+                handle_ipi, interface::IRQHandler, irq_manager, IRQHandlerDescriptor, IRQPriority,
+                IpiKind,
+            },
+            HandlerResult,
+        },
+        platform::drivers::interrupt_controller,
+    },
+    aarch64_cpu::asm::barrier,
+};
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
@@ -13,7 +27,20 @@ pub use crate::platform::device_driver::IRQNumber;
 
 #[cfg(feature = "rpi3")]
 pub(in crate::platform) mod irq_map {
-    use crate::platform::device_driver::{IRQNumber, PeripheralIRQ};
+    use crate::platform::device_driver::{IRQNumber, LocalIRQ, PeripheralIRQ};
+
+    /// Non-secure EL1 physical timer, banked per core.
+    pub const PHYS_TIMER: IRQNumber = IRQNumber::Local(LocalIRQ::new(1));
+    /// Core mailboxes 0 to 3, banked per core.
+    pub const MAILBOX: [IRQNumber; 4] = [
+        IRQNumber::Local(LocalIRQ::new(4)),
+        IRQNumber::Local(LocalIRQ::new(5)),
+        IRQNumber::Local(LocalIRQ::new(6)),
+        IRQNumber::Local(LocalIRQ::new(7)),
+    ];
+
+    /// System timer compare channel 1.
+    pub const SYSTEM_TIMER: IRQNumber = IRQNumber::Peripheral(PeripheralIRQ::new(1));
 
     pub const PL011_UART: IRQNumber = IRQNumber::Peripheral(PeripheralIRQ::new(57));
 }
@@ -22,5 +49,178 @@ pub(in crate::platform) mod irq_map {
 pub(in crate::platform) mod irq_map {
     use crate::platform::device_driver::IRQNumber;
 
+    /// Non-secure EL1 physical timer PPI.
+    pub const PHYS_TIMER: IRQNumber = IRQNumber::new(30);
+
+    /// System timer compare channel 1, VideoCore interrupt 1.
+    pub const SYSTEM_TIMER: IRQNumber = IRQNumber::new(97);
+
     pub const PL011_UART: IRQNumber = IRQNumber::new(153);
 }
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The core mailbox carrying IPIs, one bit per kind. The other mailboxes are free to use.
+#[cfg(feature = "rpi3")]
+const IPI_MAILBOX: usize = 0;
+
+/// Receives all IPIs, raised as bits in the IPI mailbox.
+#[cfg(feature = "rpi3")]
+struct IpiReceiver;
+
+/// Receives the IPIs of one kind, raised as the SGI of the same number.
+#[cfg(feature = "rpi4")]
+struct IpiReceiver(IpiKind);
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(feature = "rpi3")]
+static IPI_RECEIVER: IpiReceiver = IpiReceiver;
+
+#[cfg(feature = "rpi4")]
+static IPI_RECEIVERS: [IpiReceiver; IpiKind::ALL.len()] = [
+    IpiReceiver(IpiKind::Reschedule),
+    IpiReceiver(IpiKind::TlbShootdown),
+    IpiReceiver(IpiKind::Stop),
+];
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(feature = "rpi3")]
+impl IRQHandler for IpiReceiver {
+    fn handle(&self) -> Result<HandlerResult, &'static str> {
+        let pending = take_mailbox(IPI_MAILBOX);
+        if pending == 0 {
+            return Ok(HandlerResult::Unhandled);
+        }
+
+        for kind in IpiKind::ALL {
+            if pending & (1 << kind.number()) != 0 {
+                handle_ipi(kind);
+            }
+        }
+
+        Ok(HandlerResult::Handled)
+    }
+}
+
+#[cfg(feature = "rpi4")]
+impl IRQHandler for IpiReceiver {
+    fn handle(&self) -> Result<HandlerResult, &'static str> {
+        handle_ipi(self.0);
+        Ok(HandlerResult::Handled)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Set `bits` in mailbox `mailbox` of `core`, raising its mailbox IRQ if enabled there.
+///
+/// Mailbox 0 carries IPIs, see [`send_ipi`].
+#[cfg(feature = "rpi3")]
+pub fn send_mailbox(core: usize, mailbox: usize, bits: u32) -> Result<(), &'static str> {
+    let ic = interrupt_controller().ok_or("Interrupt controller not initialized")?;
+    ic.send_mailbox(core, mailbox, bits);
+    Ok(())
+}
+
+/// Read and clear mailbox `mailbox` of the executing core.
+///
+/// Handlers of the mailbox IRQs must do this, the IRQ stays asserted otherwise.
+#[cfg(feature = "rpi3")]
+pub fn take_mailbox(mailbox: usize) -> u32 {
+    interrupt_controller().map_or(0, |ic| ic.take_mailbox(mailbox))
+}
+
+/// Send an IPI to the cores in `target_mask`, by setting the kind's bit in their IPI mailbox.
+#[cfg(feature = "rpi3")]
+pub fn send_ipi(target_mask: u64, kind: IpiKind) -> Result<(), &'static str> {
+    let ic = interrupt_controller().ok_or("Interrupt controller not initialized")?;
+
+    // Make preceding memory writes visible to the target cores before raising the IPI.
+    barrier::dsb(barrier::ISHST);
+
+    for core in (0..crate::platform::cpu::MAX_CORES).filter(|core| target_mask & (1 << core) != 0) {
+        ic.send_mailbox(core, IPI_MAILBOX, 1 << kind.number());
+    }
+
+    Ok(())
+}
+
+/// Send an IPI to the cores in `target_mask`, as the SGI numbered like the kind.
+#[cfg(feature = "rpi4")]
+pub fn send_ipi(target_mask: u64, kind: IpiKind) -> Result<(), &'static str> {
+    let gic = interrupt_controller().ok_or("Interrupt controller not initialized")?;
+
+    // Make preceding memory writes visible to the target cores before raising the IPI.
+    barrier::dsb(barrier::ISHST);
+
+    // Core numbers match the CPU interface numbers of the GIC.
+    gic.send_sgi(kind.number(), target_mask as u8);
+
+    Ok(())
+}
+
+/// Signal `irq_number` as FIQ, taken by the boot core.
+///
+/// Through the peripheral controller's FIQ control on the BCM controller, or as Group 0
+/// interrupt on the GIC.
+pub fn route_fiq(irq_number: &IRQNumber) -> Result<(), &'static str> {
+    let ic = interrupt_controller().ok_or("Interrupt controller not initialized")?;
+    ic.route_fiq(irq_number)
+}
+
+/// Let the executing core receive IPIs, by enabling its mailbox IRQ.
+#[cfg(feature = "rpi3")]
+pub fn ipi_init_core() -> Result<(), &'static str> {
+    interrupt_controller().ok_or("Interrupt controller not initialized")?;
+    irq_manager().enable(&irq_map::MAILBOX[IPI_MAILBOX]);
+    Ok(())
+}
+
+/// Let the executing core receive IPIs, by enabling its CPU interface and the SGIs.
+#[cfg(feature = "rpi4")]
+pub fn ipi_init_core() -> Result<(), &'static str> {
+    let gic = interrupt_controller().ok_or("Interrupt controller not initialized")?;
+    gic.init_cpu_interface();
+
+    for receiver in IPI_RECEIVERS.iter() {
+        irq_manager().enable(&IRQNumber::new(receiver.0.number()));
+    }
+    Ok(())
+}
+
+/// Register the IPI handlers and let the boot core receive IPIs.
+///
+/// This must be called only after the interrupt controller is registered as IRQ manager.
+#[cfg(feature = "rpi3")]
+pub(in crate::platform) fn ipi_init() -> Result<(), &'static str> {
+    let descriptor = IRQHandlerDescriptor::new(irq_map::MAILBOX[IPI_MAILBOX], "IPI", &IPI_RECEIVER)
+        .with_priority(IRQPriority::Highest);
+    irq_manager().register_handler(descriptor)?;
+
+    ipi_init_core()
+}
+
+/// Register the IPI handlers and let the boot core receive IPIs.
+///
+/// This must be called only after the interrupt controller is registered as IRQ manager.
+#[cfg(feature = "rpi4")]
+pub(in crate::platform) fn ipi_init() -> Result<(), &'static str> {
+    for receiver in IPI_RECEIVERS.iter() {
+        let descriptor =
+            IRQHandlerDescriptor::new(IRQNumber::new(receiver.0.number()), "IPI", receiver)
+                .with_priority(IRQPriority::Highest);
+        irq_manager().register_handler(descriptor)?;
+    }
+
+    ipi_init_core()
+}
@@ -0,0 +1,437 @@
+/*
+ * SPDX-License-Identifier: BlueOak-1.0.0
+ * Copyright (c) Berkus Decker <berkus+vesper@metta.systems>
+ */
+
+//! Board memory map discovered at boot.
+//!
+//! RAM banks and device register windows are taken from the device tree passed in by the
+//! firmware. The compile-time values in [`super::map`] are used for anything the device tree
+//! does not describe, or when there is no usable device tree at all.
+
+use {
+    super::map,
+    crate::{
+        fdt::{self, Fdt},
+        memory::{Address, Physical},
+        synchronization::{interface::ReadWriteEx, InitStateLock},
+    },
+    core::fmt,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Maximum number of RAM banks tracked.
+pub const MAX_RAM_BANKS: usize = 4;
+
+/// A contiguous window of physical address space.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PhysRegion {
+    pub base: Address<Physical>,
+    pub size: usize,
+}
+
+/// Where the memory map values came from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// Compile-time constants only.
+    BuiltIn,
+    /// Device tree, with compile-time constants filling the gaps.
+    DeviceTree,
+    /// Compile-time constants, because the device tree failed to parse.
+    InvalidDeviceTree(fdt::FdtError),
+}
+
+/// Physical memory map of the running board.
+#[derive(Copy, Clone, Debug)]
+pub struct BoardMemoryMap {
+    source: Source,
+    ram: [Option<PhysRegion>; MAX_RAM_BANKS],
+    /// Peripheral bus window as seen by the ARM cores.
+    peripherals: PhysRegion,
+    pl011_uart: PhysRegion,
+    gpio: PhysRegion,
+    system_timer: PhysRegion,
+    #[cfg(feature = "rpi3")]
+    local_ic: PhysRegion,
+    #[cfg(feature = "rpi3")]
+    peripheral_ic: PhysRegion,
+    #[cfg(feature = "rpi4")]
+    gicd: PhysRegion,
+    #[cfg(feature = "rpi4")]
+    gicc: PhysRegion,
+    /// Exclusive end of everything that may be mapped, [`map::END`] without a device tree.
+    end_exclusive: Address<Physical>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static BOARD_MEMORY_MAP: InitStateLock<BoardMemoryMap> =
+    InitStateLock::new(BoardMemoryMap::built_in());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl PhysRegion {
+    const fn new(base: Address<Physical>, size: usize) -> Self {
+        Self { base, size }
+    }
+
+    fn end_exclusive(&self) -> Address<Physical> {
+        self.base + self.size
+    }
+}
+
+/// Translated `(base, size)` of a device's `index`th register window.
+fn device_region(node: &fdt::Node, index: usize) -> Option<PhysRegion> {
+    let (base, size) = node.translated_reg(index)?;
+    Some(PhysRegion::new(
+        Address::new(usize::try_from(base).ok()?),
+        usize::try_from(size).ok()?,
+    ))
+}
+
+/// Region of the first enabled node compatible with any of `compatible`.
+fn find_device(fdt: &Fdt, compatible: &[&str], index: usize) -> Option<PhysRegion> {
+    let node = fdt.find(|node| {
+        compatible.iter().any(|c| node.is_compatible(c))
+            && node.property("status").and_then(|p| p.as_str()) != Some("disabled")
+    })?;
+    device_region(&node, index)
+}
+
+impl BoardMemoryMap {
+    /// Memory map from compile-time constants.
+    const fn built_in() -> Self {
+        Self {
+            source: Source::BuiltIn,
+            ram: [
+                Some(PhysRegion::new(
+                    Address::new(map::START),
+                    map::END_INCLUSIVE - map::START + 1,
+                )),
+                None,
+                None,
+                None,
+            ],
+            peripherals: PhysRegion::new(Address::new(map::mmio::MMIO_BASE), map::mmio::MMIO_SIZE),
+            pl011_uart: PhysRegion::new(map::mmio::PL011_UART_BASE, map::mmio::PL011_UART_SIZE),
+            gpio: PhysRegion::new(map::mmio::GPIO_BASE, map::mmio::GPIO_SIZE),
+            system_timer: PhysRegion::new(
+                map::mmio::SYSTEM_TIMER_BASE,
+                map::mmio::SYSTEM_TIMER_SIZE,
+            ),
+            #[cfg(feature = "rpi3")]
+            local_ic: PhysRegion::new(map::mmio::LOCAL_IC_BASE, map::mmio::LOCAL_IC_SIZE),
+            #[cfg(feature = "rpi3")]
+            peripheral_ic: PhysRegion::new(
+                map::mmio::PERIPHERAL_IC_BASE,
+                map::mmio::PERIPHERAL_IC_SIZE,
+            ),
+            #[cfg(feature = "rpi4")]
+            gicd: PhysRegion::new(map::mmio::GICD_BASE, map::mmio::GICD_SIZE),
+            #[cfg(feature = "rpi4")]
+            gicc: PhysRegion::new(map::mmio::GICC_BASE, map::mmio::GICC_SIZE),
+            end_exclusive: map::END,
+        }
+    }
+
+    /// Memory map from the device tree, falling back to built-in values per item.
+    fn from_device_tree(fdt: &Fdt) -> Self {
+        let mut this = Self::built_in();
+        this.source = Source::DeviceTree;
+
+        // Firmware fills in the size of /memory, the blobs on disk carry zero there.
+        if let Some(reg) = fdt.memory().and_then(|node| node.reg()) {
+            let mut banks = reg
+                .filter_map(|r| Some((r.address, r.size.filter(|&size| size != 0)?)))
+                .filter_map(|(base, size)| {
+                    Some(PhysRegion::new(
+                        Address::new(usize::try_from(base).ok()?),
+                        usize::try_from(size).ok()?,
+                    ))
+                });
+            if let Some(first) = banks.next() {
+                this.ram = [Some(first), None, None, None];
+                for (slot, bank) in this.ram[1..].iter_mut().zip(banks) {
+                    *slot = Some(bank);
+                }
+            }
+        }
+
+        if let Some(range) = fdt
+            .find_node("/soc")
+            .and_then(|soc| soc.ranges())
+            .and_then(|mut ranges| ranges.next())
+        {
+            if let (Ok(base), Ok(size)) = (
+                usize::try_from(range.parent_address),
+                usize::try_from(range.size),
+            ) {
+                this.peripherals = PhysRegion::new(Address::new(base), size);
+            }
+        }
+
+        if let Some(uart) = find_device(fdt, &["arm,pl011"], 0) {
+            this.pl011_uart = uart;
+        }
+        if let Some(gpio) = find_device(fdt, &["brcm,bcm2835-gpio", "brcm,bcm2711-gpio"], 0) {
+            this.gpio = gpio;
+        }
+        if let Some(timer) = find_device(fdt, &["brcm,bcm2835-system-timer"], 0) {
+            this.system_timer = timer;
+        }
+        #[cfg(feature = "rpi3")]
+        if let Some(ic) = find_device(fdt, &["brcm,bcm2836-l1-intc"], 0) {
+            this.local_ic = ic;
+        }
+        #[cfg(feature = "rpi3")]
+        if let Some(ic) = find_device(fdt, &["brcm,bcm2836-armctrl-ic"], 0) {
+            this.peripheral_ic = ic;
+        }
+        #[cfg(feature = "rpi4")]
+        if let Some(gicd) = find_device(fdt, &["arm,gic-400"], 0) {
+            this.gicd = gicd;
+        }
+        #[cfg(feature = "rpi4")]
+        if let Some(gicc) = find_device(fdt, &["arm,gic-400"], 1) {
+            this.gicc = gicc;
+        }
+
+        this.end_exclusive = this.compute_end_exclusive();
+        this
+    }
+
+    /// Highest end address among RAM and all device windows, page aligned.
+    fn compute_end_exclusive(&self) -> Address<Physical> {
+        #[cfg(feature = "rpi3")]
+        let devices = [
+            self.peripherals,
+            self.pl011_uart,
+            self.gpio,
+            self.system_timer,
+            self.local_ic,
+            self.peripheral_ic,
+        ];
+        #[cfg(feature = "rpi4")]
+        let devices = [
+            self.peripherals,
+            self.pl011_uart,
+            self.gpio,
+            self.system_timer,
+            self.gicd,
+            self.gicc,
+        ];
+
+        self.ram
+            .iter()
+            .flatten()
+            .chain(devices.iter())
+            .map(PhysRegion::end_exclusive)
+            .max()
+            .unwrap_or(map::END)
+            .align_up_page()
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Populate the board memory map from the device tree, if there is one.
+///
+/// Keeps the built-in map if there is no device tree or it fails to parse; the failure
+/// is recorded in [`BoardMemoryMap::source`] to be reported once the console is up.
+///
+/// # Safety
+///
+/// - Must be called during kernel init, before the MMU maps anything.
+pub(in crate::platform) unsafe fn init(fdt: Option<fdt::Result<Fdt>>) {
+    let board_map = match fdt {
+        None => return,
+        Some(Ok(fdt)) => BoardMemoryMap::from_device_tree(&fdt),
+        Some(Err(error)) => BoardMemoryMap {
+            source: Source::InvalidDeviceTree(error),
+            ..BoardMemoryMap::built_in()
+        },
+    };
+    BOARD_MEMORY_MAP.write(|map| *map = board_map);
+}
+
+/// Return a copy of the current board memory map.
+pub fn board_memory_map() -> BoardMemoryMap {
+    BOARD_MEMORY_MAP.read(|map| *map)
+}
+
+impl BoardMemoryMap {
+    /// Where the values came from.
+    pub fn source(&self) -> Source {
+        self.source
+    }
+
+    /// Populated RAM banks.
+    pub fn ram_banks(&self) -> impl Iterator<Item = &PhysRegion> {
+        self.ram.iter().flatten()
+    }
+
+    /// Peripheral window.
+    pub fn peripherals(&self) -> PhysRegion {
+        self.peripherals
+    }
+
+    /// PL011 UART registers.
+    pub fn pl011_uart(&self) -> PhysRegion {
+        self.pl011_uart
+    }
+
+    /// GPIO registers.
+    pub fn gpio(&self) -> PhysRegion {
+        self.gpio
+    }
+
+    /// BCM system timer registers.
+    pub fn system_timer(&self) -> PhysRegion {
+        self.system_timer
+    }
+
+    /// BCM2836 local interrupt controller registers, the ARM local peripherals.
+    #[cfg(feature = "rpi3")]
+    pub fn local_ic(&self) -> PhysRegion {
+        self.local_ic
+    }
+
+    /// BCM2835-style peripheral interrupt controller registers.
+    #[cfg(feature = "rpi3")]
+    pub fn peripheral_ic(&self) -> PhysRegion {
+        self.peripheral_ic
+    }
+
+    /// GIC-400 distributor registers.
+    #[cfg(feature = "rpi4")]
+    pub fn gicd(&self) -> PhysRegion {
+        self.gicd
+    }
+
+    /// GIC-400 CPU interface registers.
+    #[cfg(feature = "rpi4")]
+    pub fn gicc(&self) -> PhysRegion {
+        self.gicc
+    }
+
+    /// Exclusive end of the physical address space in use.
+    pub fn end_exclusive(&self) -> Address<Physical> {
+        self.end_exclusive
+    }
+}
+
+impl fmt::Display for BoardMemoryMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.source {
+            Source::BuiltIn => writeln!(f, "built-in")?,
+            Source::DeviceTree => writeln!(f, "from device tree")?,
+            Source::InvalidDeviceTree(error) => writeln!(f, "built-in ({})", error)?,
+        }
+        for bank in self.ram_banks() {
+            writeln!(
+                f,
+                "      RAM         {} - {}",
+                bank.base,
+                bank.end_exclusive()
+            )?;
+        }
+        write!(
+            f,
+            "      Peripherals {} - {}",
+            self.peripherals.base,
+            self.peripherals.end_exclusive()
+        )
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "rpi3")]
+    static DTB: &[u8] = include_bytes!("../../../../../targets/bcm2710-rpi-3-b-plus.dtb");
+    #[cfg(feature = "rpi4")]
+    static DTB: &[u8] = include_bytes!("../../../../../targets/bcm2711-rpi-4-b.dtb");
+
+    /// Device windows from the shipped device tree agree with the built-in constants.
+    #[test_case]
+    fn device_tree_matches_built_in_devices() {
+        let built_in = BoardMemoryMap::built_in();
+        let from_dt = BoardMemoryMap::from_device_tree(&Fdt::new(DTB).unwrap());
+
+        assert_eq!(from_dt.source(), Source::DeviceTree);
+        assert_eq!(from_dt.peripherals().base, built_in.peripherals().base);
+        assert_eq!(from_dt.pl011_uart().base, built_in.pl011_uart().base);
+        assert_eq!(from_dt.gpio().base, built_in.gpio().base);
+        assert_eq!(from_dt.system_timer().base, built_in.system_timer().base);
+        #[cfg(feature = "rpi3")]
+        {
+            assert_eq!(from_dt.local_ic().base, built_in.local_ic().base);
+            assert_eq!(from_dt.peripheral_ic().base, built_in.peripheral_ic().base);
+        }
+        #[cfg(feature = "rpi4")]
+        {
+            assert_eq!(from_dt.gicd().base, built_in.gicd().base);
+            assert_eq!(from_dt.gicc().base, built_in.gicc().base);
+        }
+    }
+
+    /// RAM size reported by the device tree, large enough to end above all device windows.
+    #[cfg(feature = "rpi3")]
+    const DT_RAM_SIZE: u32 = 0x8000_0000;
+    #[cfg(feature = "rpi4")]
+    const DT_RAM_SIZE: u32 = 0xFFFF_0000;
+
+    /// Copy of [`DTB`] with the /memory size filled in, as the firmware would.
+    static mut PATCHED_DTB: [u8; 64 * 1024] = [0; 64 * 1024];
+
+    /// The end of the address space follows the RAM size reported in the device tree.
+    #[test_case]
+    fn end_follows_device_tree_ram() {
+        let reg = Fdt::new(DTB)
+            .unwrap()
+            .memory()
+            .and_then(|node| node.property("reg"))
+            .unwrap();
+        // Single bank, the size cell comes last.
+        let size_offset =
+            reg.value().as_ptr() as usize - DTB.as_ptr() as usize + reg.value().len() - 4;
+
+        let dtb = unsafe {
+            let patched = &mut *core::ptr::addr_of_mut!(PATCHED_DTB);
+            patched[..DTB.len()].copy_from_slice(DTB);
+            patched[size_offset..size_offset + 4].copy_from_slice(&DT_RAM_SIZE.to_be_bytes());
+            &patched[..DTB.len()]
+        };
+        let from_dt = BoardMemoryMap::from_device_tree(&Fdt::new(dtb).unwrap());
+
+        let bank = *from_dt.ram_banks().next().unwrap();
+        assert_eq!(bank.size, DT_RAM_SIZE as usize);
+        assert_eq!(
+            from_dt.end_exclusive(),
+            bank.end_exclusive().align_up_page()
+        );
+    }
+
+    /// A zero-sized /memory node from an unpatched blob keeps the built-in RAM bank.
+    #[test_case]
+    fn unpatched_memory_node_falls_back() {
+        let from_dt = BoardMemoryMap::from_device_tree(&Fdt::new(DTB).unwrap());
+        let bank = *from_dt.ram_banks().next().unwrap();
+        assert_eq!(bank.base, Address::new(map::START));
+        assert_eq!(bank.size, map::END_INCLUSIVE - map::START + 1);
+    }
+}
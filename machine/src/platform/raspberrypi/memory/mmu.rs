@@ -6,9 +6,10 @@ use crate::{
             self as generic_mmu, AccessPermissions, AddressSpace, AssociatedTranslationTable,
             AttributeFields, MemAttributes, MemoryRegion, PageAddress, TranslationGranule,
         },
-        Physical, Virtual,
+        Address, Physical, Virtual,
     },
-    synchronization::InitStateLock,
+    platform::cpu::MAX_CORES,
+    synchronization::IRQSafeSpinLock,
 };
 
 //--------------------------------------------------------------------------------------------------
@@ -16,7 +17,10 @@ use crate::{
 //--------------------------------------------------------------------------------------------------
 
 type KernelTranslationTable =
-    <KernelVirtAddrSpace as AssociatedTranslationTable>::TableStartFromBottom;
+    <KernelVirtAddrSpace as AssociatedTranslationTable>::TableStartFromTop;
+
+/// Number of stacks in the kernel stacks area, a kernel and an exception stack per core.
+const NUM_STACKS: usize = 2 * MAX_CORES;
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -28,19 +32,24 @@ type KernelTranslationTable =
 pub type KernelGranule = TranslationGranule<{ 64 * 1024 }>;
 
 /// The kernel's virtual address space defined by this platform.
+///
+/// It sits at the top of the address space and is translated through TTBR1.
 pub type KernelVirtAddrSpace = AddressSpace<{ 1024 * 1024 * 1024 }>;
 
+/// Offset of the kernel's virtual addresses from the physical ones.
+///
+/// The kernel binary is linked at its load address plus this offset, see `kernel.ld`.
+pub const KERNEL_VIRT_OFFSET: usize = usize::MAX - KernelVirtAddrSpace::SIZE + 1;
+
 //--------------------------------------------------------------------------------------------------
 // Global instances
 //--------------------------------------------------------------------------------------------------
 
 /// The kernel translation tables.
 ///
-/// It is mandatory that InitStateLock is transparent.
-/// That is, `size_of(InitStateLock<KernelTranslationTable>) == size_of(KernelTranslationTable)`.
-/// There is a unit tests that checks this property.
-static KERNEL_TABLES: InitStateLock<KernelTranslationTable> =
-    InitStateLock::new(KernelTranslationTable::new());
+/// Drivers map their MMIO into these after kernel init too, so they take a real lock.
+static KERNEL_TABLES: IRQSafeSpinLock<KernelTranslationTable> =
+    IRQSafeSpinLock::new(KernelTranslationTable::new());
 
 //--------------------------------------------------------------------------------------------------
 // Private Code
@@ -84,17 +93,37 @@ fn virt_boot_core_stack_region() -> MemoryRegion<Virtual> {
     MemoryRegion::new(start_page_addr, end_exclusive_page_addr)
 }
 
-// The binary is still identity mapped, so use this trivial conversion function for mapping below.
+/// A stack in the kernel stacks area.
+///
+/// Stacks are one page each, with an unmapped guard page below every one of them.
+fn virt_stack_region(index: usize) -> MemoryRegion<Virtual> {
+    assert!(index < NUM_STACKS, "Stack index out of range");
+
+    let start_page_addr = super::virt_stacks_start()
+        .checked_offset((2 * index + 1) as isize)
+        .unwrap();
+    let end_exclusive_page_addr = start_page_addr.checked_offset(1).unwrap();
+
+    MemoryRegion::new(start_page_addr, end_exclusive_page_addr)
+}
+
+/// The first page of memory, holding the firmware's spin tables.
+fn virt_spin_tables_region() -> MemoryRegion<Virtual> {
+    let start_page_addr = PageAddress::from(phys_to_kernel_virt(Address::new(0)));
+    let end_exclusive_page_addr = start_page_addr.checked_offset(1).unwrap();
 
+    MemoryRegion::new(start_page_addr, end_exclusive_page_addr)
+}
+
+/// Physical pages backing a region of the kernel binary.
 fn kernel_virt_to_phys_region(virt_region: MemoryRegion<Virtual>) -> MemoryRegion<Physical> {
     MemoryRegion::new(
-        PageAddress::from(virt_region.start_page_addr().into_inner().as_usize()),
-        PageAddress::from(
-            virt_region
-                .end_exclusive_page_addr()
-                .into_inner()
-                .as_usize(),
-        ),
+        PageAddress::from(kernel_virt_to_phys(
+            virt_region.start_page_addr().into_inner(),
+        )),
+        PageAddress::from(kernel_virt_to_phys(
+            virt_region.end_exclusive_page_addr().into_inner(),
+        )),
     )
 }
 
@@ -159,8 +188,24 @@ fn kernel_virt_to_phys_region(virt_region: MemoryRegion<Virtual>) -> MemoryRegio
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+/// Physical address of a kernel binary address.
+///
+/// The binary is mapped linearly, at [`KERNEL_VIRT_OFFSET`] from where it was loaded.
+#[inline(always)]
+pub const fn kernel_virt_to_phys(virt: Address<Virtual>) -> Address<Physical> {
+    Address::new(virt.as_usize() - KERNEL_VIRT_OFFSET)
+}
+
+/// High half address of a physical address in the low memory holding the kernel binary.
+///
+/// Only valid where the kernel's boot translation tables map it, e.g. for the spin tables.
+#[inline(always)]
+pub const fn phys_to_kernel_virt(phys: Address<Physical>) -> Address<Virtual> {
+    Address::new(phys.as_usize() + KERNEL_VIRT_OFFSET)
+}
+
 /// Return a reference to the kernel's translation tables.
-pub fn kernel_translation_tables() -> &'static InitStateLock<KernelTranslationTable> {
+pub fn kernel_translation_tables() -> &'static IRQSafeSpinLock<KernelTranslationTable> {
     &KERNEL_TABLES
 }
 
@@ -174,6 +219,18 @@ pub fn virt_mmio_remap_region() -> MemoryRegion<Virtual> {
     MemoryRegion::new(start_page_addr, end_exclusive_page_addr)
 }
 
+/// The kernel stack of a secondary core.
+///
+/// The boot core's slot is unused, it keeps running on the boot core stack.
+pub fn virt_kernel_stack_region(core: usize) -> MemoryRegion<Virtual> {
+    virt_stack_region(2 * core)
+}
+
+/// The stack a core takes exceptions on.
+pub fn virt_exception_stack_region(core: usize) -> MemoryRegion<Virtual> {
+    virt_stack_region(2 * core + 1)
+}
+
 /// Map the kernel binary.
 ///
 /// # Safety
@@ -213,6 +270,20 @@ pub unsafe fn kernel_map_binary() -> Result<(), &'static str> {
     //             },
     //         },
 
+    // Part of the boot core stack pages, unless the kernel was loaded above its link address.
+    if !virt_boot_core_stack_region().overlaps(&virt_spin_tables_region()) {
+        generic_mmu::kernel_map_at(
+            "Firmware spin tables",
+            &virt_spin_tables_region(),
+            &kernel_virt_to_phys_region(virt_spin_tables_region()),
+            &AttributeFields {
+                mem_attributes: MemAttributes::CacheableDRAM,
+                acc_perms: AccessPermissions::ReadWrite,
+                execute_never: true,
+            },
+        )?;
+    }
+
     generic_mmu::kernel_map_at(
         "Kernel code and RO data",
         &virt_code_region(),
@@ -235,6 +306,20 @@ pub unsafe fn kernel_map_binary() -> Result<(), &'static str> {
         },
     )?;
 
+    // Only the stacks, guard pages in between stay unmapped.
+    for index in 0..NUM_STACKS {
+        generic_mmu::kernel_map_at(
+            "Kernel stack",
+            &virt_stack_region(index),
+            &kernel_virt_to_phys_region(virt_stack_region(index)),
+            &AttributeFields {
+                mem_attributes: MemAttributes::CacheableDRAM,
+                acc_perms: AccessPermissions::ReadWrite,
+                execute_never: true,
+            },
+        )?;
+    }
+
     Ok(())
 }
 
@@ -275,6 +360,8 @@ mod tests {
             virt_boot_core_stack_region(),
             virt_code_region(),
             virt_data_region(),
+            virt_stack_region(0),
+            virt_stack_region(NUM_STACKS - 1),
         ];
 
         for (i, first_range) in layout.iter().enumerate() {
@@ -284,6 +371,35 @@ mod tests {
         }
     }
 
+    /// The stacks area holds every stack with its guard page, and nothing else.
+    #[test_case]
+    fn stacks_area_matches_linker_script() {
+        assert_eq!(
+            super::super::stacks_size(),
+            2 * NUM_STACKS * KernelGranule::SIZE
+        );
+    }
+
+    /// The offset the binary is linked at matches the kernel's address space.
+    #[test_case]
+    fn kernel_virt_offset_matches_linker_script() {
+        extern "Rust" {
+            static __STACK_TOP: UnsafeCell<()>;
+        }
+
+        let stack_top = Address::<Virtual>::new(unsafe { __STACK_TOP.get() as usize });
+
+        assert_eq!(KERNEL_VIRT_OFFSET, 0xffff_ffff_c000_0000);
+        assert_eq!(
+            kernel_virt_to_phys(stack_top).as_usize(),
+            crate::platform::BcmHost::kernel_load_address() as usize
+        );
+        assert_eq!(
+            phys_to_kernel_virt(kernel_virt_to_phys(stack_top)),
+            stack_top
+        );
+    }
+
     /// Check if KERNEL_TABLES is in .bss.
     #[test_case]
     fn kernel_tables_in_bss() {
@@ -3,10 +3,15 @@
 //! The physical memory layout.
 //!
 //! The Raspberry's firmware copies the kernel binary to 0x8_0000. The preceding region will be used
-//! as the boot core's stack.
+//! as the boot core's stack, except for the firmware's spin tables in the first page and a guard
+//! page below the stack.
 //!
 //! +---------------------------------------+
-//! |                                       | boot_core_stack_start @ 0x0
+//! | Spin tables                           | 0x0
+//! +---------------------------------------+
+//! | Guard page                            | 0x1_0000
+//! +---------------------------------------+
+//! |                                       | boot_core_stack_start @ 0x2_0000
 //! |                                       |                                ^
 //! | Boot-core Stack                       |                                | stack
 //! |                                       |                                | growth
@@ -23,23 +28,33 @@
 //! | .bss                                  |
 //! |                                       |
 //! +---------------------------------------+
-//! |                                       | data_end_exclusive
+//! |                                       | data_end_exclusive == stacks_start
+//! | Kernel and exception stacks           |
+//! |                                       |
+//! +---------------------------------------+
+//! |                                       | stacks_end_exclusive
 //! |                                       |
 //!
 //!
 //!
 //!
 //!
-//! The virtual memory layout is as follows:
+//! The virtual memory layout is as follows, the kernel lives in the top 1 GiB translated through
+//! TTBR1 at `mmu::KERNEL_VIRT_OFFSET` from the physical layout. The bottom half, TTBR0, is left
+//! for user space.
 //!
 //! +---------------------------------------+
-//! |                                       | boot_core_stack_start @ 0x0
+//! | Spin tables                           | 0xffff_ffff_c000_0000
+//! +---------------------------------------+
+//! | Guard page, unmapped                  | 0xffff_ffff_c001_0000
+//! +---------------------------------------+
+//! |                                       | boot_core_stack_start @ 0xffff_ffff_c002_0000
 //! |                                       |                                ^
 //! | Boot-core Stack                       |                                | stack
 //! |                                       |                                | growth
 //! |                                       |                                | direction
 //! +---------------------------------------+
-//! |                                       | code_start @ 0x8_0000 == boot_core_stack_end_exclusive
+//! |                                       | code_start @ 0xffff_ffff_c008_0000 == boot_core_stack_end_exclusive
 //! | .text                                 |
 //! | .rodata                               |
 //! | .got                                  |
@@ -50,12 +65,18 @@
 //! | .bss                                  |
 //! |                                       |
 //! +---------------------------------------+
-//! |                                       |  mmio_remap_start == data_end_exclusive
+//! |                                       |  stacks_start == data_end_exclusive
+//! | Guard page, unmapped                  |
+//! | Stack                                 |
+//! | ...                                   |
+//! +---------------------------------------+
+//! |                                       |  mmio_remap_start == stacks_end_exclusive
 //! | VA region for MMIO remapping          |
 //! |                                       |
 //! +---------------------------------------+
 //! |                                       |  mmio_remap_end_exclusive
 //! |                                       |
+pub mod board;
 pub mod mmu;
 
 //--------------------------------------------------------------------------------------------------
@@ -63,7 +84,10 @@ pub mod mmu;
 //--------------------------------------------------------------------------------------------------
 
 use {
-    crate::memory::{mmu::PageAddress, Address, Physical, Virtual},
+    crate::memory::{
+        mmu::{MemoryRegion, PageAddress},
+        Address, Physical, Virtual,
+    },
     core::cell::UnsafeCell,
 };
 
@@ -112,6 +136,13 @@ extern "Rust" {
     // the first byte _after_ the data/BSS area.
     static __STACK_TOP: UnsafeCell<()>;
 
+    // The inclusive start of the kernel stacks area, aka the address of the
+    // first byte of the area.
+    static __STACKS_START: UnsafeCell<()>;
+    // The exclusive end of the kernel stacks area, aka the address of
+    // the first byte _after_ the stacks area.
+    static __STACKS_END: UnsafeCell<()>;
+
     // The inclusive start of the kernel MMIO remap area, aka the address of the
     // first byte of the area.
     static __MMIO_REMAP_START: UnsafeCell<()>;
@@ -124,16 +155,19 @@ extern "Rust" {
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
-/// The board's physical memory map.
-/// This is a fixed memory map for Raspberry Pi,
-/// @todo we need to infer the memory map from the provided DTB instead.
+/// The board's built-in physical memory map.
+/// Used as a fallback for whatever the device tree does not provide, see [`board`].
 #[rustfmt::skip]
 pub(super) mod map {
     use super::*;
 
     /// Beginning of memory.
     pub const START:                   usize =             0x0000_0000;
+    /// End of memory - 1Gb RPi3
+    #[cfg(feature = "rpi3")]
+    pub const END_INCLUSIVE:           usize =             0x3FFF_FFFF;
     /// End of memory - 8Gb RPi4
+    #[cfg(feature = "rpi4")]
     pub const END_INCLUSIVE:           usize =             0x1_FFFF_FFFF;
 
     /// Physical RAM addresses.
@@ -142,6 +176,7 @@ pub(super) mod map {
         pub const VIDEOMEM_BASE:       usize =             0x3e00_0000;
     }
 
+    pub const SYSTEM_TIMER_OFFSET:   usize = 0x0000_3000;
     pub const VIDEOCORE_MBOX_OFFSET: usize = 0x0000_B880;
     pub const POWER_OFFSET:          usize = 0x0010_0000;
     pub const GPIO_OFFSET:           usize = 0x0020_0000;
@@ -155,6 +190,11 @@ pub(super) mod map {
 
         /// Base address of MMIO register range.
         pub const MMIO_BASE:           usize =             0x3F00_0000;
+        pub const MMIO_SIZE:           usize =             0x0100_0000;
+
+        /// Base address of the system timer.
+        pub const SYSTEM_TIMER_BASE:   Address<Physical> = Address::new(MMIO_BASE + SYSTEM_TIMER_OFFSET);
+        pub const SYSTEM_TIMER_SIZE:   usize             =              0x1C;
 
         /// Interrupt controller
         pub const PERIPHERAL_IC_BASE:  Address<Physical> = Address::new(MMIO_BASE + 0x0000_B200);
@@ -176,6 +216,10 @@ pub(super) mod map {
         /// Base address of MiniUART.
         pub const MINI_UART_BASE:      Address<Physical> = Address::new(MMIO_BASE + MINIUART_OFFSET);
 
+        /// ARM local peripherals, the per-core interrupt controller.
+        pub const LOCAL_IC_BASE:       Address<Physical> = Address::new(0x4000_0000);
+        pub const LOCAL_IC_SIZE:       usize             =              0x100;
+
         /// End of MMIO memory region.
         pub const END:                 Address<Physical> = Address::new(0x4001_0000);
     }
@@ -187,6 +231,11 @@ pub(super) mod map {
 
         /// Base address of MMIO register range.
         pub const MMIO_BASE:        usize =             0xFE00_0000;
+        pub const MMIO_SIZE:        usize =             0x0180_0000;
+
+        /// Base address of the system timer.
+        pub const SYSTEM_TIMER_BASE: Address<Physical> = Address::new(MMIO_BASE + SYSTEM_TIMER_OFFSET);
+        pub const SYSTEM_TIMER_SIZE: usize             =              0x1C;
 
         /// Base address of GPIO registers.
         pub const GPIO_BASE:        Address<Physical> = Address::new(MMIO_BASE + GPIO_OFFSET);
@@ -332,6 +381,26 @@ fn mmio_remap_size() -> usize {
     unsafe { (__MMIO_REMAP_END.get() as usize) - (__MMIO_REMAP_START.get() as usize) }
 }
 
+/// Start page address of the kernel stacks area.
+///
+/// # Safety
+///
+/// - Value is provided by the linker script and must be trusted as-is.
+#[inline(always)]
+fn virt_stacks_start() -> PageAddress<Virtual> {
+    PageAddress::from(unsafe { __STACKS_START.get() as usize })
+}
+
+/// Size of the kernel stacks area.
+///
+/// # Safety
+///
+/// - Value is provided by the linker script and must be trusted as-is.
+#[inline(always)]
+fn stacks_size() -> usize {
+    unsafe { (__STACKS_END.get() as usize) - (__STACKS_START.get() as usize) }
+}
+
 /// Start page address of the boot core's stack.
 #[inline(always)]
 fn virt_boot_core_stack_start() -> PageAddress<Virtual> {
@@ -351,5 +420,14 @@ fn boot_core_stack_size() -> usize {
 /// Exclusive end address of the physical address space.
 #[inline(always)]
 pub fn phys_addr_space_end_exclusive_addr() -> PageAddress<Physical> {
-    PageAddress::from(map::END)
+    PageAddress::from(board::board_memory_map().end_exclusive())
+}
+
+/// Whether a physical region lies entirely within one of the board's RAM banks.
+pub fn phys_ram_contains(region: &MemoryRegion<Physical>) -> bool {
+    let range = region.addr_range();
+    board::board_memory_map().ram_banks().any(|bank| {
+        let bank_start = bank.base.as_usize();
+        bank_start <= range.start && range.end - bank_start <= bank.size
+    })
 }
@@ -14,6 +14,11 @@ pub mod exception;
 pub mod memory;
 // pub mod vc;
 
+use crate::{
+    fdt::Fdt,
+    memory::{Address, Physical},
+};
+
 /// See BCM2835-ARM-Peripherals.pdf
 /// See <https://www.raspberrypi.org/forums/viewtopic.php?t=186090> for more details.
 
@@ -41,6 +46,23 @@ pub struct BcmHost;
 // raspi3b+  raspi  bcm2837
 // raspi4    raspi  bcm2711
 
+/// Collect the board configuration from the device tree passed in by the firmware.
+///
+/// Must run before the MMU is enabled: the blob is not part of the kernel mappings.
+///
+/// # Safety
+///
+/// - Must be called during kernel init, before any memory mapping is done.
+/// - `dtb_phys_addr` must point to the blob passed in by the firmware.
+pub unsafe fn init_from_device_tree(dtb_phys_addr: Option<Address<Physical>>) {
+    let fdt = dtb_phys_addr.map(|addr| Fdt::from_addr(addr.as_usize()));
+
+    memory::board::init(fdt);
+    if let Some(Ok(fdt)) = fdt {
+        cpu::init(&fdt);
+    }
+}
+
 impl BcmHost {
     /// At which address to load the kernel binary.
     pub const fn kernel_load_address() -> u64 {
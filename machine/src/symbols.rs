@@ -0,0 +1,175 @@
+/*
+ * SPDX-License-Identifier: BlueOak-1.0.0
+ * Copyright (c) Berkus Decker <berkus+vesper@metta.systems>
+ */
+
+//! Kernel symbol table.
+//!
+//! The linker script reserves a zero-filled `.ksyms` section, which the `ksymtab` tool fills
+//! in from the linked ELF before it is turned into a binary image. The table holds a
+//! header, entries sorted by address, then the demangled names they point into.
+//!
+//! Addresses in the table are link addresses, the distance between the section's runtime
+//! and link address is added on lookup. An image that didn't go through `ksymtab` has no
+//! symbols, lookups then come back empty.
+
+use core::{cell::UnsafeCell, fmt, mem::size_of, slice, str};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A code address resolved to the symbol containing it.
+#[derive(Copy, Clone, Debug)]
+pub struct Symbol {
+    /// Demangled name, without the hash.
+    pub name: &'static str,
+    /// Offset of the address from the start of the symbol.
+    pub offset: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Identifies a filled in table, must match `ksymtab`.
+const MAGIC: [u8; 8] = *b"KSYMTAB1";
+
+/// Table header, must match `ksymtab`.
+#[repr(C)]
+struct Header {
+    magic: [u8; 8],
+    /// Link address of the `.ksyms` section.
+    link_addr: u64,
+    /// Number of entries following the header.
+    count: u32,
+    /// Size of the names following the entries.
+    names_size: u32,
+}
+
+/// One symbol, must match `ksymtab`.
+#[repr(C)]
+struct Entry {
+    /// Link address.
+    addr: u64,
+    /// Size in bytes, zero if unknown.
+    size: u32,
+    /// Offset of the name from the start of the names.
+    name_offset: u32,
+    name_len: u32,
+    _reserved: u32,
+}
+
+/// Parsed view of the table.
+struct Table {
+    entries: &'static [Entry],
+    names: &'static [u8],
+    /// Runtime minus link address.
+    delta: usize,
+}
+
+extern "Rust" {
+    // Boundaries of the .ksyms section, provided by the linker script.
+    static __KSYMS_START: UnsafeCell<()>;
+    static __KSYMS_END: UnsafeCell<()>;
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// The table, if the image has one and it fits its section.
+fn table() -> Option<Table> {
+    let start = unsafe { __KSYMS_START.get() as usize };
+    let capacity = unsafe { __KSYMS_END.get() as usize } - start;
+    if capacity < size_of::<Header>() {
+        return None;
+    }
+
+    let header = unsafe { &*(start as *const Header) };
+    if header.magic != MAGIC {
+        return None;
+    }
+
+    let entries_size = header.count as usize * size_of::<Entry>();
+    if size_of::<Header>() + entries_size + header.names_size as usize > capacity {
+        return None;
+    }
+
+    let entries_start = start + size_of::<Header>();
+    unsafe {
+        Some(Table {
+            entries: slice::from_raw_parts(entries_start as *const Entry, header.count as usize),
+            names: slice::from_raw_parts(
+                (entries_start + entries_size) as *const u8,
+                header.names_size as usize,
+            ),
+            delta: start.wrapping_sub(header.link_addr as usize),
+        })
+    }
+}
+
+impl Table {
+    fn name(&self, entry: &Entry) -> Option<&'static str> {
+        let start = entry.name_offset as usize;
+        let bytes = self.names.get(start..start + entry.name_len as usize)?;
+        str::from_utf8(bytes).ok()
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Resolve a runtime code address to its symbol.
+pub fn lookup(addr: usize) -> Option<Symbol> {
+    let table = table()?;
+    let link_addr = addr.wrapping_sub(table.delta) as u64;
+
+    // Last entry starting at or below the address.
+    let index = table
+        .entries
+        .partition_point(|entry| entry.addr <= link_addr)
+        .checked_sub(1)?;
+    let entry = &table.entries[index];
+
+    let offset = (link_addr - entry.addr) as usize;
+    if entry.size != 0 && offset >= entry.size as usize {
+        return None;
+    }
+
+    Some(Symbol {
+        name: table.name(entry)?,
+        offset,
+    })
+}
+
+/// Prints as `name+0xoffset`.
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}+{:#x}", self.name, self.offset)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[inline(never)]
+    fn marker_function() -> usize {
+        marker_function as usize
+    }
+
+    /// Test binaries go through `ksymtab` too, so their functions resolve.
+    #[test_case]
+    fn function_address_resolves() {
+        let addr = marker_function();
+        let symbol = lookup(addr + 4).expect("Symbol table missing");
+        assert!(symbol.name.ends_with("marker_function"));
+        assert_eq!(symbol.offset, 4);
+    }
+}
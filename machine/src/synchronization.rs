@@ -4,7 +4,11 @@
  * Original code distributed under MIT, additional changes are under BlueOak-1.0.0
  */
 
-use core::cell::UnsafeCell;
+use core::{
+    cell::UnsafeCell,
+    hint,
+    sync::atomic::{AtomicU16, AtomicU32, Ordering},
+};
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -39,17 +43,28 @@ pub mod interface {
     }
 }
 
-/// A pseudo-lock for teaching purposes.
+/// A ticket spinlock that masks local IRQs while held.
 ///
-/// In contrast to a real Mutex implementation, does not protect against concurrent access from
-/// other cores to the contained data. This part is preserved for later lessons.
+/// Cores acquire the lock in the order they asked for it. The atomics compile down to
+/// LDAXR/STLXR loops, or to LSE instructions when the target enables them.
+pub struct IRQSafeSpinLock<T>
+where
+    T: ?Sized,
+{
+    next_ticket: AtomicU16,
+    now_serving: AtomicU16,
+    data: UnsafeCell<T>,
+}
+
+/// A reader-writer spinlock that masks local IRQs while held.
 ///
-/// The lock will only be used as long as it is safe to do so, i.e. as long as the kernel is
-/// executing on a single core.
-pub struct IRQSafeNullLock<T>
+/// A waiting writer keeps new readers out, so writers are not starved. Readers must not
+/// nest on the same lock, the inner read would wait for a writer queued in between.
+pub struct RwSpinLock<T>
 where
     T: ?Sized,
 {
+    state: AtomicU32,
     data: UnsafeCell<T>,
 }
 
@@ -67,25 +82,35 @@ where
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
-/// Since we are instantiating this struct as a static variable, which could
-/// potentially be shared between different threads, we need to tell the compiler
-/// that sharing of this struct is safe by marking it with the Sync trait.
-///
-/// At this point in time, we can do so without worrying, because the kernel
-/// anyways runs on a single core and interrupts are disabled. In short, multiple
-/// threads don't exist yet in our code.
-///
-/// Literature:
-/// * <https://doc.rust-lang.org/beta/nomicon/send-and-sync.html>
-/// * <https://doc.rust-lang.org/book/ch16-04-extensible-concurrency-sync-and-send.html>
+unsafe impl<T> Send for IRQSafeSpinLock<T> where T: ?Sized + Send {}
+unsafe impl<T> Sync for IRQSafeSpinLock<T> where T: ?Sized + Send {}
 
-unsafe impl<T> Send for IRQSafeNullLock<T> where T: ?Sized + Send {}
-unsafe impl<T> Sync for IRQSafeNullLock<T> where T: ?Sized + Send {}
+impl<T> IRQSafeSpinLock<T> {
+    /// Create an instance.
+    pub const fn new(data: T) -> Self {
+        Self {
+            next_ticket: AtomicU16::new(0),
+            now_serving: AtomicU16::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+unsafe impl<T> Send for RwSpinLock<T> where T: ?Sized + Send {}
+unsafe impl<T> Sync for RwSpinLock<T> where T: ?Sized + Send + Sync {}
+
+impl<T> RwSpinLock<T> {
+    /// Writer holds the lock.
+    const WRITER: u32 = 1 << 31;
+    /// Writer waits for the readers to drain.
+    const WRITER_WAITING: u32 = 1 << 30;
+    /// Remaining bits count the readers.
+    const READERS: u32 = Self::WRITER_WAITING - 1;
 
-impl<T> IRQSafeNullLock<T> {
     /// Create an instance.
     pub const fn new(data: T) -> Self {
         Self {
+            state: AtomicU32::new(0),
             data: UnsafeCell::new(data),
         }
     }
@@ -107,18 +132,105 @@ impl<T> InitStateLock<T> {
 // OS Interface Code
 //------------------------------------------------------------------------------
 
-use crate::{exception, state};
+use crate::{exception, memory::mmu::interface::MMU, state};
+
+/// Exclusive loads and stores only work on cacheable memory, so the spinlocks can't be
+/// taken before the MMU is on. Only the boot core runs at that point, and masking IRQs
+/// is all the protection needed.
+fn atomics_usable() -> bool {
+    crate::arch::aarch64::memory::mmu::mmu().is_enabled()
+}
 
-impl<T> interface::Mutex for IRQSafeNullLock<T> {
+impl<T> interface::Mutex for IRQSafeSpinLock<T> {
     type Data = T;
 
     fn lock<R>(&self, f: impl FnOnce(&mut Self::Data) -> R) -> R {
-        // In a real lock, there would be code encapsulating this line that ensures that this
-        // mutable reference will ever only be given out once at a time.
-        let data = unsafe { &mut *self.data.get() };
+        exception::asynchronous::exec_with_irq_masked(|| {
+            if !atomics_usable() {
+                return f(unsafe { &mut *self.data.get() });
+            }
+
+            let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+            while self.now_serving.load(Ordering::Acquire) != ticket {
+                hint::spin_loop();
+            }
+
+            let ret = f(unsafe { &mut *self.data.get() });
 
-        // Execute the closure while IRQs are masked.
-        exception::asynchronous::exec_with_irq_masked(|| f(data))
+            self.now_serving
+                .store(ticket.wrapping_add(1), Ordering::Release);
+            ret
+        })
+    }
+}
+
+impl<T> interface::ReadWriteEx for RwSpinLock<T> {
+    type Data = T;
+
+    fn write<R>(&self, f: impl FnOnce(&mut Self::Data) -> R) -> R {
+        exception::asynchronous::exec_with_irq_masked(|| {
+            if !atomics_usable() {
+                return f(unsafe { &mut *self.data.get() });
+            }
+
+            loop {
+                let state = self.state.load(Ordering::Relaxed);
+                if state & (Self::WRITER | Self::READERS) == 0 {
+                    if self
+                        .state
+                        .compare_exchange_weak(
+                            state,
+                            Self::WRITER,
+                            Ordering::Acquire,
+                            Ordering::Relaxed,
+                        )
+                        .is_ok()
+                    {
+                        break;
+                    }
+                } else if state & Self::WRITER_WAITING == 0 {
+                    self.state.fetch_or(Self::WRITER_WAITING, Ordering::Relaxed);
+                }
+                hint::spin_loop();
+            }
+
+            let ret = f(unsafe { &mut *self.data.get() });
+
+            // Other waiting writers set their flag again.
+            self.state.store(0, Ordering::Release);
+            ret
+        })
+    }
+
+    fn read<R>(&self, f: impl FnOnce(&Self::Data) -> R) -> R {
+        exception::asynchronous::exec_with_irq_masked(|| {
+            if !atomics_usable() {
+                return f(unsafe { &*self.data.get() });
+            }
+
+            loop {
+                let state = self.state.load(Ordering::Relaxed);
+                if state & (Self::WRITER | Self::WRITER_WAITING) == 0
+                    && self
+                        .state
+                        .compare_exchange_weak(
+                            state,
+                            state + 1,
+                            Ordering::Acquire,
+                            Ordering::Relaxed,
+                        )
+                        .is_ok()
+                {
+                    break;
+                }
+                hint::spin_loop();
+            }
+
+            let ret = f(unsafe { &*self.data.get() });
+
+            self.state.fetch_sub(1, Ordering::Release);
+            ret
+        })
     }
 }
 
@@ -131,7 +243,7 @@ impl<T> interface::ReadWriteEx for InitStateLock<T> {
             "InitStateLock::write called after kernel init phase"
         );
         assert!(
-            !exception::asynchronous::is_local_irq_masked(),
+            exception::asynchronous::is_local_irq_masked(),
             "InitStateLock::write called with IRQs unmasked"
         );
 
@@ -153,7 +265,36 @@ impl<T> interface::ReadWriteEx for InitStateLock<T> {
 
 #[cfg(test)]
 mod tests {
-    use super::*; //, test_macros::kernel_test};
+    use {
+        super::*,
+        crate::{cpu, platform, time},
+        core::{sync::atomic::AtomicUsize, time::Duration},
+        interface::{Mutex, ReadWriteEx},
+    };
+
+    const STRESS_ROUNDS: u64 = 10_000;
+
+    static STRESS_COUNTER: IRQSafeSpinLock<u64> = IRQSafeSpinLock::new(0);
+    static STRESS_PAIR: RwSpinLock<(u64, u64)> = RwSpinLock::new((0, 0));
+    static STRESS_DONE: AtomicUsize = AtomicUsize::new(0);
+
+    /// Non-atomic read-modify-write cycles, torn by any missing exclusion.
+    fn hammer_locks() {
+        for _ in 0..STRESS_ROUNDS {
+            STRESS_COUNTER.lock(|counter| *counter += 1);
+            STRESS_PAIR.write(|(a, b)| {
+                *a += 1;
+                *b += 1;
+            });
+            STRESS_PAIR.read(|(a, b)| assert_eq!(a, b));
+        }
+        STRESS_DONE.fetch_add(1, Ordering::Release);
+    }
+
+    fn stress_secondary_main() -> ! {
+        hammer_locks();
+        cpu::endless_sleep()
+    }
 
     /// InitStateLock must be transparent.
     #[test_case]
@@ -162,4 +303,47 @@ mod tests {
 
         assert_eq!(size_of::<InitStateLock<u64>>(), size_of::<u64>());
     }
+
+    /// Locks hand out the data and release it again.
+    #[test_case]
+    fn spin_locks_release() {
+        let lock = IRQSafeSpinLock::new(1);
+        lock.lock(|x| *x += 1);
+        assert_eq!(lock.lock(|x| *x), 2);
+
+        let rw = RwSpinLock::new(1);
+        rw.write(|x| *x += 1);
+        rw.read(|x| {
+            assert_eq!(*x, 2);
+            assert_eq!(rw.state.load(Ordering::Relaxed), 1);
+        });
+        assert_eq!(rw.state.load(Ordering::Relaxed), 0);
+    }
+
+    /// All cores increment shared data under the locks, no update may get lost.
+    #[test_case]
+    fn spin_locks_under_contention() {
+        let mut cores = 1;
+        for core in (0..platform::cpu::num_cores() as u64).filter(|&c| c != cpu::smp::core_id()) {
+            unsafe { cpu::smp::start_secondary(core, stress_secondary_main) }.unwrap();
+            cores += 1;
+        }
+
+        hammer_locks();
+
+        let deadline = time::time_manager().uptime() + Duration::from_secs(10);
+        while STRESS_DONE.load(Ordering::Acquire) < cores {
+            assert!(
+                time::time_manager().uptime() < deadline,
+                "Secondary cores did not finish"
+            );
+            hint::spin_loop();
+        }
+
+        assert_eq!(STRESS_COUNTER.lock(|x| *x), cores as u64 * STRESS_ROUNDS);
+        assert_eq!(
+            STRESS_PAIR.read(|pair| *pair),
+            (cores as u64 * STRESS_ROUNDS, cores as u64 * STRESS_ROUNDS)
+        );
+    }
 }
@@ -3,25 +3,268 @@
 // Copyright (c) 2020-2022 Andre Richter <andre.o.richter@gmail.com>
 
 //! Timer primitives.
+//!
+//! Time is read from a clock source and timeouts are signalled by a clock event device, both
+//! provided by the backend: the ARM generic timer unless another one is selected, see
+//! [`TimeManager::set_backend`].
+//!
+//! Timeouts are kept in a fixed-size queue, which runs off the timer interrupt of the core
+//! that enabled it, the boot core. Their callbacks run there as deferred work, with IRQs
+//! unmasked.
 
 #[cfg(target_arch = "aarch64")]
 use crate::arch::aarch64::time as arch_time;
 
-use core::time::Duration;
+use {
+    crate::{
+        cpu, drivers,
+        exception::{self, asynchronous::IRQNumber},
+        synchronization::{
+            interface::{Mutex, ReadWriteEx},
+            IRQSafeSpinLock, RwSpinLock,
+        },
+    },
+    core::{
+        sync::atomic::{AtomicU64, Ordering},
+        time::Duration,
+    },
+};
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
+/// Time source interfaces.
+pub mod interface {
+    use core::time::Duration;
+
+    /// A free-running counter.
+    pub trait ClockSource {
+        /// The counter's resolution.
+        fn resolution(&self) -> Duration;
+
+        /// The counter value as time since it started, usually power-on of the device.
+        fn uptime(&self) -> Duration;
+
+        /// The raw counter value, for measuring short intervals.
+        fn ticks(&self) -> u64;
+
+        /// Convert an interval measured with [`Self::ticks`] to a duration.
+        fn ticks_to_duration(&self, ticks: u64) -> Duration;
+    }
+
+    /// Raises an interrupt at a programmed time.
+    ///
+    /// Its interrupt handler calls [`TimeManager::handle_clock_event`].
+    ///
+    /// [`TimeManager::handle_clock_event`]: super::TimeManager::handle_clock_event
+    pub trait ClockEvent {
+        /// Raise the interrupt once the uptime of the matching [`ClockSource`] reaches
+        /// `deadline`, right away if it has passed. Replaces any deadline set before.
+        fn set_deadline(&self, deadline: Duration);
+
+        /// Cancel the deadline, deasserting the interrupt.
+        fn stop(&self);
+    }
+
+    /// A clock source with a clock event device running off the same counter.
+    pub trait TimerBackend: ClockSource + ClockEvent {
+        /// Descriptive name.
+        fn name(&self) -> &'static str;
+    }
+}
+
 /// Provides time management functions.
-pub struct TimeManager;
+pub struct TimeManager {
+    queue: IRQSafeSpinLock<TimerQueue>,
+    backend: RwSpinLock<&'static (dyn interface::TimerBackend + Sync)>,
+}
+
+/// Called from the timer interrupt once a timeout expires.
+pub type TimeoutCallback = fn();
+
+/// Identifies a timeout set with [`TimeManager`], to cancel it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TimeoutHandle {
+    slot: usize,
+    generation: u32,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Timeouts pending at once.
+const NUM_TIMEOUTS: usize = 16;
+
+/// No core runs the timer queue yet.
+const NO_TIMER_CORE: u64 = u64::MAX;
+
+#[derive(Copy, Clone)]
+struct Timeout {
+    /// Uptime at which the timeout expires.
+    due: Duration,
+    /// Expires again every period, if set.
+    period: Option<Duration>,
+    callback: TimeoutCallback,
+}
+
+struct TimerQueue {
+    timeouts: [Option<Timeout>; NUM_TIMEOUTS],
+    /// Bumped whenever a slot is reused, telling apart handles to earlier timeouts.
+    generations: [u32; NUM_TIMEOUTS],
+}
+
+/// The executing core's ARM generic timer, through CNTPCT and the EL1 physical timer.
+struct ArchTimer;
 
 //--------------------------------------------------------------------------------------------------
 // Global instances
 //--------------------------------------------------------------------------------------------------
 
+static ARCH_TIMER: ArchTimer = ArchTimer;
+
 static TIME_MANAGER: TimeManager = TimeManager::new();
 
+/// The core whose timer interrupt runs the queue.
+static TIMER_CORE: AtomicU64 = AtomicU64::new(NO_TIMER_CORE);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl TimerQueue {
+    const fn new() -> Self {
+        Self {
+            timeouts: [None; NUM_TIMEOUTS],
+            generations: [0; NUM_TIMEOUTS],
+        }
+    }
+
+    fn insert(&mut self, timeout: Timeout) -> Result<TimeoutHandle, &'static str> {
+        let slot = self
+            .timeouts
+            .iter()
+            .position(Option::is_none)
+            .ok_or("Timer queue full")?;
+
+        self.generations[slot] = self.generations[slot].wrapping_add(1);
+        self.timeouts[slot] = Some(timeout);
+
+        Ok(TimeoutHandle {
+            slot,
+            generation: self.generations[slot],
+        })
+    }
+
+    /// Remove the timeout, false if it already expired or was cancelled.
+    fn cancel(&mut self, handle: TimeoutHandle) -> bool {
+        if self.generations[handle.slot] != handle.generation {
+            return false;
+        }
+        self.timeouts[handle.slot].take().is_some()
+    }
+
+    /// The earliest deadline.
+    fn next_due(&self) -> Option<Duration> {
+        self.timeouts
+            .iter()
+            .flatten()
+            .map(|timeout| timeout.due)
+            .min()
+    }
+
+    /// Take the earliest timeout expired at `now`, rescheduling it if periodic.
+    fn pop_expired(&mut self, now: Duration) -> Option<TimeoutCallback> {
+        let (slot, _) = self
+            .timeouts
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, timeout)| Some((slot, timeout.as_ref()?.due)))
+            .filter(|&(_, due)| due <= now)
+            .min_by_key(|&(_, due)| due)?;
+
+        let timeout = self.timeouts[slot].as_mut()?;
+        let callback = timeout.callback;
+
+        match timeout.period {
+            // Periods missed meanwhile are skipped, not run back to back.
+            Some(period) if timeout.due + period > now => timeout.due += period,
+            Some(period) => timeout.due = now + period,
+            None => self.timeouts[slot] = None,
+        }
+
+        Some(callback)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.timeouts.iter().all(Option::is_none)
+    }
+}
+
+impl interface::ClockSource for ArchTimer {
+    fn resolution(&self) -> Duration {
+        arch_time::resolution()
+    }
+
+    fn uptime(&self) -> Duration {
+        arch_time::uptime()
+    }
+
+    fn ticks(&self) -> u64 {
+        arch_time::ticks()
+    }
+
+    fn ticks_to_duration(&self, ticks: u64) -> Duration {
+        arch_time::ticks_to_duration(ticks)
+    }
+}
+
+impl interface::ClockEvent for ArchTimer {
+    fn set_deadline(&self, deadline: Duration) {
+        arch_time::set_timer_deadline(deadline)
+    }
+
+    fn stop(&self) {
+        arch_time::stop_timer()
+    }
+}
+
+impl interface::TimerBackend for ArchTimer {
+    fn name(&self) -> &'static str {
+        TimeManager::COMPATIBLE
+    }
+}
+
+impl TimeManager {
+    fn backend(&self) -> &'static (dyn interface::TimerBackend + Sync) {
+        self.backend.read(|backend| *backend)
+    }
+
+    /// Program the backend's clock event for the queue's earliest timeout.
+    fn rearm(&self, queue: &TimerQueue) {
+        match queue.next_due() {
+            Some(due) => self.backend().set_deadline(due),
+            None => self.backend().stop(),
+        }
+    }
+
+    fn add_timeout(&self, timeout: Timeout) -> Result<TimeoutHandle, &'static str> {
+        if TIMER_CORE.load(Ordering::Acquire) != cpu::smp::core_id() {
+            return Err("Timer interrupt not enabled on this core");
+        }
+
+        self.queue.lock(|queue| {
+            let handle = queue.insert(timeout)?;
+            self.rearm(queue);
+            Ok(handle)
+        })
+    }
+}
+
+/// Only there to wake up [`TimeManager::sleep_for`].
+fn wake_up() {}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
@@ -31,26 +274,262 @@ pub fn time_manager() -> &'static TimeManager {
     &TIME_MANAGER
 }
 
+/// Return a reference to the ARM generic timer backend, the default one.
+pub fn arch_timer() -> &'static (dyn interface::TimerBackend + Sync) {
+    &ARCH_TIMER
+}
+
 impl TimeManager {
-    /// Create an instance.
+    pub const COMPATIBLE: &'static str = "ARM Generic Timer";
+
+    /// Create an instance, on the ARM generic timer.
     pub const fn new() -> Self {
-        Self
+        Self {
+            queue: IRQSafeSpinLock::new(TimerQueue::new()),
+            backend: RwSpinLock::new(&ARCH_TIMER),
+        }
+    }
+
+    /// Switch to another clock source and clock event device.
+    ///
+    /// The uptime continues from the new backend's counter, which may have started at another
+    /// time. Fails while timeouts are pending, their deadlines would not carry over.
+    pub fn set_backend(
+        &self,
+        backend: &'static (dyn interface::TimerBackend + Sync),
+    ) -> Result<(), &'static str> {
+        self.queue.lock(|queue| {
+            if !queue.is_empty() {
+                return Err("Timeouts pending");
+            }
+
+            self.backend().stop();
+            self.backend.write(|current| *current = backend);
+            Ok(())
+        })
+    }
+
+    /// Name of the backend in use.
+    pub fn backend_name(&self) -> &'static str {
+        self.backend().name()
     }
 
     /// The timer's resolution.
     pub fn resolution(&self) -> Duration {
-        arch_time::resolution()
+        self.backend().resolution()
     }
 
     /// The uptime since power-on of the device.
     ///
     /// This includes time consumed by firmware and bootloaders.
     pub fn uptime(&self) -> Duration {
-        arch_time::uptime()
+        self.backend().uptime()
+    }
+
+    /// The raw counter value, for measuring short intervals.
+    pub fn ticks(&self) -> u64 {
+        self.backend().ticks()
+    }
+
+    /// Convert an interval measured with [`Self::ticks`] to a duration.
+    pub fn ticks_to_duration(&self, ticks: u64) -> Duration {
+        self.backend().ticks_to_duration(ticks)
     }
 
     /// Spin for a given duration.
+    ///
+    /// Always on the ARM generic timer, which is there before any driver.
     pub fn spin_for(&self, duration: Duration) {
         arch_time::spin_for(duration)
     }
+
+    /// Sleep for a given duration, waiting for interrupts instead of spinning.
+    ///
+    /// Spins on cores without the timer interrupt.
+    pub fn sleep_for(&self, duration: Duration) {
+        let deadline = self.uptime() + duration;
+
+        match self.set_timeout_once(duration, wake_up) {
+            Ok(handle) => {
+                use exception::asynchronous::{
+                    local_irq_mask, local_irq_mask_save, local_irq_restore, local_irq_unmask,
+                };
+
+                // Check and wait with IRQs masked, so that the timeout can't fire in between and
+                // leave the core waiting for some other interrupt. A pending IRQ still ends WFI,
+                // it is taken once IRQs are unmasked.
+                let saved = local_irq_mask_save();
+                while self.uptime() < deadline {
+                    cpu::wait_for_interrupt();
+                    local_irq_unmask();
+                    local_irq_mask();
+                }
+                local_irq_restore(saved);
+
+                self.cancel_timeout(handle);
+            }
+            Err(_) => self.spin_for(duration),
+        }
+    }
+
+    /// Call `callback` once, after `delay`.
+    ///
+    /// Only on the core with the timer interrupt.
+    pub fn set_timeout_once(
+        &self,
+        delay: Duration,
+        callback: TimeoutCallback,
+    ) -> Result<TimeoutHandle, &'static str> {
+        self.add_timeout(Timeout {
+            due: self.uptime() + delay,
+            period: None,
+            callback,
+        })
+    }
+
+    /// Call `callback` every `period`, until cancelled.
+    ///
+    /// Only on the core with the timer interrupt.
+    pub fn set_timeout_periodic(
+        &self,
+        period: Duration,
+        callback: TimeoutCallback,
+    ) -> Result<TimeoutHandle, &'static str> {
+        if period < self.resolution() {
+            return Err("Timer period below resolution");
+        }
+
+        self.add_timeout(Timeout {
+            due: self.uptime() + period,
+            period: Some(period),
+            callback,
+        })
+    }
+
+    /// Cancel a timeout, false if it already expired or was cancelled before.
+    pub fn cancel_timeout(&self, handle: TimeoutHandle) -> bool {
+        // The timer is left armed, an early interrupt finds nothing to do and rearms it.
+        self.queue.lock(|queue| queue.cancel(handle))
+    }
+
+    /// Run the expired timeouts and rearm the backend's clock event.
+    ///
+    /// Called by the interrupt handler of the backend's clock event device.
+    pub fn handle_clock_event(&self) {
+        use exception::asynchronous::{defer, WorkPriority};
+
+        // Hand the callbacks off to deferred work, calling them right away only if its queue is
+        // full. Either way outside of the queue lock, so that they may set timeouts.
+        while let Some(callback) = self.queue.lock(|queue| queue.pop_expired(self.uptime())) {
+            if defer(callback, WorkPriority::High).is_err() {
+                callback();
+            }
+        }
+        self.queue.lock(|queue| self.rearm(queue));
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// OS Interface Code
+//--------------------------------------------------------------------------------------------------
+
+impl drivers::interface::DeviceDriver for TimeManager {
+    type IRQNumberType = IRQNumber;
+
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    fn register_and_enable_irq_handler(
+        &'static self,
+        irq_number: &Self::IRQNumberType,
+    ) -> Result<(), &'static str> {
+        use exception::asynchronous::{irq_manager, IRQHandlerDescriptor, IRQPriority};
+
+        let descriptor = IRQHandlerDescriptor::new(*irq_number, Self::COMPATIBLE, self)
+            .with_priority(IRQPriority::High);
+
+        irq_manager().register_handler(descriptor)?;
+        irq_manager().enable(irq_number);
+        TIMER_CORE.store(cpu::smp::core_id(), Ordering::Release);
+
+        Ok(())
+    }
+}
+
+/// Handles the interrupt of the ARM generic timer, which only fires while it is the backend.
+impl exception::asynchronous::interface::IRQHandler for TimeManager {
+    fn handle(&self) -> Result<exception::HandlerResult, &'static str> {
+        self.handle_clock_event();
+
+        Ok(exception::HandlerResult::Handled)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nop() {}
+
+    fn timeout(due_ms: u64, period_ms: Option<u64>) -> Timeout {
+        Timeout {
+            due: Duration::from_millis(due_ms),
+            period: period_ms.map(Duration::from_millis),
+            callback: nop,
+        }
+    }
+
+    #[test_case]
+    fn timeouts_expire_in_order() {
+        let mut queue = TimerQueue::new();
+        queue.insert(timeout(20, None)).unwrap();
+        queue.insert(timeout(10, None)).unwrap();
+        assert_eq!(queue.next_due(), Some(Duration::from_millis(10)));
+
+        assert!(queue.pop_expired(Duration::from_millis(5)).is_none());
+        assert!(queue.pop_expired(Duration::from_millis(25)).is_some());
+        assert_eq!(queue.next_due(), Some(Duration::from_millis(20)));
+        assert!(queue.pop_expired(Duration::from_millis(25)).is_some());
+        assert_eq!(queue.next_due(), None);
+    }
+
+    #[test_case]
+    fn periodic_timeouts_are_rescheduled() {
+        let mut queue = TimerQueue::new();
+        queue.insert(timeout(10, Some(10))).unwrap();
+
+        assert!(queue.pop_expired(Duration::from_millis(12)).is_some());
+        assert_eq!(queue.next_due(), Some(Duration::from_millis(20)));
+
+        // Running late skips the missed periods.
+        assert!(queue.pop_expired(Duration::from_millis(55)).is_some());
+        assert_eq!(queue.next_due(), Some(Duration::from_millis(65)));
+    }
+
+    #[test_case]
+    fn stale_handles_cancel_nothing() {
+        let mut queue = TimerQueue::new();
+        let first = queue.insert(timeout(10, None)).unwrap();
+        assert!(queue.cancel(first));
+        assert!(!queue.cancel(first));
+
+        // The slot is reused, the old handle must not cancel the new timeout.
+        let second = queue.insert(timeout(10, None)).unwrap();
+        assert!(!queue.cancel(first));
+        assert!(queue.cancel(second));
+    }
+
+    #[test_case]
+    fn full_queue_is_refused() {
+        let mut queue = TimerQueue::new();
+        for _ in 0..NUM_TIMEOUTS {
+            queue.insert(timeout(10, None)).unwrap();
+        }
+        assert!(queue.insert(timeout(10, None)).is_err());
+    }
 }
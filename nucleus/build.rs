@@ -7,4 +7,9 @@ fn main() {
     println!("cargo:rerun-if-changed={}", LINKER_SCRIPT);
     println!("cargo:rerun-if-changed={}", LINKER_SCRIPT_AUX);
     println!("cargo:rustc-link-arg=--script={}", LINKER_SCRIPT);
+    // Position-independent, relocated by the boot code. Relocations are also applied at link
+    // time, so the image runs as is from its link address. Read-only data has relocations too.
+    println!("cargo:rustc-link-arg=--pie");
+    println!("cargo:rustc-link-arg=--apply-dynamic-relocs");
+    println!("cargo:rustc-link-arg=-znotext");
 }
@@ -35,36 +35,43 @@ use core::panic::PanicInfo;
 use machine::devices::serial::SerialOps;
 use {
     cfg_if::cfg_if,
-    core::{cell::UnsafeCell, time::Duration},
-    machine::{arch, console::console, entry, exception, info, memory, println, time, warn},
+    core::{
+        cell::UnsafeCell,
+        sync::atomic::{AtomicU32, Ordering},
+        time::Duration,
+    },
+    machine::{
+        arch, console::console, cpu::BootInfo, entry, exception, info, memory, println, time, warn,
+    },
 };
 
 entry!(kernel_init);
 
 /// Kernel early init code.
-/// `arch` crate is responsible for calling it.
+/// `arch` crate is responsible for calling it, passing in the information collected at boot.
 ///
 /// # Safety
 ///
 /// - Only a single core must be active and running this function.
 /// - The init calls in this function must appear in the correct order:
-///     - MMU + Data caching must be activated at the earliest. Without it, any atomic operations,
-///       e.g. the yet-to-be-introduced spinlocks in the device drivers (which currently employ
-///       IRQSafeNullLocks instead of spinlocks), will fail to work (properly) on the RPi SoCs.
-pub unsafe fn kernel_init() -> ! {
+///     - The device tree is only reachable through the boot identity map, it must be read
+///       before switching to the kernel's own translation tables.
+///     - The switch must precede driver init, MMIO is mapped into the kernel's tables.
+pub unsafe fn kernel_init(boot_info: BootInfo) -> ! {
     #[cfg(feature = "jtag")]
     machine::debug::jtag::wait_debugger();
 
     exception::handling_init();
 
+    // Must precede any mapping, it defines the physical address space.
+    machine::platform::init_from_device_tree(boot_info.dtb_phys_addr());
+
     let phys_kernel_tables_base_addr = match memory::mmu::kernel_map_binary() {
         Err(string) => panic!("Error mapping kernel binary: {}", string),
         Ok(addr) => addr,
     };
 
-    if let Err(e) = memory::mmu::enable_mmu_and_caching(phys_kernel_tables_base_addr) {
-        panic!("Enabling MMU failed: {}", e);
-    }
+    memory::mmu::switch_to_kernel_tables(phys_kernel_tables_base_addr);
 
     memory::mmu::post_enable_init();
 
@@ -81,14 +88,33 @@ pub unsafe fn kernel_init() -> ! {
     // Announce conclusion of the kernel_init() phase.
     machine::state::state_manager().transition_to_single_core_main();
 
+    // Bring up the other cores, this switches the kernel to MultiCoreMain.
+    if let Err(e) = machine::cpu::smp::start_secondary_cores(kernel_secondary_main) {
+        warn!("Secondary cores not started: {}", e);
+    }
+
     // Transition from unsafe to safe.
-    kernel_main()
+    kernel_main(boot_info)
+}
+
+/// Secondary cores idle here once they are set up, woken up by IPIs only.
+fn kernel_secondary_main() -> ! {
+    match exception::asynchronous::ipi_init_core() {
+        Ok(()) => exception::asynchronous::local_irq_unmask(),
+        Err(e) => warn!(
+            "Core {} does not receive IPIs: {}",
+            machine::cpu::smp::core_id(),
+            e
+        ),
+    }
+
+    idle()
 }
 
 /// Safe kernel code.
 // #[inline]
 #[cfg(not(test))]
-pub fn kernel_main() -> ! {
+pub fn kernel_main(boot_info: BootInfo) -> ! {
     // info!("{}", libkernel::version());
     // info!("Booting on: {}", bsp::board_name());
 
@@ -98,6 +124,13 @@ pub fn kernel_main() -> ! {
         env!("CARGO_PKG_VERSION")
     );
     info!("Booting on: {}", machine::platform::BcmHost::board_name());
+    info!("Boot info: {}", boot_info);
+    info!("Load offset: {:#x}", boot_info.load_offset());
+    info!("Cores online: {}", machine::cpu::smp::cores_online());
+    info!(
+        "Memory map: {}",
+        machine::platform::memory::board::board_memory_map()
+    );
 
     // info!("MMU online. Special regions:");
     // machine::platform::memory::mmu::virt_mem_layout().print_layout();
@@ -109,8 +142,9 @@ pub fn kernel_main() -> ! {
     exception::asynchronous::print_state();
 
     info!(
-        "Architectural timer resolution: {} ns",
-        time::time_manager().resolution().as_nanos()
+        "Timer resolution: {} ns ({})",
+        time::time_manager().resolution().as_nanos(),
+        time::time_manager().backend_name()
     );
 
     info!("Drivers loaded:");
@@ -119,18 +153,47 @@ pub fn kernel_main() -> ! {
     info!("Registered IRQ handlers:");
     exception::asynchronous::irq_manager().print_handler();
 
+    match exception::asynchronous::irq_manager().set_nesting(true) {
+        Ok(()) => info!("Nested IRQs enabled"),
+        Err(e) => info!("Nested IRQs disabled: {}", e),
+    }
+
     // Test a failing timer case.
     time::time_manager().spin_for(Duration::from_nanos(1));
 
-    loop {
-        info!("Spinning for 1 second");
-        time::time_manager().spin_for(Duration::from_secs(1));
+    match time::time_manager().set_timeout_periodic(Duration::from_secs(1), tick) {
+        Ok(_) => info!("Ticking every second"),
+        Err(e) => warn!("Periodic timer not set: {}", e),
     }
+
+    idle()
     // command_prompt();
     //
     // reboot()
 }
 
+/// Run deferred work and wait for interrupts, forever.
+fn idle() -> ! {
+    loop {
+        exception::asynchronous::run_deferred_work();
+        machine::cpu::wait_for_interrupt();
+    }
+}
+
+/// Called after the timer interrupt every second, printing IRQ counters every ten ticks.
+#[cfg(not(test))]
+fn tick() {
+    const TICKS_PER_IRQ_STATS: u32 = 10;
+    static TICKS: AtomicU32 = AtomicU32::new(0);
+
+    info!("Tick at {:?}", time::time_manager().uptime());
+
+    if TICKS.fetch_add(1, Ordering::Relaxed) % TICKS_PER_IRQ_STATS == TICKS_PER_IRQ_STATS - 1 {
+        info!("IRQ statistics:");
+        print_irq_stats();
+    }
+}
+
 #[cfg(not(test))]
 #[panic_handler]
 fn panicked(info: &PanicInfo) -> ! {
@@ -154,6 +217,7 @@ fn command_prompt() {
             b"feats" => print_mmu_state_and_features(),
             // b"disp" => check_display_init(),
             b"trap" => check_data_abort_trap(),
+            b"irq stats" => print_irq_stats(),
             // b"map" => machine::platform::memory::mmu::virt_mem_layout().print_layout(),
             // b"led on" => set_led(true),
             // b"led off" => set_led(false),
@@ -164,6 +228,14 @@ fn command_prompt() {
     }
 }
 
+fn print_irq_stats() {
+    exception::asynchronous::irq_manager().print_stats();
+    info!(
+        "      Deferred work: {}",
+        exception::asynchronous::deferred_work_stats()
+    );
+}
+
 fn print_help() {
     println!("Supported console commands:");
     println!("  mmu  - initialize MMU");
@@ -173,6 +245,7 @@ fn print_help() {
     // println!("  disp - try to init VC framebuffer and draw some text");
     println!("  trap - trigger and recover from a data abort exception");
     println!("  map  - show kernel memory layout");
+    println!("  irq stats - print per-IRQ counters, handler durations and deferred work");
     // println!("  led [on|off]  - change RPi LED status");
     println!("  end  - leave console and reset board");
 }
@@ -240,20 +313,22 @@ fn reboot() -> ! {
 //     Ok(())
 // }
 
+/// Lower half address, not translated at all once the kernel runs on its own tables.
+const UNMAPPED_ADDR: usize = 3 * 1024 * 1024 * 1024;
+
 fn check_data_abort_trap() {
     // Cause an exception by accessing a virtual address for which no
-    // address translations have been set up.
-    //
-    // This line of code accesses the address 3 GiB, but page tables are
-    // only set up for the range [0..1) GiB.
-    let big_addr: u64 = 3 * 1024 * 1024 * 1024;
-    unsafe { core::ptr::read_volatile(big_addr as *mut u64) };
-
-    info!("[i] Whoa! We recovered from an exception.");
+    // address translations have been set up, through a probe that
+    // recovers from the resulting data abort.
+    let addr = memory::Address::<memory::Virtual>::new(UNMAPPED_ADDR);
+    match unsafe { memory::probe_read::<u64>(addr) } {
+        Err(fault) => info!("[i] Whoa! We recovered from an exception: {}", fault),
+        Ok(value) => warn!("[!] Read {:#x} from unmapped {}", value, addr),
+    }
 }
 
 #[cfg(test)]
-pub fn kernel_main() -> ! {
+pub fn kernel_main(_boot_info: BootInfo) -> ! {
     test_main()
 }
 
@@ -268,6 +343,9 @@ mod main_tests {
 
     #[test_case]
     fn test_data_abort_trap() {
+        let addr = memory::Address::new(UNMAPPED_ADDR);
+        assert!(unsafe { memory::probe_read::<u64>(addr) }.is_err());
+
         check_data_abort_trap()
     }
 }